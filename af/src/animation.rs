@@ -0,0 +1,458 @@
+use gl::types::*;
+use std::fs::File;
+use std::io::Read;
+use assets::aseprite;
+use assets::aseprite::Aseprite;
+
+// Animations are read from the .png.info files that sit next to sprite
+// sheets. Lines look like:
+//
+//     idle frame 1 offset = 0       -- clip "idle" starts at sheet frame 0
+//     walk frame 1 offset = 1       -- clip "walk" starts at sheet frame 1
+//     total frame count = 9         -- the last clip runs up to here
+//
+// and optionally:
+//
+//     walk frame duration = 0.08    -- seconds per frame for the whole clip
+//     walk frame 3 duration = 0.2   -- just for one frame (1-based)
+//     walk loop = false             -- play once and hold the last frame
+//     walk frame 2 event = step     -- fire "step" when frame 2 comes up
+//
+// They can also come straight from an .aseprite file, where tags are clips
+// (see Animations::from_aseprite).
+//
+// Everything here is fixed size so it can live in GameData.
+
+pub const MAX_CLIPS: usize = 8;
+pub const MAX_CLIP_FRAMES: usize = 32;
+pub const MAX_NAME_LEN: usize = 15;
+pub const DEFAULT_FRAME_DURATION: f32 = 0.1;
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct Name {
+    len: u8,
+    bytes: [u8; MAX_NAME_LEN]
+}
+
+impl Name {
+    pub fn new(name: &str) -> Name {
+        if name.len() > MAX_NAME_LEN {
+            panic!("Name {} is longer than {} bytes", name, MAX_NAME_LEN);
+        }
+        let mut result = Name { len: name.len() as u8, bytes: [0; MAX_NAME_LEN] };
+        for (i, &b) in name.as_bytes().iter().enumerate() { result.bytes[i] = b; }
+        result
+    }
+
+    pub fn empty() -> Name { Name { len: 0, bytes: [0; MAX_NAME_LEN] } }
+
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    pub fn as_str(&self) -> &str {
+        ::std::str::from_utf8(&self.bytes[..self.len as usize]).unwrap()
+    }
+
+    pub fn is(&self, name: &str) -> bool { self.as_str() == name }
+}
+
+#[derive(Copy, Clone)]
+pub struct Clip {
+    pub name:        Name,
+    pub first_frame: GLint, // in the sprite sheet
+    pub frame_count: GLint,
+    pub looping:     bool,
+    pub durations:   [f32; MAX_CLIP_FRAMES],
+    pub events:      [Name; MAX_CLIP_FRAMES]
+}
+
+impl Clip {
+    fn new(name: Name) -> Clip {
+        Clip {
+            name: name,
+            first_frame: 0,
+            frame_count: 0,
+            looping: true,
+            durations: [DEFAULT_FRAME_DURATION; MAX_CLIP_FRAMES],
+            events: [Name::empty(); MAX_CLIP_FRAMES]
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Animations {
+    pub clips: [Clip; MAX_CLIPS],
+    pub clip_count: usize
+}
+
+impl Animations {
+    // One looping clip called "all" covering the whole sheet. For when an
+    // info file is missing or broken.
+    pub fn single(frame_count: GLint) -> Animations {
+        let mut clip = Clip::new(Name::new("all"));
+        clip.frame_count = frame_count;
+
+        let mut animations = Animations { clips: [clip; MAX_CLIPS], clip_count: 1 };
+        animations.clips[0] = clip;
+        animations
+    }
+
+    pub fn clips(&self) -> &[Clip] { &self.clips[..self.clip_count] }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.clips().iter().position(|c| c.name.is(name))
+    }
+
+    pub fn load(filename: &str) -> Result<Animations, String> {
+        if filename.ends_with(".aseprite") {
+            return aseprite::load(filename).and_then(|ase|
+                Animations::from_aseprite(&ase).map_err(|e| format!("{}: {}", filename, e))
+            );
+        }
+
+        let mut contents = String::new();
+        match File::open(filename).and_then(|mut f| f.read_to_string(&mut contents)) {
+            Ok(_) => {}
+            Err(e) => return Err(format!("Couldn't read {}: {}", filename, e))
+        }
+        Animations::parse(&contents).map_err(|e| format!("{}: {}", filename, e))
+    }
+
+    pub fn parse(info: &str) -> Result<Animations, String> {
+        let mut clips: Vec<Clip> = Vec::new();
+        let mut default_durations: Vec<f32> = Vec::new();
+        // Per frame durations, for the frames that have one.
+        let mut frame_durations: Vec<[Option<f32>; MAX_CLIP_FRAMES]> = Vec::new();
+        let mut total: Option<GLint> = None;
+
+        macro_rules! clip_named {
+            ($name:expr) => {{
+                let index = match clips.iter().position(|c| c.name.is($name)) {
+                    Some(i) => i,
+                    None => {
+                        if clips.len() == MAX_CLIPS {
+                            return Err(format!("more than {} clips", MAX_CLIPS));
+                        }
+                        if $name.len() > MAX_NAME_LEN {
+                            return Err(format!("clip name {} is too long", $name));
+                        }
+                        clips.push(Clip::new(Name::new($name)));
+                        default_durations.push(DEFAULT_FRAME_DURATION);
+                        frame_durations.push([None; MAX_CLIP_FRAMES]);
+                        clips.len() - 1
+                    }
+                };
+                index
+            }}
+        }
+
+        for (line_number, line) in info.lines().enumerate() {
+            let line_number = line_number + 1;
+            let line = line.trim();
+            if line.is_empty() { continue }
+
+            let mut sides = line.splitn(2, '=');
+            let key: Vec<&str> = sides.next().unwrap()
+                .split(' ').filter(|word| !word.is_empty()).collect();
+            let value = match sides.next() {
+                Some(v) => v.trim(),
+                None => return Err(format!("line {}: expected `=`", line_number))
+            };
+
+            macro_rules! value_as {
+                ($ty:ty) => {
+                    match value.parse::<$ty>() {
+                        Ok(v) => v,
+                        Err(_) => return Err(format!("line {}: bad value `{}`", line_number, value))
+                    }
+                }
+            }
+            // Zero would never move on to the next frame.
+            macro_rules! duration {
+                () => {
+                    match value.parse::<f32>() {
+                        Ok(d) if d > 0.0 => d,
+                        _ => return Err(format!("line {}: bad duration `{}` (must be more than 0)", line_number, value))
+                    }
+                }
+            }
+            macro_rules! frame_number {
+                ($n:expr) => {
+                    match $n.parse::<usize>() {
+                        Ok(n) if n >= 1 && n <= MAX_CLIP_FRAMES => n - 1,
+                        _ => return Err(format!("line {}: bad frame number `{}`", line_number, $n))
+                    }
+                }
+            }
+
+            if key == ["total", "frame", "count"] {
+                let count = value_as!(GLint);
+                if count <= 0 {
+                    return Err(format!("line {}: total frame count is {} (must be more than 0)", line_number, count));
+                }
+                total = Some(count);
+            }
+            else if key.len() == 4 && key[1] == "frame" && key[3] == "offset" {
+                let i = clip_named!(key[0]);
+                let offset = value_as!(GLint);
+                // Frame N being at `offset` puts frame 1 N - 1 frames earlier.
+                match offset.checked_sub(frame_number!(key[2]) as GLint) {
+                    Some(first) if first >= 0 => clips[i].first_frame = first,
+                    _ => return Err(format!(
+                        "line {}: frame {} of {} can't be at {} (that puts frame 1 before the first frame)",
+                        line_number, key[2], key[0], offset
+                    ))
+                }
+            }
+            else if key.len() == 3 && key[1] == "frame" && key[2] == "duration" {
+                let i = clip_named!(key[0]);
+                default_durations[i] = duration!();
+            }
+            else if key.len() == 4 && key[1] == "frame" && key[3] == "duration" {
+                let i = clip_named!(key[0]);
+                frame_durations[i][frame_number!(key[2])] = Some(duration!());
+            }
+            else if key.len() == 4 && key[1] == "frame" && key[3] == "event" {
+                if value.len() > MAX_NAME_LEN {
+                    return Err(format!("line {}: event name {} is too long", line_number, value));
+                }
+                let i = clip_named!(key[0]);
+                clips[i].events[frame_number!(key[2])] = Name::new(value);
+            }
+            else if key.len() == 2 && key[1] == "loop" {
+                let i = clip_named!(key[0]);
+                clips[i].looping = value_as!(bool);
+            }
+            else {
+                return Err(format!("line {}: don't know what `{}` means", line_number, line));
+            }
+        }
+
+        let total = match total {
+            Some(t) => t,
+            None => return Err("missing `total frame count`".to_string())
+        };
+        if clips.is_empty() {
+            return Err("no clips".to_string());
+        }
+
+        // Each clip runs until the next one starts.
+        let mut order: Vec<usize> = (0..clips.len()).collect();
+        order.sort_by(|&a, &b| clips[a].first_frame.cmp(&clips[b].first_frame));
+        for (position, &i) in order.iter().enumerate() {
+            let end = match order.get(position + 1) {
+                Some(&next) => clips[next].first_frame,
+                None => total
+            };
+            clips[i].frame_count = end - clips[i].first_frame;
+
+            if clips[i].frame_count <= 0 || clips[i].frame_count as usize > MAX_CLIP_FRAMES {
+                return Err(format!(
+                    "clip {} has {} frames (must be 1 to {})",
+                    clips[i].name.as_str(), clips[i].frame_count, MAX_CLIP_FRAMES
+                ));
+            }
+            for (d, frame) in clips[i].durations.iter_mut().zip(frame_durations[i].iter()) {
+                *d = frame.unwrap_or(default_durations[i]);
+            }
+        }
+
+        let mut animations = Animations { clips: [clips[0]; MAX_CLIPS], clip_count: clips.len() };
+        for (i, clip) in clips.iter().enumerate() { animations.clips[i] = *clip; }
+        Ok(animations)
+    }
+}
+
+impl Animations {
+    // Each tag is a clip, with the frames' own durations. Tags that repeat
+    // a set number of times don't loop. No tags means one clip for the
+    // whole file.
+    pub fn from_aseprite(ase: &Aseprite) -> Result<Animations, String> {
+        if ase.frames.is_empty() {
+            return Err("no frames".to_string());
+        }
+        if ase.tags.is_empty() {
+            if ase.frames.len() > MAX_CLIP_FRAMES {
+                return Err(format!("{} frames and no tags (untagged files can have 1 to {})", ase.frames.len(), MAX_CLIP_FRAMES));
+            }
+            let mut animations = Animations::single(ase.frames.len() as GLint);
+            for (d, frame) in animations.clips[0].durations.iter_mut().zip(ase.frames.iter()) {
+                *d = if frame.duration > 0.0 { frame.duration } else { DEFAULT_FRAME_DURATION };
+            }
+            return Ok(animations);
+        }
+        if ase.tags.len() > MAX_CLIPS {
+            return Err(format!("more than {} tags", MAX_CLIPS));
+        }
+
+        let mut animations = Animations { clips: [Clip::new(Name::empty()); MAX_CLIPS], clip_count: ase.tags.len() };
+        for (clip, tag) in animations.clips.iter_mut().zip(ase.tags.iter()) {
+            if tag.name.len() > MAX_NAME_LEN {
+                return Err(format!("tag name {} is too long", tag.name));
+            }
+            if tag.direction != aseprite::TAG_FORWARD {
+                return Err(format!("tag {} plays in reverse or ping-pong, which isn't supported (only forward)", tag.name));
+            }
+            if tag.to < tag.from || tag.to >= ase.frames.len() {
+                return Err(format!("tag {} covers frames {} to {} of {}", tag.name, tag.from, tag.to, ase.frames.len()));
+            }
+            let frame_count = tag.to + 1 - tag.from;
+            if frame_count > MAX_CLIP_FRAMES {
+                return Err(format!("tag {} has {} frames (must be 1 to {})", tag.name, frame_count, MAX_CLIP_FRAMES));
+            }
+
+            *clip = Clip::new(Name::new(&tag.name));
+            clip.first_frame = tag.from as GLint;
+            clip.frame_count = frame_count as GLint;
+            clip.looping = tag.repeat == 0;
+            for i in 0..frame_count {
+                let duration = ase.frames[tag.from + i].duration;
+                clip.durations[i] = if duration > 0.0 { duration } else { DEFAULT_FRAME_DURATION };
+            }
+        }
+        Ok(animations)
+    }
+}
+
+// Per-sprite playback state. Refers to clips by index, so it has to be used
+// with the same Animations it was started with.
+#[derive(Copy, Clone)]
+pub struct AnimationPlayer {
+    pub clip:     usize,
+    pub frame:    GLint, // within the clip
+    pub time:     f32,   // spent on the current frame
+    pub finished: bool   // only ever true for non-looping clips
+}
+
+impl AnimationPlayer {
+    pub fn new(clip: usize) -> AnimationPlayer {
+        AnimationPlayer { clip: clip, frame: 0, time: 0.0, finished: false }
+    }
+
+    // Switches clips, restarting only if it's a different one.
+    pub fn play(&mut self, clip: usize) {
+        if clip != self.clip { *self = AnimationPlayer::new(clip); }
+    }
+
+    // Frame to draw from the sprite sheet.
+    pub fn sheet_frame(&self, animations: &Animations) -> GLint {
+        let ref clip = animations.clips[self.clip % animations.clip_count];
+        clip.first_frame + self.frame % clip.frame_count
+    }
+
+    // Advances by `dt` seconds, calling `on_event` for the event of every
+    // frame that comes up along the way.
+    pub fn update<F>(&mut self, animations: &Animations, dt: f32, mut on_event: F)
+        where F: FnMut(Name)
+    {
+        if self.clip >= animations.clip_count {
+            // Clips changed under us (hot reload); start over.
+            *self = AnimationPlayer::new(0);
+        }
+        let ref clip = animations.clips[self.clip];
+        if self.frame >= clip.frame_count { self.frame = 0; }
+        if self.finished { return }
+
+        self.time += dt;
+        while self.time >= clip.durations[self.frame as usize] {
+            self.time -= clip.durations[self.frame as usize];
+
+            if self.frame + 1 < clip.frame_count {
+                self.frame += 1;
+            }
+            else if clip.looping {
+                self.frame = 0;
+            }
+            else {
+                self.finished = true;
+                self.time = 0.0;
+                return;
+            }
+
+            let event = clip.events[self.frame as usize];
+            if !event.is_empty() { on_event(event); }
+        }
+    }
+}
+
+#[test]
+fn clips_run_until_the_next_one_starts() {
+    let animations = Animations::parse(
+        "idle frame 1 offset = 0\nwalk frame 1 offset = 1\ntotal frame count = 9"
+    ).unwrap();
+
+    let idle = animations.find("idle").unwrap();
+    let walk = animations.find("walk").unwrap();
+    assert_eq!(animations.clips[idle].frame_count, 1);
+    assert_eq!(animations.clips[walk].first_frame, 1);
+    assert_eq!(animations.clips[walk].frame_count, 8);
+}
+
+#[test]
+fn frames_take_their_own_time_and_fire_events() {
+    let animations = Animations::parse(
+        "walk frame 1 offset = 0\njump frame 1 offset = 3\ntotal frame count = 5\n\
+         walk frame duration = 0.1\nwalk frame 2 duration = 0.3\nwalk frame 2 event = step\n\
+         jump loop = false"
+    ).unwrap();
+    let walk = animations.find("walk").unwrap();
+    let jump = animations.find("jump").unwrap();
+
+    // Frame 2 of walk is the long one, and looping goes back to frame 1.
+    let mut player = AnimationPlayer::new(walk);
+    let mut events = Vec::new();
+    player.update(&animations, 0.15, |e| events.push(e));
+    assert_eq!((player.frame, events.len()), (1, 1));
+    assert!(events[0].is("step"));
+    player.update(&animations, 0.2, |e| events.push(e));
+    assert_eq!(player.frame, 1);
+    player.update(&animations, 0.22, |e| events.push(e));
+    assert_eq!((player.frame, player.sheet_frame(&animations)), (0, 0));
+
+    // A clip that doesn't loop holds its last frame.
+    player.play(jump);
+    player.update(&animations, 1.0, |_| {});
+    assert!(player.finished);
+    assert_eq!(player.sheet_frame(&animations), 4);
+    player.update(&animations, 1.0, |_| {});
+    assert_eq!(player.sheet_frame(&animations), 4);
+}
+
+#[test]
+fn durations_have_to_be_more_than_zero() {
+    let no_time = Animations::parse("walk frame 1 offset = 0\ntotal frame count = 2\nwalk frame duration = 0");
+    assert_eq!(no_time.err(), Some("line 3: bad duration `0` (must be more than 0)".to_string()));
+    assert!(Animations::parse("walk frame 1 offset = 0\ntotal frame count = 2\nwalk frame 2 duration = -1").is_err());
+}
+
+#[test]
+fn clips_cant_start_before_the_first_frame() {
+    let early = Animations::parse("walk frame 3 offset = 1\ntotal frame count = 4");
+    assert_eq!(early.err(), Some("line 1: frame 3 of walk can't be at 1 (that puts frame 1 before the first frame)".to_string()));
+    assert!(Animations::parse("walk frame 1 offset = 0\ntotal frame count = 0").is_err());
+    assert!(Animations::parse("walk frame 1 offset = 0\ntotal frame count = -3").is_err());
+    assert!(Animations::parse("walk frame 2 offset = 1\ntotal frame count = 4").is_ok());
+}
+
+#[test]
+fn aseprite_frames_without_a_duration_get_the_default() {
+    use assets::aseprite::{Frame, Tag};
+    let mut ase = Aseprite {
+        width: 1, height: 1, layers: Vec::new(), tags: Vec::new(),
+        frames: vec![Frame { duration: 0.0, cels: Vec::new() }, Frame { duration: 0.2, cels: Vec::new() }]
+    };
+    let animations = Animations::from_aseprite(&ase).unwrap();
+    assert_eq!(&animations.clips[0].durations[..2], &[DEFAULT_FRAME_DURATION, 0.2]);
+
+    ase.tags.push(Tag { name: "bounce".to_string(), from: 0, to: 1, direction: 2, repeat: 0 });
+    assert!(Animations::from_aseprite(&ase).is_err());
+}
+
+#[test]
+fn untagged_aseprite_files_fit_in_one_clip() {
+    use assets::aseprite::Frame;
+    let frames = |count: usize| (0..count).map(|_| Frame { duration: 0.1, cels: Vec::new() }).collect();
+    let ase = Aseprite { width: 1, height: 1, layers: Vec::new(), tags: Vec::new(), frames: frames(MAX_CLIP_FRAMES) };
+    assert!(Animations::from_aseprite(&ase).is_ok());
+    let ase = Aseprite { width: 1, height: 1, layers: Vec::new(), tags: Vec::new(), frames: frames(MAX_CLIP_FRAMES + 1) };
+    assert!(Animations::from_aseprite(&ase).is_err());
+}
@@ -0,0 +1,451 @@
+use std::fs::File;
+use std::io::Read;
+use render::zlib_decode;
+
+// Reads .aseprite files directly, so art doesn't have to be exported by
+// hand. What we use out of them:
+//
+//   * every frame, flattened into a sheet one frame tall (see Aseprite::sheet),
+//     either all visible layers or just one layer (or group) -- so body,
+//     back-foot and front-foot can be layers of the same file;
+//   * tags, which become animation clips (see Animations::from_aseprite);
+//   * frame durations.
+//
+// Layers are composited with plain alpha blending; other blend modes draw as
+// normal. Tilemap layers are skipped.
+//
+// Format: https://github.com/aseprite/aseprite/blob/main/docs/ase-file-specs.md
+
+const HEADER_MAGIC: u16 = 0xA5E0;
+const FRAME_MAGIC: u16 = 0xF1FA;
+
+const CHUNK_LAYER: u16 = 0x2004;
+const CHUNK_CEL: u16 = 0x2005;
+const CHUNK_TAGS: u16 = 0x2018;
+const CHUNK_PALETTE: u16 = 0x2019;
+
+const LAYER_VISIBLE: u16 = 1;
+const LAYER_TYPE_NORMAL: u16 = 0;
+const LAYER_TYPE_GROUP: u16 = 1;
+
+const CEL_RAW: u16 = 0;
+const CEL_LINKED: u16 = 1;
+const CEL_COMPRESSED: u16 = 2;
+
+const HEADER_FLAG_LAYER_OPACITY: u32 = 1;
+
+// Reverse and ping-pong are 1 to 3.
+pub const TAG_FORWARD: u8 = 0;
+
+pub struct Layer {
+    pub name: String,
+    pub visible: bool,
+    pub kind: u16,
+    // Groups nest by bumping this (see parent).
+    pub child_level: u16,
+    pub opacity: u8
+}
+
+pub struct Cel {
+    pub layer: usize,
+    pub x: i32,
+    pub y: i32,
+    pub opacity: u8,
+    pub width: i32,
+    pub height: i32,
+    // RGBA once the whole file is read.
+    pub pixels: Vec<u8>
+}
+
+pub struct Frame {
+    pub duration: f32, // seconds
+    pub cels: Vec<Cel>
+}
+
+pub struct Tag {
+    pub name: String,
+    pub from: usize,
+    pub to: usize, // inclusive
+    // TAG_FORWARD, or one of the other ways of playing we don't support.
+    pub direction: u8,
+    // How many times to play; 0 is forever.
+    pub repeat: u16
+}
+
+pub struct Aseprite {
+    pub width: i32,
+    pub height: i32,
+    pub layers: Vec<Layer>,
+    pub frames: Vec<Frame>,
+    pub tags: Vec<Tag>
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.pos + count > self.bytes.len() {
+            return Err(format!("file ends early (wanted {} bytes at {})", count, self.pos));
+        }
+        let bytes = &self.bytes[self.pos..self.pos + count];
+        self.pos += count;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, count: usize) -> Result<(), String> { self.take(count).map(|_| ()) }
+
+    fn u8(&mut self) -> Result<u8, String> { self.take(1).map(|b| b[0]) }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        self.take(2).map(|b| b[0] as u16 | (b[1] as u16) << 8)
+    }
+
+    fn i16(&mut self) -> Result<i16, String> { self.u16().map(|n| n as i16) }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        self.take(4).map(|b| b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = try!(self.u16()) as usize;
+        let bytes = try!(self.take(len));
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+pub fn load(filename: &str) -> Result<Aseprite, String> {
+    let mut contents = Vec::new();
+    match File::open(filename).and_then(|mut f| f.read_to_end(&mut contents)) {
+        Ok(_) => {}
+        Err(e) => return Err(format!("Couldn't read {}: {}", filename, e))
+    }
+    parse(&contents).map_err(|e| format!("{}: {}", filename, e))
+}
+
+pub fn parse(bytes: &[u8]) -> Result<Aseprite, String> {
+    let mut reader = Reader { bytes: bytes, pos: 0 };
+
+    // === Header ===
+    try!(reader.skip(4)); // file size
+    if try!(reader.u16()) != HEADER_MAGIC {
+        return Err("not an .aseprite file".to_string());
+    }
+    let frame_count = try!(reader.u16()) as usize;
+    let width  = try!(reader.u16()) as i32;
+    let height = try!(reader.u16()) as i32;
+    let depth  = try!(reader.u16());
+    if depth != 32 && depth != 16 && depth != 8 {
+        return Err(format!("{} bits per pixel isn't a color mode", depth));
+    }
+    let flags = try!(reader.u32());
+    try!(reader.skip(2 + 4 + 4)); // speed, then two zeroes
+    let transparent_index = try!(reader.u8());
+    try!(reader.skip(128 - 29));
+
+    let mut ase = Aseprite { width: width, height: height, layers: Vec::new(), frames: Vec::new(), tags: Vec::new() };
+    let mut palette = vec![[0u8; 4]; 256];
+
+    // === Frames ===
+    for frame_number in 0..frame_count {
+        let frame_start = reader.pos;
+        let frame_size = try!(reader.u32()) as usize;
+        if try!(reader.u16()) != FRAME_MAGIC {
+            return Err(format!("frame {} is corrupt", frame_number));
+        }
+        let old_chunk_count = try!(reader.u16()) as usize;
+        let duration = try!(reader.u16());
+        try!(reader.skip(2));
+        let chunk_count = match try!(reader.u32()) as usize {
+            0 => old_chunk_count,
+            n => n
+        };
+
+        let mut frame = Frame { duration: duration as f32 / 1000.0, cels: Vec::new() };
+        for _ in 0..chunk_count {
+            let chunk_start = reader.pos;
+            let chunk_size = try!(reader.u32()) as usize;
+            let chunk_type = try!(reader.u16());
+            if chunk_size < 6 || chunk_start + chunk_size > bytes.len() {
+                return Err(format!("frame {} has a corrupt chunk", frame_number));
+            }
+            let mut chunk = Reader { bytes: &bytes[..chunk_start + chunk_size], pos: reader.pos };
+
+            match chunk_type {
+                CHUNK_LAYER => {
+                    let layer_flags = try!(chunk.u16());
+                    let kind = try!(chunk.u16());
+                    let child_level = try!(chunk.u16());
+                    try!(chunk.skip(2 + 2 + 2)); // default size, blend mode
+                    let opacity = try!(chunk.u8());
+                    try!(chunk.skip(3));
+                    let name = try!(chunk.string());
+                    ase.layers.push(Layer {
+                        name: name,
+                        visible: layer_flags & LAYER_VISIBLE != 0,
+                        kind: kind,
+                        child_level: child_level,
+                        opacity: if flags & HEADER_FLAG_LAYER_OPACITY != 0 { opacity } else { 255 }
+                    });
+                }
+
+                CHUNK_CEL => {
+                    let layer = try!(chunk.u16()) as usize;
+                    let x = try!(chunk.i16()) as i32;
+                    let y = try!(chunk.i16()) as i32;
+                    let opacity = try!(chunk.u8());
+                    let cel_type = try!(chunk.u16());
+                    try!(chunk.skip(2 + 5)); // z-index, reserved
+
+                    let cel = match cel_type {
+                        CEL_RAW | CEL_COMPRESSED => {
+                            let cel_width  = try!(chunk.u16()) as i32;
+                            let cel_height = try!(chunk.u16()) as i32;
+                            let rest = chunk.bytes.len() - chunk.pos;
+                            let data = try!(chunk.take(rest));
+                            let pixels = if cel_type == CEL_RAW { data.to_vec() } else {
+                                try!(zlib_decode(data).ok_or(format!("frame {} has a corrupt cel", frame_number)))
+                            };
+                            let size = (cel_width as usize).checked_mul(cel_height as usize)
+                                .and_then(|count| count.checked_mul(depth as usize / 8));
+                            match size {
+                                Some(size) if pixels.len() >= size => {}
+                                _ => return Err(format!("frame {} has a cel that's too short", frame_number))
+                            }
+                            // (to_rgba takes grayscale two bytes at a time.)
+                            if depth == 16 && pixels.len() % 2 != 0 {
+                                return Err(format!("frame {} has a grayscale cel with half a pixel", frame_number));
+                            }
+                            Cel { layer: layer, x: x, y: y, opacity: opacity,
+                                  width: cel_width, height: cel_height, pixels: pixels }
+                        }
+                        CEL_LINKED => {
+                            let linked = try!(chunk.u16()) as usize;
+                            let original = ase.frames.get(linked)
+                                .and_then(|f| f.cels.iter().find(|c| c.layer == layer));
+                            match original {
+                                Some(c) => Cel { layer: layer, x: x, y: y, opacity: opacity,
+                                                 width: c.width, height: c.height, pixels: c.pixels.clone() },
+                                None => return Err(format!("frame {} links to a cel that isn't there", frame_number))
+                            }
+                        }
+                        // Tilemaps
+                        _ => { reader.pos = chunk_start + chunk_size; continue }
+                    };
+                    frame.cels.push(cel);
+                }
+
+                CHUNK_TAGS => {
+                    let count = try!(chunk.u16());
+                    try!(chunk.skip(8));
+                    for _ in 0..count {
+                        let from = try!(chunk.u16()) as usize;
+                        let to   = try!(chunk.u16()) as usize;
+                        let direction = try!(chunk.u8());
+                        let repeat = try!(chunk.u16());
+                        try!(chunk.skip(6 + 3 + 1));
+                        let name = try!(chunk.string());
+                        ase.tags.push(Tag { name: name, from: from, to: to, direction: direction, repeat: repeat });
+                    }
+                }
+
+                CHUNK_PALETTE => {
+                    try!(chunk.skip(4)); // new size
+                    let first = try!(chunk.u32()) as usize;
+                    let last  = try!(chunk.u32()) as usize;
+                    if last < first {
+                        return Err(format!("palette goes from color {} to {}", first, last));
+                    }
+                    try!(chunk.skip(8));
+                    // first to last inclusive, without a last + 1 to overflow.
+                    for i in (0..last - first).chain(Some(last - first)).map(|n| first + n) {
+                        let entry_flags = try!(chunk.u16());
+                        let rgba = try!(chunk.take(4));
+                        if i < palette.len() { palette[i] = [rgba[0], rgba[1], rgba[2], rgba[3]]; }
+                        if entry_flags & 1 != 0 { try!(chunk.string()); }
+                    }
+                }
+
+                _ => {}
+            }
+            reader.pos = chunk_start + chunk_size;
+        }
+        ase.frames.push(frame);
+        reader.pos = frame_start + frame_size;
+    }
+
+    // Linked cels were copied before conversion, so everything converts once.
+    for frame in ase.frames.iter_mut() {
+        for cel in frame.cels.iter_mut() {
+            cel.pixels = to_rgba(&cel.pixels, depth, &palette, transparent_index);
+        }
+    }
+    Ok(ase)
+}
+
+fn to_rgba(pixels: &[u8], depth: u16, palette: &[[u8; 4]], transparent_index: u8) -> Vec<u8> {
+    match depth {
+        32 => pixels.to_vec(),
+        16 => {
+            let mut rgba = Vec::with_capacity(pixels.len() * 2);
+            for pair in pixels.chunks(2) {
+                rgba.extend([pair[0], pair[0], pair[0], pair[1]].iter().cloned());
+            }
+            rgba
+        }
+        _ => {
+            let mut rgba = Vec::with_capacity(pixels.len() * 4);
+            for &index in pixels.iter() {
+                if index == transparent_index { rgba.extend([0u8; 4].iter().cloned()); }
+                else { rgba.extend(palette[index as usize].iter().cloned()); }
+            }
+            rgba
+        }
+    }
+}
+
+// Plain "over", with straight alpha.
+fn blend(dest: &mut [u8], src: &[u8], opacity: f32) {
+    let src_alpha  = src[3] as f32 / 255.0 * opacity;
+    let dest_alpha = dest[3] as f32 / 255.0;
+    let out_alpha  = src_alpha + dest_alpha * (1.0 - src_alpha);
+    if out_alpha <= 0.0 { return }
+    for c in 0..3 {
+        let color = (src[c] as f32 * src_alpha + dest[c] as f32 * dest_alpha * (1.0 - src_alpha)) / out_alpha;
+        dest[c] = color as u8;
+    }
+    dest[3] = (out_alpha * 255.0) as u8;
+}
+
+impl Aseprite {
+    pub fn find_layer(&self, name: &str) -> Option<usize> {
+        self.layers.iter().position(|l| l.name == name)
+    }
+
+    // The group the given layer is in, if any.
+    pub fn parent(&self, layer: usize) -> Option<usize> {
+        let level = self.layers[layer].child_level;
+        if level == 0 { return None }
+        (0..layer).rev().find(|&i| self.layers[i].child_level == level - 1)
+    }
+
+    // Whether `layer` shows up when drawing `only` (or everything when None).
+    // Picking a layer draws it even if it's hidden, along with whatever
+    // visible layers it has under it.
+    fn draws(&self, layer: usize, only: Option<usize>) -> bool {
+        if self.layers[layer].kind != LAYER_TYPE_NORMAL { return false }
+        let mut current = Some(layer);
+        while let Some(i) = current {
+            if Some(i) == only { return true }
+            if !self.layers[i].visible { return false }
+            current = self.parent(i);
+        }
+        only.is_none()
+    }
+
+    // Every frame side by side, flattened, as RGBA rows from the top. Frame
+    // n is at x = n * width.
+    pub fn sheet(&self, layer: Option<&str>) -> Result<(Vec<u8>, i32, i32), String> {
+        let only = match layer {
+            Some(name) => match self.find_layer(name) {
+                Some(i) => Some(i),
+                None => return Err(format!("no layer called {}", name))
+            },
+            None => None
+        };
+        if let Some(i) = only {
+            if self.layers[i].kind != LAYER_TYPE_NORMAL && self.layers[i].kind != LAYER_TYPE_GROUP {
+                return Err(format!("layer {} is a tilemap", self.layers[i].name));
+            }
+        }
+
+        let sheet_width = self.width * self.frames.len() as i32;
+        let mut pixels = vec![0u8; (sheet_width * self.height * 4) as usize];
+
+        for (n, frame) in self.frames.iter().enumerate() {
+            // Cels come in any order, but layers stack bottom to top.
+            for layer in 0..self.layers.len() {
+                if !self.draws(layer, only) { continue }
+                for cel in frame.cels.iter().filter(|c| c.layer == layer) {
+                    let opacity = cel.opacity as f32 / 255.0 * self.layers[layer].opacity as f32 / 255.0;
+                    for y in 0..cel.height {
+                        let sheet_y = cel.y + y;
+                        if sheet_y < 0 || sheet_y >= self.height { continue }
+                        for x in 0..cel.width {
+                            let frame_x = cel.x + x;
+                            if frame_x < 0 || frame_x >= self.width { continue }
+                            let src  = ((y * cel.width + x) * 4) as usize;
+                            let dest = ((sheet_y * sheet_width + n as i32 * self.width + frame_x) * 4) as usize;
+                            blend(&mut pixels[dest..dest + 4], &cel.pixels[src..src + 4], opacity);
+                        }
+                    }
+                }
+            }
+        }
+        Ok((pixels, sheet_width, self.height))
+    }
+}
+
+#[test]
+fn half_transparent_over_opaque() {
+    let mut dest = [0, 0, 255, 255];
+    blend(&mut dest, &[255, 0, 0, 255], 0.5);
+    assert_eq!(dest, [127, 0, 127, 255]);
+}
+
+// fixture.aseprite is 2x2 with three frames (100, 200 and 50 ms) and layers
+// body, feet (a group) with foot in it, and hidden (not visible).
+//   frame 0: body is a raw red/green/blue/clear square, foot a zlib white
+//            pixel at (1, 1) and hidden a black one at (0, 0)
+//   frame 1: body links to frame 0's, foot is white at (0, 1)
+//   frame 2: body is one (10, 20, 30) pixel at (1, 0)
+// with tags idle (frame 0) and walk (frames 1 to 2, played once).
+// fixture-indexed.aseprite is one 2x1 indexed frame: palette entry 2, then
+// the transparent index, with a ping-pong tag.
+#[test]
+fn fixtures_parse_into_layers_frames_and_tags() {
+    let ase = parse(include_bytes!("fixture.aseprite")).unwrap();
+    assert_eq!((ase.width, ase.height, ase.frames.len()), (2, 2, 3));
+    let durations: Vec<f32> = ase.frames.iter().map(|f| f.duration).collect();
+    assert_eq!(durations, vec![0.1, 0.2, 0.05]);
+
+    let names: Vec<&str> = ase.layers.iter().map(|l| &l.name[..]).collect();
+    assert_eq!(names, vec!["body", "feet", "foot", "hidden"]);
+    assert_eq!(ase.layers[1].kind, LAYER_TYPE_GROUP);
+    assert_eq!((ase.parent(2), ase.parent(3)), (Some(1), None));
+    assert!(!ase.layers[3].visible);
+
+    let tags: Vec<(&str, usize, usize, u16)> = ase.tags.iter().map(|t| (&t.name[..], t.from, t.to, t.repeat)).collect();
+    assert_eq!(tags, vec![("idle", 0, 0, 0), ("walk", 1, 2, 1)]);
+
+    // The linked cel got frame 0's pixels.
+    assert_eq!(ase.frames[1].cels[0].pixels, ase.frames[0].cels[0].pixels);
+
+    let pixel = |sheet: &(Vec<u8>, i32, i32), x: i32, y: i32| {
+        let i = ((y * sheet.1 + x) * 4) as usize;
+        [sheet.0[i], sheet.0[i + 1], sheet.0[i + 2], sheet.0[i + 3]]
+    };
+    let all = ase.sheet(None).unwrap();
+    assert_eq!((all.1, all.2), (6, 2));
+    assert_eq!(pixel(&all, 0, 0), [255, 0, 0, 255]);     // not the hidden layer's black
+    assert_eq!(pixel(&all, 1, 1), [255, 255, 255, 255]); // foot over body's clear pixel
+    assert_eq!(pixel(&all, 2, 1), [255, 255, 255, 255]); // foot over blue, frame 1
+    assert_eq!(pixel(&all, 5, 0), [10, 20, 30, 255]);    // the offset cel in frame 2
+    assert_eq!(pixel(&all, 4, 0), [0, 0, 0, 0]);
+
+    let feet = ase.sheet(Some("feet")).unwrap();
+    assert_eq!(pixel(&feet, 0, 0), [0, 0, 0, 0]);
+    assert_eq!(pixel(&feet, 1, 1), [255, 255, 255, 255]);
+    let hidden = ase.sheet(Some("hidden")).unwrap();
+    assert_eq!(pixel(&hidden, 0, 0), [0, 0, 0, 255]);
+
+    let indexed = parse(include_bytes!("fixture-indexed.aseprite")).unwrap();
+    assert_eq!(indexed.sheet(None).unwrap().0, vec![200, 100, 50, 255, 0, 0, 0, 0]);
+    assert!(indexed.tags[0].direction != TAG_FORWARD);
+    // Grayscale is value then alpha.
+    assert_eq!(to_rgba(&[100, 200], 16, &[], 0), vec![100, 100, 100, 200]);
+
+    assert!(parse(&include_bytes!("fixture.aseprite")[..200]).is_err());
+}
@@ -0,0 +1,456 @@
+use gl::types::*;
+use std::mem::zeroed;
+use vecmath::Vec2;
+use render::{Frame, ImageAsset, Texture, read_pixels, free_pixels};
+use assets::registry::Images;
+use assets::sheet;
+use assets::sheet::SheetFrame;
+use assets::aseprite;
+
+// Every frame of every image in the manifest gets packed into one (or, if
+// they really don't fit, a few) atlas textures at load time. Images that
+// share an atlas bind the same texture, and their texcoords point at where
+// their frames ended up.
+//
+// Packing is the dumb shelf kind: tallest frames first, left to right, new
+// shelf when a row fills up. Sprite sheets are mostly same-sized frames, so
+// it does fine.
+
+pub const MAX_ATLASES: usize = 4;
+// Between frames, so nothing bleeds into its neighbours.
+pub const PADDING: i32 = 1;
+// Smallest atlas we bother with; doubles until everything fits.
+pub const MIN_ATLAS_SIZE: i32 = 256;
+pub const MAX_ATLAS_SIZE: i32 = 2048;
+
+#[derive(Copy, Clone)]
+pub struct Atlas {
+    pub texture: GLuint,
+    pub size:    i32 // square
+}
+
+// Where a frame went. x and y are in pixels from the atlas's first row,
+// like the sheets they're cut from.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AtlasRect {
+    pub atlas:  usize,
+    pub x:      i32,
+    pub y:      i32,
+    pub width:  i32,
+    pub height: i32
+}
+
+// Packs rectangles of the given sizes into as few atlas_size atlases as
+// possible. Results are in the same order as `sizes`.
+pub fn pack(sizes: &[(i32, i32)], atlas_size: i32) -> Result<Vec<AtlasRect>, String> {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by(|&a, &b| sizes[b].1.cmp(&sizes[a].1));
+
+    let mut rects = vec![AtlasRect { atlas: 0, x: 0, y: 0, width: 0, height: 0 }; sizes.len()];
+    let (mut atlas, mut x, mut y, mut shelf_height) = (0, 0, 0, 0);
+
+    for &i in order.iter() {
+        let (width, height) = sizes[i];
+        if width > atlas_size || height > atlas_size {
+            return Err(format!("a {}x{} frame is bigger than a whole {}x{} atlas",
+                               width, height, atlas_size, atlas_size));
+        }
+        if x + width > atlas_size {
+            x = 0;
+            y += shelf_height + PADDING;
+            shelf_height = 0;
+        }
+        if y + height > atlas_size {
+            atlas += 1;
+            x = 0; y = 0; shelf_height = 0;
+        }
+        if atlas == MAX_ATLASES {
+            return Err(format!("frames don't fit in {} {}x{} atlases", MAX_ATLASES, atlas_size, atlas_size));
+        }
+
+        rects[i] = AtlasRect { atlas: atlas, x: x, y: y, width: width, height: height };
+        x += width + PADDING;
+        if height > shelf_height { shelf_height = height; }
+    }
+    Ok(rects)
+}
+
+// Like pack, but rects in the same group (like the frames of one image,
+// which only binds one texture) always end up in the same atlas. Groups go
+// into the first atlas they fit in.
+pub fn pack_groups(sizes: &[(i32, i32)], groups: &[usize], atlas_size: i32) -> Result<Vec<AtlasRect>, String> {
+    let fits_one = |members: &[usize]| {
+        let member_sizes: Vec<(i32, i32)> = members.iter().map(|&i| sizes[i]).collect();
+        match pack(&member_sizes, atlas_size) {
+            Ok(rects) => rects.iter().all(|r| r.atlas == 0),
+            Err(_) => false
+        }
+    };
+
+    let mut seen: Vec<usize> = Vec::new();
+    let mut atlases: Vec<Vec<usize>> = Vec::new();
+    for &group in groups.iter() {
+        if seen.contains(&group) { continue }
+        seen.push(group);
+        let members: Vec<usize> = (0..sizes.len()).filter(|&i| groups[i] == group).collect();
+        if !fits_one(&members) {
+            return Err(format!("{} frames of one image don't fit in one {}x{} atlas",
+                               members.len(), atlas_size, atlas_size));
+        }
+
+        let mut placed = false;
+        for atlas in atlases.iter_mut() {
+            let mut candidate = atlas.clone();
+            candidate.extend(members.iter().cloned());
+            if fits_one(&candidate) {
+                *atlas = candidate;
+                placed = true;
+                break;
+            }
+        }
+        if !placed {
+            if atlases.len() == MAX_ATLASES {
+                return Err(format!("frames don't fit in {} {}x{} atlases", MAX_ATLASES, atlas_size, atlas_size));
+            }
+            atlases.push(members);
+        }
+    }
+
+    let mut rects = vec![AtlasRect { atlas: 0, x: 0, y: 0, width: 0, height: 0 }; sizes.len()];
+    for (atlas, members) in atlases.iter().enumerate() {
+        let member_sizes: Vec<(i32, i32)> = members.iter().map(|&i| sizes[i]).collect();
+        let packed = try!(pack(&member_sizes, atlas_size));
+        for (&i, rect) in members.iter().zip(packed.iter()) {
+            rects[i] = AtlasRect { atlas: atlas, .. *rect };
+        }
+    }
+    Ok(rects)
+}
+
+// The smallest atlas size that fits everything in one atlas, or the biggest
+// size we're allowed if nothing does. See pack_groups for `groups`.
+pub fn pack_smallest(sizes: &[(i32, i32)], groups: &[usize], max_size: i32) -> Result<(i32, Vec<AtlasRect>), String> {
+    let mut size = MIN_ATLAS_SIZE;
+    while size < max_size {
+        match pack_groups(sizes, groups, size) {
+            Ok(ref rects) if rects.iter().all(|r| r.atlas == 0) => return Ok((size, rects.clone())),
+            _ => size *= 2
+        }
+    }
+    pack_groups(sizes, groups, max_size).map(|rects| (max_size, rects))
+}
+
+// A sheet's pixels, from stb_image or decoded by us.
+enum Pixels {
+    Stb(*const u8),
+    Decoded(Vec<u8>)
+}
+
+impl Pixels {
+    fn as_ptr(&self) -> *const u8 {
+        match *self {
+            Pixels::Stb(pixels) => pixels,
+            Pixels::Decoded(ref pixels) => pixels.as_ptr()
+        }
+    }
+}
+
+impl Drop for Pixels {
+    fn drop(&mut self) {
+        if let Pixels::Stb(pixels) = *self { unsafe { free_pixels(pixels) } }
+    }
+}
+
+// Reads an image's sheet, flattening .aseprite files into a row of frames.
+unsafe fn read_sheet(image: &ImageAsset) -> Result<(Pixels, i32, i32), String> {
+    let filename = image.filename.as_str();
+    if filename.ends_with(".aseprite") {
+        let ase = try!(aseprite::load(filename));
+        let layer = if image.layer.as_str().is_empty() { None } else { Some(image.layer.as_str()) };
+        let (pixels, width, height) = try!(ase.sheet(layer).map_err(|e| format!("{}: {}", filename, e)));
+        Ok((Pixels::Decoded(pixels), width, height))
+    }
+    else {
+        read_pixels(filename).map(|(pixels, width, height)| (Pixels::Stb(pixels), width, height))
+    }
+}
+
+// Copies every frame of a sheet into its spot in the atlas.
+unsafe fn blit_frames(frames: &[SheetFrame], rects: &[AtlasRect], atlases: &[Atlas], pixels: *const u8, sheet_width: i32) {
+    gl::PixelStorei(gl::UNPACK_ROW_LENGTH, sheet_width);
+    for (frame, rect) in frames.iter().zip(rects.iter()) {
+        gl::PixelStorei(gl::UNPACK_SKIP_PIXELS, frame.x);
+        gl::PixelStorei(gl::UNPACK_SKIP_ROWS, frame.y);
+
+        gl::BindTexture(gl::TEXTURE_2D, atlases[rect.atlas].texture);
+        gl::TexSubImage2D(
+            gl::TEXTURE_2D, 0, rect.x, rect.y, rect.width, rect.height,
+            gl::RGBA, gl::UNSIGNED_BYTE, pixels as *const _
+        );
+    }
+    gl::PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+    gl::PixelStorei(gl::UNPACK_SKIP_PIXELS, 0);
+    gl::PixelStorei(gl::UNPACK_SKIP_ROWS, 0);
+}
+
+unsafe fn create_atlas_texture(size: i32) -> GLuint {
+    let mut id = 0;
+    gl::GenTextures(1, &mut id);
+    gl::BindTexture(gl::TEXTURE_2D, id);
+
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+
+    // Starts out transparent, so the padding stays that way.
+    let clear = vec![0u8; (size * size * 4) as usize];
+    gl::TexImage2D(
+        gl::TEXTURE_2D, 0, gl::RGBA as i32, size, size, 0,
+        gl::RGBA, gl::UNSIGNED_BYTE, clear.as_ptr() as *const _
+    );
+    id
+}
+
+impl Images {
+    fn sheet_frames_of(&self, image: usize) -> &[SheetFrame] {
+        let ref image = self.assets[image];
+        &self.sheet_frames[image.first_texcoord..image.first_texcoord + image.texcoord_count]
+    }
+
+    // Cuts grid images up for a sheet of the given width, then makes sure
+    // every frame is on the sheet.
+    fn fit_frames(&mut self, image: usize, width: i32, height: i32) -> Result<(), String> {
+        if !self.assets[image].has_sheet() {
+            let frames = {
+                let ref image = self.assets[image];
+                sheet::grid(image.texcoord_count, image.frame_width, image.frame_height, width)
+            };
+            let first = self.assets[image].first_texcoord;
+            for (i, frame) in frames.iter().enumerate() { self.sheet_frames[first + i] = *frame; }
+        }
+
+        match self.sheet_frames_of(image).iter().position(|f| !f.fits(width, height)) {
+            Some(i) => {
+                let f = self.sheet_frames_of(image)[i];
+                Err(format!("{} is {}x{}, which doesn't fit frame {} ({}x{} at {},{})",
+                            self.assets[image].filename.as_str(), width, height,
+                            i, f.width, f.height, f.x, f.y))
+            }
+            None => Ok(())
+        }
+    }
+
+    // Reads every image in the registry, packs their frames into atlases and
+    // points each image's texture and texcoords at them.
+    pub unsafe fn build_atlases(&mut self) -> Result<(), String> {
+        let mut sheets: Vec<(Pixels, i32, i32)> = Vec::with_capacity(self.count);
+        let mut error = None;
+        for i in 0..self.count {
+            let sheet = read_sheet(&self.assets[i]);
+            match sheet {
+                Ok((pixels, width, height)) => {
+                    sheets.push((pixels, width, height));
+                    if let Err(e) = self.fit_frames(i, width, height) {
+                        error = Some(e);
+                        break;
+                    }
+                }
+                Err(e) => { error = Some(e); break }
+            }
+        }
+        // (Dropping `sheets` frees whatever got read.)
+        if let Some(e) = error {
+            return Err(e);
+        }
+
+        // Images that are the same file cut the same way share frames.
+        let mut sizes: Vec<(i32, i32)> = Vec::new();
+        let mut groups: Vec<usize> = Vec::new();
+        let mut first_rect: Vec<usize> = Vec::with_capacity(self.count);
+        for i in 0..self.count {
+            match (0..i).find(|&j| self.assets[j].same_frames_as(&self.assets[i])) {
+                Some(j) => { let first = first_rect[j]; first_rect.push(first); }
+                None => {
+                    first_rect.push(sizes.len());
+                    for frame in self.sheet_frames_of(i).iter() {
+                        sizes.push((frame.width, frame.height));
+                        groups.push(i);
+                    }
+                }
+            }
+        }
+
+        let mut max_size = 0;
+        gl::GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut max_size);
+        if max_size > MAX_ATLAS_SIZE { max_size = MAX_ATLAS_SIZE; }
+        let (size, rects) = try!(pack_smallest(&sizes, &groups, max_size));
+
+        self.unload_atlases();
+        self.atlas_count = rects.iter().map(|r| r.atlas + 1).max().unwrap_or(0);
+        for atlas in 0..self.atlas_count {
+            self.atlases[atlas] = Atlas { texture: create_atlas_texture(size), size: size };
+        }
+        println!("Packed {} frames into {} {}x{} atlases.", sizes.len(), self.atlas_count, size, size);
+
+        for i in 0..self.count {
+            let first = first_rect[i];
+            let count = self.assets[i].texcoord_count;
+            let first_texcoord = self.assets[i].first_texcoord;
+            for f in 0..count { self.atlas_rects[first_texcoord + f] = rects[first + f]; }
+
+            let (pixels, width) = (sheets[i].0.as_ptr(), sheets[i].1);
+            let atlases = self.atlases;
+            let image_rects = &rects[first..first + count];
+            blit_frames(self.sheet_frames_of(i), image_rects, &atlases, pixels, width);
+            self.assets[i].use_atlas(image_rects, &atlases);
+
+            if self.assets[i].vbo == 0 { gl::GenBuffers(1, &mut self.assets[i].vbo); }
+        }
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        self.upload_frame_table();
+        Ok(())
+    }
+
+    // Re-reads any image from the given file into its spot in the atlas.
+    // Keeps the old pixels if the file can't be read (editors like to save
+    // in a few steps) or if the frames don't fit anymore. Changed sheet
+    // metadata repacks everything, since frame sizes can change.
+    pub unsafe fn reload(&mut self, filename: &str) {
+        if self.all().iter().any(|image| image.sheet.as_str() == filename) {
+            self.reload_sheet(filename);
+            return;
+        }
+
+        for i in 0..self.count {
+            if self.assets[i].filename.as_str() != filename { continue }
+
+            let sheet = read_sheet(&self.assets[i]);
+            let (pixels, width, height) = match sheet {
+                Ok(p) => p,
+                Err(e) => { println!("{} -- keeping the old frames.", e); return }
+            };
+            // Grid frames only move around on the sheet, so they still fit
+            // the same spots in the atlas.
+            let old_frames = self.sheet_frames;
+            match self.fit_frames(i, width, height) {
+                Err(e) => {
+                    println!("{} -- keeping the old frames.", e);
+                    self.sheet_frames = old_frames;
+                }
+                Ok(()) => {
+                    println!("Reloading {} into its atlas.", filename);
+                    let ref image = self.assets[i];
+                    let rects = &self.atlas_rects[image.first_texcoord..image.first_texcoord + image.texcoord_count];
+                    blit_frames(self.sheet_frames_of(i), rects, &self.atlases, pixels.as_ptr(), width);
+                    gl::BindTexture(gl::TEXTURE_2D, 0);
+                }
+            }
+        }
+    }
+
+    unsafe fn reload_sheet(&mut self, filename: &str) {
+        let meta = match sheet::load(filename) {
+            Ok(meta) => meta,
+            Err(e) => { println!("{} -- keeping the old frames.", e); return }
+        };
+
+        let old_frames = self.sheet_frames;
+        for i in 0..self.count {
+            if self.assets[i].sheet.as_str() != filename { continue }
+            // Frame counts are baked into the texcoord space.
+            let unchanged = {
+                let ref image = self.assets[i];
+                meta.frames.len() == image.texcoord_count && meta.image == image.filename.as_str()
+            };
+            if !unchanged {
+                println!("{} has {} frames of {} now instead of {} of {} -- restart to pick that up.",
+                         filename, meta.frames.len(), meta.image,
+                         self.assets[i].texcoord_count, self.assets[i].filename.as_str());
+                self.sheet_frames = old_frames;
+                return;
+            }
+            let first = self.assets[i].first_texcoord;
+            for (f, frame) in meta.frames.iter().enumerate() {
+                self.sheet_frames[first + f] = *frame;
+            }
+        }
+
+        println!("Repacking atlases for {}.", filename);
+        match self.build_atlases() {
+            Ok(()) => self.bound_texture = 0,
+            Err(e) => {
+                println!("{} -- keeping the old frames.", e);
+                self.sheet_frames = old_frames;
+            }
+        }
+    }
+
+    pub unsafe fn unload_atlases(&mut self) {
+        for atlas in self.atlases[..self.atlas_count].iter() {
+            gl::DeleteTextures(1, &atlas.texture);
+        }
+        self.atlas_count = 0;
+    }
+}
+
+impl ImageAsset {
+    fn same_frames_as(&self, other: &ImageAsset) -> bool {
+        self.filename.as_str() == other.filename.as_str() &&
+        self.sheet.as_str() == other.sheet.as_str() &&
+        self.layer.as_str() == other.layer.as_str() &&
+        self.frame_width == other.frame_width &&
+        self.frame_height == other.frame_height &&
+        self.texcoord_count == other.texcoord_count
+    }
+
+    // Points this image at its frames in the atlas. All of an image's frames
+    // have to be in the same atlas, since it only binds one texture (which
+    // pack_groups makes sure of).
+    unsafe fn use_atlas(&mut self, rects: &[AtlasRect], atlases: &[Atlas]) {
+        let atlas = atlases[rects[0].atlas];
+        assert!(rects.iter().all(|r| r.atlas == rects[0].atlas), "{}'s frames got split across atlases", self.name.as_str());
+
+        for (rect, texcoords) in rects.iter().zip(self.texcoords().iter_mut()) {
+            let mut frame = Frame {
+                // Texcoords are upside down (see the .vert files).
+                position:  Vec2::new(rect.x as f32, (atlas.size - rect.y - rect.height) as f32),
+                size:      Vec2::new(rect.width as f32, rect.height as f32),
+                texcoords: zeroed()
+            };
+            frame.generate_texcoords(atlas.size as f32, atlas.size as f32);
+            frame.texcoords.copy_to(texcoords);
+        }
+
+        self.texture = Texture {
+            id:     atlas.texture,
+            width:  atlas.size,
+            height: atlas.size
+        };
+    }
+}
+
+#[test]
+fn shelves_fill_left_to_right_then_down() {
+    let rects = pack(&[(90, 90), (90, 90), (16, 16), (4, 5)], 190).unwrap();
+    assert_eq!(rects[0], AtlasRect { atlas: 0, x: 0,  y: 0, width: 90, height: 90 });
+    assert_eq!(rects[1], AtlasRect { atlas: 0, x: 91, y: 0, width: 90, height: 90 });
+    assert_eq!(rects[2], AtlasRect { atlas: 0, x: 0,  y: 91, width: 16, height: 16 });
+    assert_eq!(rects[3], AtlasRect { atlas: 0, x: 17, y: 91, width: 4,  height: 5 });
+
+    let spilled = pack(&[(90, 90), (90, 90)], 100).unwrap();
+    assert_eq!(spilled[1].atlas, 1);
+}
+
+#[test]
+fn an_images_frames_stay_in_one_atlas() {
+    // Four 60x60 frames fit a 128x128 atlas. Plain packing would put two of
+    // the second image's three frames in with the first image's two.
+    let sizes = [(60, 60); 5];
+    let groups = [0, 0, 1, 1, 1];
+    let rects = pack_groups(&sizes, &groups, 128).unwrap();
+    assert_eq!((rects[0].atlas, rects[1].atlas), (0, 0));
+    assert_eq!((rects[2].atlas, rects[3].atlas, rects[4].atlas), (1, 1, 1));
+
+    assert!(pack_groups(&[(60, 60); 5], &[0; 5], 128).is_err());
+}
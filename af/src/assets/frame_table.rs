@@ -0,0 +1,64 @@
+use gl::types::*;
+use assets::registry::{Images, MAX_TEXCOORDS};
+
+// Every frame's texcoords and size, for every image, in one float texture
+// that the vertex shaders texelFetch from (see load_frame in
+// assets/shaders/include/sprite.vert.glsl). It only changes when the atlases
+// get rebuilt, so drawing an image just sets which frame is its first.
+//
+// Two RGBA texels per frame, in the same order as Images::texcoords:
+//
+//     [u0, v0, u1, v1]       bottom left and top right texcoords
+//     [width, height, offset_x, offset_y]  in pixels (see SheetFrame)
+//
+// wrapping onto the next row every FRAME_TABLE_WIDTH texels.
+
+pub const FRAME_TABLE_WIDTH: usize = 256;
+pub const TEXELS_PER_FRAME: usize = 2;
+pub const FRAME_TABLE_HEIGHT: usize = MAX_TEXCOORDS * TEXELS_PER_FRAME / FRAME_TABLE_WIDTH;
+// Texture unit the table sits on; images get unit 0.
+pub const FRAME_TABLE_UNIT: GLuint = 1;
+
+impl Images {
+    // (Re)uploads the whole table from texcoords, atlas_rects and
+    // sheet_frames.
+    pub unsafe fn upload_frame_table(&mut self) {
+        let mut texels = vec![0.0f32; FRAME_TABLE_WIDTH * FRAME_TABLE_HEIGHT * 4];
+        for i in 0..self.texcoords_used {
+            let ref texcoords = self.texcoords[i];
+            let ref rect = self.atlas_rects[i];
+            let ref frame = self.sheet_frames[i];
+            let at = i * TEXELS_PER_FRAME * 4;
+            texels[at]     = texcoords.bottom_left.x;
+            texels[at + 1] = texcoords.bottom_left.y;
+            texels[at + 2] = texcoords.top_right.x;
+            texels[at + 3] = texcoords.top_right.y;
+            texels[at + 4] = rect.width as f32;
+            texels[at + 5] = rect.height as f32;
+            texels[at + 6] = frame.offset_x;
+            texels[at + 7] = frame.offset_y;
+        }
+
+        if self.frame_table == 0 {
+            gl::GenTextures(1, &mut self.frame_table);
+        }
+        gl::BindTexture(gl::TEXTURE_2D, self.frame_table);
+        // Float textures can't be filtered on ES, and texelFetch doesn't
+        // care anyway.
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+        gl::TexImage2D(
+            gl::TEXTURE_2D, 0, gl::RGBA32F as GLint,
+            FRAME_TABLE_WIDTH as GLsizei, FRAME_TABLE_HEIGHT as GLsizei, 0,
+            gl::RGBA, gl::FLOAT, texels.as_ptr() as *const _
+        );
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+    }
+
+    // Call before drawing: something else may have been bound to its unit.
+    pub unsafe fn bind_frame_table(&self) {
+        gl::ActiveTexture(gl::TEXTURE0 + FRAME_TABLE_UNIT);
+        gl::BindTexture(gl::TEXTURE_2D, self.frame_table);
+        gl::ActiveTexture(gl::TEXTURE0);
+    }
+}
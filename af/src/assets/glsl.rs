@@ -0,0 +1,110 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// Shader sources live in assets/shaders as .vert/.frag files. The only thing
+// we add to GLSL is
+//
+//     #include "include/sprite.vert.glsl"
+//
+// which pastes in another file, relative to the one doing the including.
+// Every file gets its own GLSL source string number (see #line), so compile
+// errors can be traced back to the right file through GlslSource::file.
+// Number 0 is left for whatever gets generated in front of the files (the
+// #version line and attributes from shader_assets!).
+
+pub static SHADER_DIR: &'static str = "assets/shaders";
+
+// Deep enough for any sane include tree, shallow enough to catch cycles.
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+pub struct GlslSource {
+    pub text: String,
+    // files[0] is source string number 1.
+    pub files: Vec<String>
+}
+
+impl GlslSource {
+    // The file a GLSL source string number refers to, or None for the
+    // generated part.
+    pub fn file(&self, source_number: usize) -> Option<&str> {
+        if source_number == 0 { return None }
+        self.files.get(source_number - 1).map(|f| &f[..])
+    }
+}
+
+pub fn shader_path(filename: &str) -> String {
+    format!("{}/{}", SHADER_DIR, filename)
+}
+
+fn read(filename: &str) -> Result<String, String> {
+    let mut contents = String::new();
+    match File::open(filename).and_then(|mut f| f.read_to_string(&mut contents)) {
+        Ok(_) => Ok(contents),
+        Err(e) => Err(format!("Couldn't read {}: {}", filename, e))
+    }
+}
+
+// Reads the given shader file with all of its includes pasted in.
+pub fn load(filename: &str) -> Result<GlslSource, String> {
+    preprocess(filename, &mut |f| read(f))
+}
+
+pub fn preprocess<F>(filename: &str, read_file: &mut F) -> Result<GlslSource, String>
+    where F: FnMut(&str) -> Result<String, String>
+{
+    let mut source = GlslSource { text: String::new(), files: Vec::new() };
+    try!(paste(filename, read_file, &mut source, 0));
+    Ok(source)
+}
+
+fn paste<F>(filename: &str, read_file: &mut F, source: &mut GlslSource, depth: usize) -> Result<(), String>
+    where F: FnMut(&str) -> Result<String, String>
+{
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(format!("{}: includes nested more than {} deep (is something including itself?)",
+                           filename, MAX_INCLUDE_DEPTH));
+    }
+
+    let contents = try!(read_file(filename));
+    source.files.push(filename.to_string());
+    let file_number = source.files.len();
+    source.text.push_str(&format!("#line 1 {}\n", file_number));
+
+    for (line_number, line) in contents.lines().enumerate() {
+        let trimmed = line.trim();
+        if !trimmed.starts_with("#include") {
+            source.text.push_str(line);
+            source.text.push('\n');
+            continue;
+        }
+
+        let included = trimmed["#include".len()..].trim();
+        if included.len() < 2 || !included.starts_with('"') || !included.ends_with('"') {
+            return Err(format!("{} line {}: expected #include \"file\"", filename, line_number + 1));
+        }
+        let included = &included[1..included.len() - 1];
+        let path = match Path::new(filename).parent() {
+            Some(dir) if dir.to_str() != Some("") => format!("{}/{}", dir.to_str().unwrap(), included),
+            _ => included.to_string()
+        };
+
+        try!(paste(&path, read_file, source, depth + 1));
+        // Pick up where we left off.
+        source.text.push_str(&format!("#line {} {}\n", line_number + 2, file_number));
+    }
+    Ok(())
+}
+
+#[test]
+fn includes_are_relative_to_the_including_file() {
+    let source = preprocess("shaders/a.vert", &mut |f| match f {
+        "shaders/a.vert"     => Ok("#include \"inc/b.glsl\"\nvoid main() {}".to_string()),
+        "shaders/inc/b.glsl" => Ok("float b;".to_string()),
+        _ => Err(format!("no {}", f))
+    }).unwrap();
+
+    assert_eq!(source.file(1), Some("shaders/a.vert"));
+    assert_eq!(source.file(2), Some("shaders/inc/b.glsl"));
+    assert_eq!(source.text, "#line 1 1\n#line 1 2\nfloat b;\n#line 2 1\nvoid main() {}\n");
+}
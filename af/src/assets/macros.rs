@@ -1,36 +1,3 @@
-#[macro_export]
-macro_rules! image_assets {
-    ($($texcoords_name:ident $name:ident: $sprite_type:ident [$texcoords:expr][$w:expr;$h:expr] $path:expr),+) =>  {
-
-    pub struct Images {
-        $(
-        pub $name: ImageAsset,
-        pub $texcoords_name: [Texcoords; $texcoords]
-        // concat_idents!($name, _texcoords): [Texcoords, $num_texcoords]
-        ),*
-    }
-
-    impl Images {
-        pub fn init(&mut self, gl_data: *const GLData) {
-            $(
-            self.$name = ImageAsset {
-                gl_data: gl_data,
-                filename: $path,
-                vbo: 0,
-                set_attributes: $sprite_type::set,
-                shader: $sprite_type::shader,
-                attributes_size: size_of::<$sprite_type>(),
-                texture: unsafe { zeroed() },
-                frame_width: $w,
-                frame_height: $h,
-                texcoord_count: $texcoords
-            };
-            )*
-        }
-    }
-    }
-}
-
 macro_rules! vertex_attrib_pointer {
     (float, $loc:expr, $size:expr, $offset:expr) => {
         gl::VertexAttribPointer(
@@ -65,19 +32,19 @@ macro_rules! shader_assets {
         $sprite_type:ident:
         [vertex]
             $(layout (location = $loc:expr) in $glsltype:ident($attrtype:ty) $name:ident;)*
-            ($vertmain:expr)
+            ($vertfile:expr)
         [fragment]
-            ($fragmain:expr)
+            ($fragfile:expr)
         )+
     ) => {
         pub struct Shader {
             pub program:             GLuint,
             pub cam_pos_uniform:     GLint,
             pub scale_uniform:       GLint,
-            pub sprite_size_uniform: GLint,
             pub screen_size_uniform: GLint,
             pub tex_uniform:         GLint,
-            pub frames_uniform:      GLint,
+            pub frame_table_uniform: GLint,
+            pub first_frame_uniform: GLint,
         }
 
         #[allow(non_snake_case)]
@@ -89,20 +56,21 @@ macro_rules! shader_assets {
             pub fn count() -> usize {
                 let mut c = 0;
                 $(
-                    $fragmain; // compiler complains if we don't reference stuff in loop.
+                    $fragfile; // compiler complains if we don't reference stuff in loop.
                     c += 1;
                 )*
                 c
             }
 
-            pub fn compile(gl_data: &mut GLData, window: &glfw::Window) -> Vec<&'static str> {
-                let mut failed = Vec::<&'static str>::with_capacity(Shaders::count());
+            // Shaders that fail keep whatever program they had before.
+            pub fn compile(gl_data: &mut GLData, window: &glfw::Window) -> Vec<ShaderError> {
+                let mut failed = Vec::<ShaderError>::with_capacity(Shaders::count());
                 let cam_pos_str     = CString::new("cam_pos".to_string()).unwrap();
                 let scale_str       = CString::new("scale".to_string()).unwrap();
-                let sprite_size_str = CString::new("sprite_size".to_string()).unwrap();
                 let screen_size_str = CString::new("screen_size".to_string()).unwrap();
                 let tex_str         = CString::new("tex".to_string()).unwrap();
-                let frames_str      = CString::new("frames".to_string()).unwrap();
+                let frame_table_str = CString::new("frame_table".to_string()).unwrap();
+                let first_frame_str = CString::new("first_frame".to_string()).unwrap();
 
                 $(unsafe {
                     println!("About to compile {}", stringify!($sprite_type));
@@ -113,17 +81,30 @@ macro_rules! shader_assets {
                             Some(shader.program)
                         } else { None };
 
-                    shader.program = match render::create_program($sprite_type::vertex_shader(), $sprite_type::fragment_shader()) {
-                        Some(program) => {
+                    let name = stringify!($sprite_type);
+                    let program = $sprite_type::vertex_shader()
+                        .map_err(|e| ShaderError::new(ShaderStage::Vertex, name, e))
+                        .and_then(|vert| $sprite_type::fragment_shader()
+                            .map_err(|e| ShaderError::new(ShaderStage::Fragment, name, e))
+                            .and_then(|frag| render::create_program(&vert, &frag, name))
+                        );
+
+                    shader.program = match program {
+                        Ok(program) => {
                             gl::UseProgram(program);
 
                             shader.cam_pos_uniform = gl::GetUniformLocation(program, cam_pos_str.as_ptr());
                             shader.scale_uniform = gl::GetUniformLocation(program, scale_str.as_ptr());
 
-                            shader.sprite_size_uniform = gl::GetUniformLocation(program, sprite_size_str.as_ptr());
                             shader.screen_size_uniform = gl::GetUniformLocation(program, screen_size_str.as_ptr());
                             shader.tex_uniform = gl::GetUniformLocation(program, tex_str.as_ptr());
-                            shader.frames_uniform = gl::GetUniformLocation(program, frames_str.as_ptr());
+                            shader.frame_table_uniform = gl::GetUniformLocation(program, frame_table_str.as_ptr());
+                            shader.first_frame_uniform = gl::GetUniformLocation(program, first_frame_str.as_ptr());
+
+                            // Texture units never change (see Texture::bind and
+                            // Images::bind_frame_table).
+                            gl::Uniform1i(shader.tex_uniform, 0);
+                            gl::Uniform1i(shader.frame_table_uniform, frame_table::FRAME_TABLE_UNIT as GLint);
 
                             // TODO this should maybe be handled elsewhere
                             gl::Uniform1f(shader.scale_uniform, 2.0);
@@ -139,8 +120,8 @@ macro_rules! shader_assets {
                             program
                         }
 
-                        None => {
-                            failed.push(stringify!($sprite_type));
+                        Err(e) => {
+                            failed.push(e);
                             shader.program
                         }
                     }
@@ -154,20 +135,43 @@ macro_rules! shader_assets {
             }
         }
 
+        // For images declared in the manifest, which only know their sprite
+        // type by name.
+        pub fn sprite_type_info(name: &str) -> Option<SpriteTypeInfo> {
+            $(
+                if name == stringify!($sprite_type) {
+                    return Some(SpriteTypeInfo {
+                        name:            stringify!($sprite_type),
+                        set_attributes:  $sprite_type::set,
+                        shader:          $sprite_type::shader,
+                        attributes_size: size_of::<$sprite_type>()
+                    });
+                }
+            )*
+            None
+        }
+
         $(
+            // In declaration order, since `set` points each attribute at
+            // the sum of the sizes before it.
             #[derive(Clone)]
+            #[repr(C)]
             pub struct $sprite_type {
                 $( pub $name: $attrtype ),*
             }
             impl Copy for $sprite_type { }
 
+            impl SpriteType for $sprite_type {
+                fn name() -> &'static str { stringify!($sprite_type) }
+            }
+
             impl $sprite_type {
                 #[allow(unused_assignments)] // Compiler is wrong about offset not being used...
-                pub fn set(vbo: GLuint) { unsafe {
+                pub fn set(vbo: GLuint, first_instance: usize) { unsafe {
                     gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
 
                     let size_of_sprite = size_of::<$sprite_type>() as GLint;
-                    let mut offset: i64 = 0;
+                    let mut offset: i64 = first_instance as i64 * size_of_sprite as i64;
 
                     $(
                         gl::EnableVertexAttribArray($loc);
@@ -186,7 +190,7 @@ macro_rules! shader_assets {
                     &gl_data.shaders.$sprite_type
                 }
 
-                pub fn vertex_shader() -> String {
+                pub fn vertex_shader() -> Result<GlslSource, String> {
                     let mut vertex = String::with_capacity(4092);
                     vertex.push_str("
                         #version 300 es
@@ -204,90 +208,30 @@ macro_rules! shader_assets {
                             $loc, stringify!($glsltype), stringify!($name)
                         ));
                     });*
-
-                    vertex.push_str("
-                        // NOTE up this if you run into problems
-                        uniform vec2[256] frames;
-                        uniform vec2 screen_size;
-                        uniform vec2 cam_pos;     // in pixels
-                        uniform vec2 sprite_size; // in pixels
-                        uniform float scale;
-
-                        out vec2 texcoord;
-
-                        const vec2 TEXCOORD_FROM_ID[4] = vec2[4](
-                            vec2(1.0, 1.0), vec2(1.0, 0.0),
-                            vec2(0.0, 0.0), vec2(0.0, 1.0)
-                        );
-
-                        vec2 from_pixel(vec2 pos)
-                        {
-                            return pos / screen_size;
-                        }
-                        ivec2 from_pixel(ivec2 pos)
-                        {
-                            return pos / ivec2(screen_size);
-                        }
-
-                        int flipped_vertex_id()
-                        {
-                            return 3 - gl_VertexID;
-                        }
-
-                        // These are I suppose an optimization for squares
-                        // rotating about their centers.
-                        const float VERT_DIST = 1.41421356237;
-                        const float ANGLE_OFFSETS[4] = float[4](
-                            // pi/4
-                            0.78539816339,
-                            // 7pi/4
-                            5.49778714378,
-                            // 5pi/4
-                            3.92699081699,
-                            // 3pi/4
-                            2.35619449019
-                        );
-
-                        vec4 color_from(int color)
-                        {
-                            // Totally assumes little endian...
-                            int red = int((uint(color) & 0xFF000000u) >> 24u);
-                            return vec4(
-                                float(red) / 256.0,
-                                float((color & 0x00FF0000) >> 16) / 256.0,
-                                float((color & 0x0000FF00) >> 8)  / 256.0,
-                                float( color & 0x000000FF)        / 256.0
-                            );
-                        }
-                    ");
-                    vertex.push_str($vertmain);
-                    // println!("VERTEX:\n{}", vertex);
-                    vertex
+                    // Has to match the table's layout (see frame_table.rs).
+                    vertex.push_str(&format!("const int FRAME_TABLE_WIDTH = {};\n",
+                        frame_table::FRAME_TABLE_WIDTH
+                    ));
+
+                    let mut source = try!(glsl::load(&glsl::shader_path($vertfile)));
+                    vertex.push_str(&source.text);
+                    source.text = vertex;
+                    // println!("VERTEX:\n{}", source.text);
+                    Ok(source)
                 }
 
-                pub fn fragment_shader() -> String {
+                pub fn fragment_shader() -> Result<GlslSource, String> {
                     let mut fragment = String::with_capacity(1028);
                     fragment.push_str("
                         #version 300 es
                         precision mediump float;
-
-                        in vec2 texcoord;
-                        out vec4 color;
-                        uniform sampler2D tex;
-
-                        bool approx(vec4 a, vec4 b, float alpha)
-                        {
-                            vec4 diff = abs(a - b);
-                            return diff.x <= alpha &&
-                                   diff.y <= alpha &&
-                                   diff.z <= alpha &&
-                                   diff.w <= alpha;
-                        }
                     ");
-                    fragment.push_str($fragmain);
 
-                    // println!("FRAGMENT:\n{}", fragment);
-                    fragment
+                    let mut source = try!(glsl::load(&glsl::shader_path($fragfile)));
+                    fragment.push_str(&source.text);
+                    source.text = fragment;
+                    // println!("FRAGMENT:\n{}", source.text);
+                    Ok(source)
                 }
             }
         )*
@@ -2,24 +2,63 @@ extern crate gl;
 extern crate glfw;
 
 use gl::types::*;
-use std::mem::{zeroed, size_of, transmute};
+use std::mem::{size_of, transmute};
 use render;
-use render::{GLData, ImageAsset, Texcoords};
+use render::{GLData, ShaderError, ShaderStage};
 use std::ffi::CString;
 use vecmath::*;
 
 #[macro_use]
 mod macros;
-
-image_assets!(
-    ccbdy crattlecrute_body:       SpriteType2Color2 [9][90;90] "assets/crattlecrute/body.png",
-    ccbft crattlecrute_back_foot:  SpriteType2Color2 [9][90;90] "assets/crattlecrute/back-foot.png",
-    ccfft crattlecrute_front_foot: SpriteType2Color2 [9][90;90] "assets/crattlecrute/front-foot.png",
-    ceye1 eye_1:     SpriteType3Color1 [1][4;5] "assets/eyes/standard-eye.png",
-    tstsp test_spin: SpriteType3Color1 [9][90;90] "assets/crattlecrute/body.png",
-    dirt1 dirt_1:    SpriteType1 [1][16;16] "assets/terrain/dirt1.png"
-);
-
+pub mod registry;
+pub mod glsl;
+pub mod atlas;
+pub mod frame_table;
+pub mod sheet;
+pub mod aseprite;
+
+pub use self::registry::{Images, ImageHandle};
+use self::glsl::GlslSource;
+
+// Implemented by every sprite type generated by shader_assets!.
+pub trait SpriteType {
+    fn name() -> &'static str;
+}
+
+pub struct SpriteTypeInfo {
+    pub name:            &'static str,
+    pub set_attributes:  extern "Rust" fn(GLuint, usize),
+    pub shader:          extern "Rust" fn(&GLData) -> &Shader,
+    pub attributes_size: usize
+}
+
+// Handles to the images game code draws directly, looked up from the
+// registry by name.
+#[derive(Copy, Clone)]
+pub struct Sprites {
+    pub crattlecrute_body:       ImageHandle<SpriteType2Color2>,
+    pub crattlecrute_back_foot:  ImageHandle<SpriteType2Color2>,
+    pub crattlecrute_front_foot: ImageHandle<SpriteType2Color2>,
+    pub eye_1:     ImageHandle<SpriteType3Color1>,
+    pub test_spin: ImageHandle<SpriteType3Color1>,
+    pub dirt_1:    ImageHandle<SpriteType1>
+}
+
+impl Sprites {
+    pub fn find(images: &Images) -> Result<Sprites, String> {
+        Ok(Sprites {
+            crattlecrute_body:       try!(images.handle("crattlecrute_body")),
+            crattlecrute_back_foot:  try!(images.handle("crattlecrute_back_foot")),
+            crattlecrute_front_foot: try!(images.handle("crattlecrute_front_foot")),
+            eye_1:     try!(images.handle("eye_1")),
+            test_spin: try!(images.handle("test_spin")),
+            dirt_1:    try!(images.handle("dirt_1"))
+        })
+    }
+}
+
+// Attributes are declared here so the Rust structs and the GLSL inputs can't
+// disagree; the shader bodies are files in assets/shaders (see glsl.rs).
 shader_assets!(
 // No rotation or color swapping - just frames and flipping.
 SpriteType1:
@@ -28,30 +67,10 @@ SpriteType1:
         layout (location = 1) in vec2(Vec2<GLfloat>) position; // in pixels
         layout (location = 2) in int(GLint) frame;
         layout (location = 3) in int(GLint) flipped;   // actually a bool
-    ("
-     void main()
-     {
-        vec2 pixel_screen_pos = (position - cam_pos) * 2.0;
-        gl_Position = vec4(
-            (vertex_pos * from_pixel(sprite_size) + from_pixel(pixel_screen_pos)) * scale,
-            0.0f, 1.0f
-        );
-        int index = flipped != 0 ? flipped_vertex_id() : gl_VertexID;
-        if (frame == -1)
-            texcoord = TEXCOORD_FROM_ID[index];
-        else
-            texcoord = frames[frame * 4 + index];
-        texcoord.y = 1.0 - texcoord.y;
-     }
-     ")
+    ("sprite_type_1.vert")
 
     [fragment]
-    ("
-     void main()
-     {
-        color = texture(tex, texcoord);
-     }
-     ")
+    ("sprite_type_1.frag")
 
 // Frames, flipping, rotates around center, 2 colors can be swapped.
 // Position refers to the center of the sprite.
@@ -64,54 +83,10 @@ SpriteType2Color2:
         layout (location = 4) in float(GLfloat) angle;
         layout (location = 5) in ivec2(Vec2<GLuint>) color_swap_1;
         layout (location = 6) in ivec2(Vec2<GLuint>) color_swap_2;
-    ("
-     out vec4 cswap1_from;
-     out vec4 cswap1_to;
-     out vec4 cswap2_from;
-     out vec4 cswap2_to;
-
-     void main()
-     {
-         vec2 pixel_screen_pos = (position - cam_pos) * 2.0;
-
-         float vert_angle = angle + ANGLE_OFFSETS[gl_VertexID];
-         vec2 vert = VERT_DIST * vec2(cos(vert_angle), sin(vert_angle));
-
-         gl_Position = vec4(
-             (vert * from_pixel(sprite_size) + from_pixel(pixel_screen_pos)) * scale,
-             0.0f, 1.0f
-         );
-         int index = flipped != 0 ? flipped_vertex_id() : gl_VertexID;
-         if (frame == -1)
-             texcoord = TEXCOORD_FROM_ID[index];
-         else
-             texcoord = frames[frame * 4 + index];
-         texcoord.y = 1.0 - texcoord.y;
-
-         cswap1_from = color_from(color_swap_1.x);
-         cswap1_to   = color_from(color_swap_1.y);
-         cswap2_from = color_from(color_swap_2.x);
-         cswap2_to   = color_from(color_swap_2.y);
-     }
-     ")
+    ("sprite_type_2_color_2.vert")
 
     [fragment]
-    ("
-     in vec4 cswap1_from;
-     in vec4 cswap1_to;
-     in vec4 cswap2_from;
-     in vec4 cswap2_to;
-
-     void main()
-     {
-        color = texture(tex, texcoord);
-
-        if (approx(color, cswap1_from, 0.1))
-            color = cswap1_to;
-        else if (approx(color, cswap2_from, 0.1))
-            color = cswap2_to;
-     }
-     ")
+    ("sprite_type_2_color_2.frag")
 
 // Rotates around given focal point, 1 color swap.
 SpriteType3Color1:
@@ -123,52 +98,8 @@ SpriteType3Color1:
         layout (location = 4) in float(GLfloat) angle;
         layout (location = 5) in ivec2(Vec2<GLint>) focus; // in pixels
         layout (location = 6) in ivec2(Vec2<GLuint>) color_swap;
-    ("
-     out vec4 cswap_from;
-     out vec4 cswap_to;
-
-     void main()
-     {
-         vec2 pixel_screen_pos = (position - cam_pos) * 2.0;
-         vec2 effective_focus = flipped == 0 ?
-             vec2(focus) : vec2(sprite_size.x - float(focus.x), focus.y);
-
-         vec2 pixel_offset = vertex_pos * sprite_size - effective_focus * 2.0;
-         float pixel_angle = angle + atan(pixel_offset.y, pixel_offset.x);
-         vec2 direction    = vec2(cos(pixel_angle), sin(pixel_angle));
-         float distance    = sqrt(dot(pixel_offset, pixel_offset));
-
-         vec2 vert = from_pixel(distance * direction);
-
-         gl_Position = vec4(
-             (vert + from_pixel(pixel_screen_pos)) * scale,
-             0.0f, 1.0f
-         );
-
-         int index = flipped != 0 ? flipped_vertex_id() : gl_VertexID;
-         if (frame == -1)
-             texcoord = TEXCOORD_FROM_ID[index];
-         else
-             texcoord = frames[frame * 4 + index];
-         texcoord.y = 1.0 - texcoord.y;
-
-         cswap_from = color_from(color_swap.x);
-         cswap_to   = color_from(color_swap.y);
-     }
-     ")
+    ("sprite_type_3_color_1.vert")
 
     [fragment]
-    ("
-     in vec4 cswap_from;
-     in vec4 cswap_to;
-
-     void main()
-     {
-        color = texture(tex, texcoord);
-
-        if (approx(color, cswap_from, 0.1))
-            color = cswap_to;
-     }
-     ")
+    ("sprite_type_3_color_1.frag")
 );
-
@@ -0,0 +1,357 @@
+use std::fs::File;
+use std::io::Read;
+use std::marker::PhantomData;
+use std::mem::zeroed;
+use std::ptr;
+use gl::types::GLuint;
+use render::{GLData, ImageAsset, Texcoords};
+use assets::{SpriteType, sprite_type_info};
+use assets::atlas::{Atlas, AtlasRect, MAX_ATLASES};
+use assets::sheet;
+use assets::sheet::SheetFrame;
+use assets::aseprite;
+
+// Every image the game can draw, read from assets/images.manifest at startup:
+//
+//     # name                   sprite type        frames  size   path
+//     crattlecrute_body        SpriteType2Color2  9       90x90  assets/crattlecrute/body.png
+//     crattlecrute_hat         SpriteType2Color2                 assets/crattlecrute/hat.json
+//     crattlecrute_tail        SpriteType2Color2                 assets/crattlecrute/tail.aseprite  tail
+//
+// One image per line, columns separated by whitespace, `#` starts a comment.
+// Images whose frames aren't a grid leave out frames and size and point at
+// the sheet's JSON instead (see sheet.rs). .aseprite files work the same
+// way, optionally followed by the one layer (or group) to use (see
+// aseprite.rs).
+// Game code gets at images through typed handles (see Images::handle), so
+// drawing an image with the wrong sprite type is caught when the handle is
+// looked up instead of when the GPU chews on garbage.
+//
+// The frames all end up packed into a few shared textures (see atlas.rs).
+//
+// This all lives in GLData (host memory), so it's fixed size.
+
+pub static MANIFEST_PATH: &'static str = "assets/images.manifest";
+
+pub const MAX_IMAGES: usize = 32;
+pub const MAX_TEXCOORDS: usize = 1024;
+pub const MAX_ASSET_STRING: usize = 63;
+
+// A string that can live in GLData.
+#[derive(Copy, Clone)]
+pub struct AssetString {
+    len: u8,
+    bytes: [u8; MAX_ASSET_STRING]
+}
+
+impl AssetString {
+    pub fn new(string: &str) -> Option<AssetString> {
+        if string.len() > MAX_ASSET_STRING { return None }
+        let mut result = AssetString { len: string.len() as u8, bytes: [0; MAX_ASSET_STRING] };
+        for (i, &b) in string.as_bytes().iter().enumerate() { result.bytes[i] = b; }
+        Some(result)
+    }
+
+    // Cuts off whatever doesn't fit.
+    pub fn truncated(string: &str) -> AssetString {
+        let mut len = if string.len() > MAX_ASSET_STRING { MAX_ASSET_STRING } else { string.len() };
+        while !string.is_char_boundary(len) { len -= 1; }
+        AssetString::new(&string[..len]).unwrap()
+    }
+
+    pub fn as_str(&self) -> &str {
+        ::std::str::from_utf8(&self.bytes[..self.len as usize]).unwrap()
+    }
+}
+
+pub struct ImageHandle<T> {
+    pub index: usize,
+    _sprite: PhantomData<T>
+}
+impl<T> Copy for ImageHandle<T> {}
+impl<T> Clone for ImageHandle<T> {
+    fn clone(&self) -> ImageHandle<T> { *self }
+}
+
+pub struct Images {
+    pub assets: [ImageAsset; MAX_IMAGES],
+    pub count:  usize,
+    // Shared by all images; each one gets texcoord_count of these.
+    pub texcoords: [Texcoords; MAX_TEXCOORDS],
+    pub texcoords_used: usize,
+    // Where each of those frames is on its image's sheet.
+    pub sheet_frames: [SheetFrame; MAX_TEXCOORDS],
+    // Where each of those frames is in the atlases.
+    pub atlas_rects: [AtlasRect; MAX_TEXCOORDS],
+    pub atlases: [Atlas; MAX_ATLASES],
+    pub atlas_count: usize,
+    // Texcoords and sizes of all of the above, for the shaders (see
+    // frame_table.rs).
+    pub frame_table: GLuint,
+    // So images sharing an atlas don't re-bind it (see ImageAsset::set).
+    // Zero whenever something else might have bound a texture.
+    pub bound_texture: GLuint
+}
+
+// One image's line of the manifest.
+#[derive(Copy, Clone)]
+struct ManifestEntry {
+    line:        usize,
+    name:        AssetString,
+    sprite_type: AssetString,
+    frames:      usize,
+    width:       usize,
+    height:      usize,
+    path:        AssetString,
+    // Whether path is sheet metadata or an .aseprite file, which say the
+    // rest.
+    sheet:       bool,
+    // Of the .aseprite file; empty for all visible layers.
+    layer:       AssetString
+}
+
+// The whole manifest, parsed once. GameData keeps one so the game side can
+// find images by file (see Manifest::image) without going back to disk, and
+// the registry loads from the same one.
+pub struct Manifest {
+    entries: [ManifestEntry; MAX_IMAGES],
+    count:   usize
+}
+
+fn asset_string(what: &str, string: &str) -> Result<AssetString, String> {
+    AssetString::new(string).ok_or(format!("{} {} is too long", what, string))
+}
+
+fn parse_line(line_number: usize, line: &str) -> Result<ManifestEntry, String> {
+    let columns: Vec<&str> = line.split(|c: char| c == ' ' || c == '\t')
+        .filter(|column| !column.is_empty()).collect();
+    if columns.len() == 3 || columns.len() == 4 {
+        if columns.len() == 4 && !columns[2].ends_with(".aseprite") {
+            return Err("only .aseprite files take a layer".to_string());
+        }
+        return Ok(ManifestEntry {
+            line: line_number,
+            name: try!(asset_string("name", columns[0])),
+            sprite_type: try!(asset_string("sprite type", columns[1])),
+            frames: 0,
+            width: 0,
+            height: 0,
+            path: try!(asset_string("path", columns[2])),
+            sheet: true,
+            layer: try!(asset_string("layer", if columns.len() == 4 { columns[3] } else { "" }))
+        });
+    }
+    if columns.len() != 5 {
+        return Err("expected `name sprite-type frames WxH path`, `name sprite-type sheet.json` \
+                    or `name sprite-type file.aseprite [layer]`".to_string());
+    }
+
+    let frames = match columns[2].parse::<usize>() {
+        Ok(f) if f > 0 => f,
+        _ => return Err(format!("bad frame count `{}`", columns[2]))
+    };
+    let size: Vec<&str> = columns[3].split('x').collect();
+    let (width, height) = match (size.get(0).map(|w| w.parse::<usize>()), size.get(1).map(|h| h.parse::<usize>())) {
+        (Some(Ok(w)), Some(Ok(h))) if size.len() == 2 => (w, h),
+        _ => return Err(format!("bad frame size `{}` (expected WxH)", columns[3]))
+    };
+
+    Ok(ManifestEntry {
+        line: line_number,
+        name: try!(asset_string("name", columns[0])),
+        sprite_type: try!(asset_string("sprite type", columns[1])),
+        frames: frames,
+        width: width,
+        height: height,
+        path: try!(asset_string("path", columns[4])),
+        sheet: false,
+        layer: AssetString::new("").unwrap()
+    })
+}
+
+impl Manifest {
+    pub fn empty() -> Manifest {
+        Manifest { entries: unsafe { zeroed() }, count: 0 }
+    }
+
+    pub fn read(filename: &str) -> Result<Manifest, String> {
+        let mut contents = String::new();
+        match File::open(filename).and_then(|mut f| f.read_to_string(&mut contents)) {
+            Ok(_) => {}
+            Err(e) => return Err(format!("Couldn't read {}: {}", filename, e))
+        }
+        Manifest::parse(&contents).map_err(|e| format!("{} {}", filename, e))
+    }
+
+    pub fn parse(contents: &str) -> Result<Manifest, String> {
+        let mut manifest = Manifest::empty();
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = match line.find('#') { Some(i) => &line[..i], None => line }.trim();
+            if line.is_empty() { continue }
+
+            let entry = try!(parse_line(line_number + 1, line).map_err(|e| format!("line {}: {}", line_number + 1, e)));
+            if manifest.count == MAX_IMAGES {
+                return Err(format!("line {}: more than {} images", line_number + 1, MAX_IMAGES));
+            }
+            manifest.entries[manifest.count] = entry;
+            manifest.count += 1;
+        }
+        Ok(manifest)
+    }
+
+    fn entries(&self) -> &[ManifestEntry] { &self.entries[..self.count] }
+
+    // Name of the SpriteType1 image drawn from `path`, for things that only
+    // know the file (like Tiled tilesets). Checks that its frames are the
+    // right size and that there are enough of them.
+    pub fn image(&self, path: &str, frame_width: usize, frame_height: usize, frames: usize) -> Result<String, String> {
+        let entry = match self.entries().iter().find(|e| e.path.as_str() == path && e.sprite_type.as_str() == "SpriteType1") {
+            Some(entry) => entry,
+            None => return Err(format!("{} isn't a SpriteType1 image in {}", path, MANIFEST_PATH))
+        };
+        // (Sheets and .aseprite files say their own sizes; trust them.)
+        if !entry.sheet {
+            if entry.width != frame_width || entry.height != frame_height {
+                return Err(format!(
+                    "{} is cut into {}x{} frames in {}, not {}x{}",
+                    entry.name.as_str(), entry.width, entry.height, MANIFEST_PATH, frame_width, frame_height
+                ));
+            }
+            if entry.frames < frames {
+                return Err(format!("{} only has {} frames in {}, not {}", entry.name.as_str(), entry.frames, MANIFEST_PATH, frames));
+            }
+        }
+        Ok(entry.name.as_str().to_string())
+    }
+}
+
+impl Images {
+    pub fn all(&mut self) -> &mut [ImageAsset] { &mut self.assets[..self.count] }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.assets[..self.count].iter().position(|image| image.name.as_str() == name)
+    }
+
+    // Looks up an image by name, making sure it was declared with sprite type T.
+    pub fn handle<T: SpriteType>(&self, name: &str) -> Result<ImageHandle<T>, String> {
+        match self.find(name) {
+            Some(index) => {
+                let declared = self.assets[index].sprite_type.as_str();
+                if declared == T::name() {
+                    Ok(ImageHandle { index: index, _sprite: PhantomData })
+                } else {
+                    Err(format!("Image {} is a {}, not a {}", name, declared, T::name()))
+                }
+            }
+            None => Err(format!("No image called {} in {}", name, MANIFEST_PATH))
+        }
+    }
+
+    pub fn get<T>(&mut self, handle: ImageHandle<T>) -> &mut ImageAsset {
+        &mut self.assets[handle.index]
+    }
+
+    // Loads every image in the manifest. Only for first load -- the registry
+    // keeps living in GLData across reloads.
+    pub unsafe fn load_manifest(&mut self, manifest: &Manifest, gl_data: *const GLData) -> Result<(), String> {
+        self.count = 0;
+        self.texcoords_used = 0;
+        self.atlas_count = 0;
+        self.frame_table = 0;
+        self.bound_texture = 0;
+
+        for &entry in manifest.entries().iter() {
+            try!(self.add(entry, gl_data).map_err(|e| format!("{} line {}: {}", MANIFEST_PATH, entry.line, e)));
+        }
+
+        self.build_atlases()
+    }
+
+    fn add(&mut self, mut entry: ManifestEntry, gl_data: *const GLData) -> Result<(), String> {
+        // Grid frames get cut once we know how wide the sheet is (see
+        // build_atlases). .aseprite files come out as a row of frames.
+        let is_aseprite = entry.path.as_str().ends_with(".aseprite");
+        if is_aseprite {
+            let ase = try!(aseprite::load(entry.path.as_str()));
+            let layer = entry.layer.as_str();
+            if !layer.is_empty() && ase.find_layer(layer).is_none() {
+                return Err(format!("{} has no layer called {}", entry.path.as_str(), layer));
+            }
+            entry.frames = ase.frames.len();
+            entry.width  = ase.width as usize;
+            entry.height = ase.height as usize;
+        }
+        let meta = if entry.sheet && !is_aseprite { Some(try!(sheet::load(entry.path.as_str()))) } else { None };
+        if let Some(ref meta) = meta {
+            entry.frames = meta.frames.len();
+            entry.width  = meta.frames.iter().map(|f| f.width as usize).max().unwrap();
+            entry.height = meta.frames.iter().map(|f| f.height as usize).max().unwrap();
+        }
+
+        if self.count == MAX_IMAGES {
+            return Err(format!("more than {} images", MAX_IMAGES));
+        }
+        if self.find(entry.name.as_str()).is_some() {
+            return Err(format!("{} is declared twice", entry.name.as_str()));
+        }
+        if self.texcoords_used + entry.frames > MAX_TEXCOORDS {
+            return Err(format!("out of texcoord space ({} frames total)", MAX_TEXCOORDS));
+        }
+        let info = match sprite_type_info(entry.sprite_type.as_str()) {
+            Some(info) => info,
+            None => return Err(format!("unknown sprite type {}", entry.sprite_type.as_str()))
+        };
+        let (path, sheet) = match meta {
+            Some(ref meta) => (try!(asset_string("path", &meta.image)), entry.path),
+            None => (entry.path, AssetString::new("").unwrap())
+        };
+
+        unsafe {
+            ptr::write(&mut self.assets[self.count], ImageAsset {
+                gl_data:         gl_data,
+                name:            entry.name,
+                filename:        path,
+                sheet:           sheet,
+                layer:           entry.layer,
+                sprite_type:     AssetString::new(info.name).unwrap(),
+                vbo:             0,
+                instance_capacity: 0,
+                instance_count:    0,
+                set_attributes:  info.set_attributes,
+                shader:          info.shader,
+                attributes_size: info.attributes_size,
+                texture:         zeroed(),
+                frame_width:     entry.width,
+                frame_height:    entry.height,
+                texcoord_count:  entry.frames,
+                first_texcoord:  self.texcoords_used,
+                texcoords:       &mut self.texcoords[self.texcoords_used]
+            });
+        }
+        if let Some(ref meta) = meta {
+            for (i, frame) in meta.frames.iter().enumerate() {
+                self.sheet_frames[self.texcoords_used + i] = *frame;
+            }
+        }
+        self.count += 1;
+        self.texcoords_used += entry.frames;
+        Ok(())
+    }
+
+    // The function pointers in each ImageAsset point into the game lib, so
+    // they go stale on every reload.
+    pub fn relink(&mut self) {
+        for image in self.all().iter_mut() {
+            match sprite_type_info(image.sprite_type.as_str()) {
+                Some(info) => {
+                    image.set_attributes = info.set_attributes;
+                    image.shader         = info.shader;
+                }
+                None => panic!(
+                    "Sprite type {} (used by {}) doesn't exist anymore!",
+                    image.sprite_type.as_str(), image.name.as_str()
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use json;
+use json::Json;
+
+// Where each frame of an image is on its sheet. Plain sheets are a grid of
+// same-sized frames (see grid); anything fancier comes with a JSON file from
+// Aseprite or TexturePacker (the "hash" or "array" kind), which is how
+// trimmed and mixed-size frames get to share a sheet:
+//
+//     { "frames": {
+//         "body 0": { "frame": {"x":0,"y":0,"w":40,"h":52},
+//                     "spriteSourceSize": {"x":25,"y":30,"w":40,"h":52},
+//                     "sourceSize": {"w":90,"h":90},
+//                     "pivot": {"x":0.5,"y":0.5} },
+//         ...
+//       },
+//       "meta": { "image": "body.png" } }
+//
+// Frames are taken in the order they're listed. spriteSourceSize/sourceSize
+// (trimming) and pivot are optional; without them a frame is drawn centered
+// on the sprite's position, like grid frames are.
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SheetFrame {
+    // On the sheet, in pixels from the top left.
+    pub x:      i32,
+    pub y:      i32,
+    pub width:  i32,
+    pub height: i32,
+    // From the pivot to the middle of this frame, in pixels, y up. This is
+    // what puts trimmed frames back where they were before trimming.
+    pub offset_x: f32,
+    pub offset_y: f32
+}
+
+impl SheetFrame {
+    pub fn fits(&self, sheet_width: i32, sheet_height: i32) -> bool {
+        self.x >= 0 && self.y >= 0 &&
+        self.x + self.width <= sheet_width && self.y + self.height <= sheet_height
+    }
+}
+
+pub struct SheetMeta {
+    // The sheet image, relative to the working directory like everything
+    // else in the manifest.
+    pub image:  String,
+    pub frames: Vec<SheetFrame>
+}
+
+// `count` frames in rows from the top, left to right.
+pub fn grid(count: usize, frame_width: usize, frame_height: usize, sheet_width: i32) -> Vec<SheetFrame> {
+    let mut columns = sheet_width as usize / frame_width;
+    // Doesn't fit at all; SheetFrame::fits will say so.
+    if columns == 0 { columns = 1; }
+
+    (0..count).map(|frame| SheetFrame {
+        x:      ((frame % columns) * frame_width) as i32,
+        y:      ((frame / columns) * frame_height) as i32,
+        width:  frame_width as i32,
+        height: frame_height as i32,
+        offset_x: 0.0,
+        offset_y: 0.0
+    }).collect()
+}
+
+pub fn load(filename: &str) -> Result<SheetMeta, String> {
+    let mut contents = String::new();
+    match File::open(filename).and_then(|mut f| f.read_to_string(&mut contents)) {
+        Ok(_) => {}
+        Err(e) => return Err(format!("Couldn't read {}: {}", filename, e))
+    }
+    parse(filename, &contents).map_err(|e| format!("{}: {}", filename, e))
+}
+
+pub fn parse(filename: &str, text: &str) -> Result<SheetMeta, String> {
+    let root = try!(json::parse(text));
+
+    let image = match root.get("meta").and_then(|m| m.get("image")).and_then(|i| i.as_str()) {
+        Some(image) => image,
+        None => return Err("no meta.image".to_string())
+    };
+    // Exporters write the image path relative to the JSON.
+    let image = match Path::new(filename).parent().and_then(|dir| dir.to_str()) {
+        Some(dir) if dir != "" => format!("{}/{}", dir, image),
+        _ => image.to_string()
+    };
+
+    let frame_values: Vec<&Json> = match root.get("frames") {
+        Some(&Json::Object(ref pairs)) => pairs.iter().map(|p| &p.1).collect(),
+        Some(&Json::Array(ref values)) => values.iter().collect(),
+        _ => return Err("no frames".to_string())
+    };
+    if frame_values.is_empty() {
+        return Err("no frames".to_string());
+    }
+
+    let mut frames = Vec::with_capacity(frame_values.len());
+    for (i, value) in frame_values.iter().enumerate() {
+        frames.push(try!(parse_frame(value).map_err(|e| format!("frame {}: {}", i, e))));
+    }
+    Ok(SheetMeta { image: image, frames: frames })
+}
+
+fn parse_frame(value: &Json) -> Result<SheetFrame, String> {
+    if value.get("rotated").and_then(|r| r.as_bool()) == Some(true) {
+        return Err("rotated frames aren't supported (turn rotation off in the exporter)".to_string());
+    }
+    let rect = try!(value.get("frame").ok_or("no \"frame\" rect".to_string()));
+    let (x, y)          = (try!(rect.number("x")), try!(rect.number("y")));
+    let (width, height) = (try!(rect.number("w")), try!(rect.number("h")));
+
+    // Where the trimmed frame was on the untrimmed one.
+    let (trim_x, trim_y) = match value.get("spriteSourceSize") {
+        Some(source) => (try!(source.number("x")), try!(source.number("y"))),
+        None => (0.0, 0.0)
+    };
+    let (source_width, source_height) = match value.get("sourceSize") {
+        Some(size) => (try!(size.number("w")), try!(size.number("h"))),
+        None => (width, height)
+    };
+    // From the top left, as a fraction of the untrimmed size.
+    let (pivot_x, pivot_y) = match value.get("pivot") {
+        Some(pivot) => (try!(pivot.number("x")), try!(pivot.number("y"))),
+        None => (0.5, 0.5)
+    };
+
+    Ok(SheetFrame {
+        x: x as i32, y: y as i32, width: width as i32, height: height as i32,
+        offset_x: (trim_x + width / 2.0 - pivot_x * source_width) as f32,
+        offset_y: (pivot_y * source_height - trim_y - height / 2.0) as f32
+    })
+}
+
+#[test]
+fn trimmed_frames_are_offset_back_into_place() {
+    let meta = parse("assets/crattlecrute/body.json", "{
+        \"frames\": [
+            { \"filename\": \"a\", \"frame\": {\"x\":0,\"y\":0,\"w\":90,\"h\":90} },
+            { \"filename\": \"b\", \"frame\": {\"x\":91,\"y\":0,\"w\":10,\"h\":20},
+              \"spriteSourceSize\": {\"x\":80,\"y\":0,\"w\":10,\"h\":20},
+              \"sourceSize\": {\"w\":90,\"h\":90} }
+        ],
+        \"meta\": { \"image\": \"body.png\" }
+    }").unwrap();
+
+    assert_eq!(meta.image, "assets/crattlecrute/body.png");
+    assert_eq!((meta.frames[0].offset_x, meta.frames[0].offset_y), (0.0, 0.0));
+    // Top right corner of a 90x90 sprite.
+    assert_eq!((meta.frames[1].offset_x, meta.frames[1].offset_y), (40.0, 35.0));
+}
@@ -0,0 +1,211 @@
+use gl::types::*;
+use std::fs::File;
+use std::io::Read;
+use vecmath::Vec2;
+use animation::{Name, MAX_NAME_LEN};
+
+// Per-frame attachment points (where the eye, hat, held item etc. go on each
+// frame of a sprite sheet), read from the .png.points file next to the sheet:
+//
+//     eye frame 1 = 16 21 0.0       -- x y angle, in sheet pixels / radians
+//     hat frame 1 = 12 30 0.1
+//
+// x y are sheet pixels from the middle of the frame, y up. Frames are 1-based
+// like in the .info files, and frames without an entry for a point get (0, 0)
+// and no rotation.
+//
+// asset-dev/export_points.py writes these from asset-dev/crattlecrute.blend.
+// They get re-read when they change on disk (see sim::asset_changed), so a
+// re-export shows up live.
+
+pub const MAX_POINTS: usize = 4;
+pub const MAX_POINT_FRAMES: usize = 64;
+
+#[derive(Copy, Clone)]
+pub struct Attachment {
+    pub pos: Vec2<GLint>,
+    pub angle: GLfloat
+}
+
+impl Attachment {
+    pub fn none() -> Attachment { Attachment { pos: Vec2::s(0), angle: 0.0 } }
+}
+
+#[derive(Copy)]
+pub struct AttachmentPoints {
+    pub names:  [Name; MAX_POINTS],
+    pub frames: [[Attachment; MAX_POINT_FRAMES]; MAX_POINTS],
+    pub count:  usize,
+    // Hash of the file these came from, to tell when it changes.
+    pub source_hash: u64
+}
+
+impl Clone for AttachmentPoints {
+    fn clone(&self) -> AttachmentPoints { *self }
+}
+
+fn hash(contents: &str) -> u64 {
+    // FNV-1a
+    let mut h = 0xcbf29ce484222325u64;
+    for &b in contents.as_bytes().iter() {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h
+}
+
+fn read(filename: &str) -> Result<String, String> {
+    let mut contents = String::new();
+    match File::open(filename).and_then(|mut f| f.read_to_string(&mut contents)) {
+        Ok(_) => Ok(contents),
+        Err(e) => Err(format!("Couldn't read {}: {}", filename, e))
+    }
+}
+
+impl AttachmentPoints {
+    pub fn empty() -> AttachmentPoints {
+        AttachmentPoints {
+            names:  [Name::empty(); MAX_POINTS],
+            frames: [[Attachment::none(); MAX_POINT_FRAMES]; MAX_POINTS],
+            count:  0,
+            source_hash: 0
+        }
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.names[..self.count].iter().position(|n| n.is(name))
+    }
+
+    // The given point on the given (0-based) sheet frame.
+    pub fn get(&self, point: usize, frame: GLint) -> Attachment {
+        if point >= self.count || frame < 0 || frame as usize >= MAX_POINT_FRAMES {
+            return Attachment::none();
+        }
+        self.frames[point][frame as usize]
+    }
+
+    pub fn load(filename: &str) -> Result<AttachmentPoints, String> {
+        let contents = try!(read(filename));
+        AttachmentPoints::parse(&contents).map_err(|e| format!("{}: {}", filename, e))
+    }
+
+    // Re-reads the file and swaps in the new points if it changed. Keeps the
+    // old points if the new file doesn't parse.
+    pub fn reload_if_changed(&mut self, filename: &str) {
+        let contents = match read(filename) {
+            Ok(c) => c,
+            Err(_) => return
+        };
+        if hash(&contents) == self.source_hash { return }
+
+        match AttachmentPoints::parse(&contents) {
+            Ok(points) => {
+                println!("Reloaded attachment points from {}", filename);
+                *self = points;
+            }
+            Err(e) => {
+                println!("{}: {} -- keeping the old attachment points.", filename, e);
+                // Don't complain again until it changes.
+                self.source_hash = hash(&contents);
+            }
+        }
+    }
+
+    pub fn parse(contents: &str) -> Result<AttachmentPoints, String> {
+        let mut points = AttachmentPoints::empty();
+        points.source_hash = hash(contents);
+
+        for (line_number, line) in contents.lines().enumerate() {
+            let line_number = line_number + 1;
+            let line = line.trim();
+            if line.is_empty() { continue }
+
+            let mut sides = line.splitn(2, '=');
+            let key: Vec<&str> = sides.next().unwrap()
+                .split(' ').filter(|word| !word.is_empty()).collect();
+            let values: Vec<&str> = match sides.next() {
+                Some(v) => v.split(' ').filter(|word| !word.is_empty()).collect(),
+                None => return Err(format!("line {}: expected `=`", line_number))
+            };
+
+            if key.len() != 3 || key[1] != "frame" {
+                return Err(format!("line {}: expected `<point> frame <n> = x y angle`", line_number));
+            }
+            if values.len() != 3 {
+                return Err(format!("line {}: expected `x y angle` after `=`", line_number));
+            }
+
+            let frame = match key[2].parse::<usize>() {
+                Ok(n) if n >= 1 && n <= MAX_POINT_FRAMES => n - 1,
+                _ => return Err(format!("line {}: bad frame number `{}`", line_number, key[2]))
+            };
+            let attachment = match (values[0].parse::<GLint>(), values[1].parse::<GLint>(), values[2].parse::<GLfloat>()) {
+                (Ok(x), Ok(y), Ok(angle)) => Attachment { pos: Vec2::new(x, y), angle: angle },
+                _ => return Err(format!("line {}: bad values `{:?}`", line_number, values))
+            };
+
+            let point = match points.find(key[0]) {
+                Some(p) => p,
+                None => {
+                    if points.count == MAX_POINTS {
+                        return Err(format!("more than {} attachment points", MAX_POINTS));
+                    }
+                    if key[0].len() > MAX_NAME_LEN {
+                        return Err(format!("line {}: point name {} is too long", line_number, key[0]));
+                    }
+                    points.names[points.count] = Name::new(key[0]);
+                    points.count += 1;
+                    points.count - 1
+                }
+            };
+            points.frames[point][frame] = attachment;
+        }
+
+        Ok(points)
+    }
+}
+
+#[cfg(test)]
+fn pos(points: &AttachmentPoints, point: usize, frame: GLint) -> (GLint, GLint) {
+    let attachment = points.get(point, frame);
+    (attachment.pos.x, attachment.pos.y)
+}
+
+#[test]
+fn points_parse_by_name_and_frame() {
+    let points = AttachmentPoints::parse("eye frame 1 = 16 21 0.0\n\nhat frame 2 = -3 30 0.5\neye frame 2 = 17 20 -0.25\n").unwrap();
+    let eye = points.find("eye").unwrap();
+    let hat = points.find("hat").unwrap();
+    assert_eq!(points.count, 2);
+    assert_eq!(pos(&points, eye, 1), (17, 20));
+    assert_eq!(points.get(eye, 1).angle, -0.25);
+    assert_eq!(pos(&points, hat, 1), (-3, 30));
+    // Frames (and points) with nothing given are left alone.
+    assert_eq!(pos(&points, hat, 0), (0, 0));
+    assert_eq!(pos(&points, 5, 0), (0, 0));
+
+    assert!(AttachmentPoints::parse("eye frame 0 = 1 2 0.0").is_err());
+    assert!(AttachmentPoints::parse("eye frame 1 = 1 2").is_err());
+    assert!(AttachmentPoints::parse("eye 1 = 1 2 0.0").is_err());
+}
+
+#[test]
+fn reloading_keeps_the_old_points_when_the_new_ones_are_broken() {
+    use std::fs;
+    use std::io::Write;
+
+    let path = ::std::env::temp_dir().join("af-attachment-reload.png.points");
+    let path = path.to_str().unwrap();
+    let write = |contents: &str| File::create(path).and_then(|mut f| f.write_all(contents.as_bytes())).unwrap();
+
+    write("eye frame 1 = 1 2 0.0");
+    let mut points = AttachmentPoints::load(path).unwrap();
+    write("eye frame 1 = 3 4 0.0");
+    points.reload_if_changed(path);
+    assert_eq!(pos(&points, 0, 0), (3, 4));
+
+    write("eye frame 1 = oops");
+    points.reload_if_changed(path);
+    assert_eq!(pos(&points, 0, 0), (3, 4));
+    let _ = fs::remove_file(path);
+}
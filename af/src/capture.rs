@@ -0,0 +1,452 @@
+extern crate gl;
+extern crate glfw;
+extern crate libc;
+
+use gl::types::*;
+use std::ffi::CString;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::mem::{transmute, zeroed};
+use std::ptr;
+use std::slice;
+use libc::{c_char, c_int};
+use glfw::Context;
+use render::GLData;
+use render_queue::RenderQueue;
+use memory::Arena;
+use assets::Sprites;
+use assets::registry::{Manifest, MANIFEST_PATH};
+use gl_debug;
+use GameData;
+
+extern "C" {
+    fn stbi_load(
+        filename: *const c_char,
+        x: *mut c_int,
+        y: *mut c_int,
+        components: *mut c_int,
+        force_components: c_int
+    ) -> *const u8;
+
+    fn stbi_image_free(ptr: *const u8);
+}
+
+// RGBA8 pixels, top row first.
+pub struct Image {
+    pub width:  usize,
+    pub height: usize,
+    pub pixels: Vec<u8>
+}
+
+// An offscreen color target to render into instead of the window.
+pub struct Framebuffer {
+    pub fbo:     GLuint,
+    pub texture: GLuint,
+    pub width:   i32,
+    pub height:  i32
+}
+
+impl Framebuffer {
+    pub unsafe fn new(width: i32, height: i32) -> Framebuffer {
+        let mut framebuffer = Framebuffer { fbo: 0, texture: 0, width: width, height: height };
+
+        gl::GenTextures(1, &mut framebuffer.texture);
+        gl::BindTexture(gl::TEXTURE_2D, framebuffer.texture);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+        gl::TexImage2D(
+            gl::TEXTURE_2D, 0, gl::RGBA as i32,
+            width, height, 0, gl::RGBA,
+            gl::UNSIGNED_BYTE, ptr::null()
+        );
+
+        gl::GenFramebuffers(1, &mut framebuffer.fbo);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer.fbo);
+        gl::FramebufferTexture2D(
+            gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0,
+            gl::TEXTURE_2D, framebuffer.texture, 0
+        );
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            panic!("Offscreen framebuffer incomplete! Status: {:X}", status);
+        }
+        framebuffer
+    }
+
+    // Reads back whatever was drawn into this framebuffer.
+    pub unsafe fn read(&self) -> Image {
+        let width  = self.width as usize;
+        let height = self.height as usize;
+        let mut pixels = vec![0u8; width * height * 4];
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(
+            0, 0, self.width, self.height,
+            gl::RGBA, gl::UNSIGNED_BYTE, transmute(&mut pixels[0])
+        );
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        // GL hands rows back bottom first.
+        let row = width * 4;
+        let mut flipped = Vec::with_capacity(pixels.len());
+        for y in (0..height).rev() {
+            flipped.extend(pixels[y * row..(y + 1) * row].iter().cloned());
+        }
+
+        Image { width: width, height: height, pixels: flipped }
+    }
+
+    pub unsafe fn unload(&mut self) {
+        gl::DeleteFramebuffers(1, &self.fbo);
+        gl::DeleteTextures(1, &self.texture);
+    }
+}
+
+// Runs `draw` with a `width` x `height` offscreen buffer bound and reads the
+// result back. Leaves the viewport and screen size uniforms the way it found
+// them. `draw` is responsible for clearing.
+pub unsafe fn capture<F>(gl_data: &mut GLData, width: i32, height: i32, draw: F) -> Image
+    where F: FnOnce(&mut GLData)
+{
+    let mut viewport = [0 as GLint; 4];
+    gl::GetIntegerv(gl::VIEWPORT, &mut viewport[0]);
+
+    let mut framebuffer = Framebuffer::new(width, height);
+    gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer.fbo);
+    gl::Viewport(0, 0, width, height);
+    gl_data.shaders.each_shader(|shader, _name| {
+        gl::UseProgram(shader.program);
+        gl::Uniform2f(shader.screen_size_uniform, width as GLfloat, height as GLfloat);
+    });
+
+    draw(gl_data);
+    gl::Finish();
+
+    let image = framebuffer.read();
+    framebuffer.unload();
+
+    gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+    gl_data.shaders.each_shader(|shader, _name| {
+        gl::UseProgram(shader.program);
+        gl::Uniform2f(shader.screen_size_uniform, viewport[2] as GLfloat, viewport[3] as GLfloat);
+    });
+
+    image
+}
+
+// A whole game frame, as of the latest simulation step, unless drawing it
+// ran into a GL error.
+pub unsafe fn capture_frame(game: &mut GameData, gl_data: &mut GLData, width: i32, height: i32) -> Result<Image, GLenum> {
+    let mut result = Ok(());
+    let image = capture(gl_data, width, height, |gl_data| result = ::render(game, gl_data, None, 1.0));
+    result.map(|_| image)
+}
+
+impl Image {
+    pub fn load(filename: &str) -> Option<Image> {
+        let mut width = 0; let mut height = 0; let mut comp = 0;
+        unsafe {
+            let cfilename = CString::new(filename.to_string()).unwrap();
+            let img = stbi_load(cfilename.as_ptr(), &mut width, &mut height, &mut comp, 4);
+            if img.is_null() { return None }
+
+            let len = (width * height * 4) as usize;
+            let pixels = slice::from_raw_parts(img, len).to_vec();
+            stbi_image_free(img);
+
+            Some(Image { width: width as usize, height: height as usize, pixels: pixels })
+        }
+    }
+
+    // Number of pixels where any channel differs by more than `tolerance`.
+    // Images of different sizes differ in every pixel.
+    pub fn diff(&self, other: &Image, tolerance: u8) -> usize {
+        if self.width != other.width || self.height != other.height {
+            return self.width * self.height;
+        }
+
+        self.pixels.chunks(4).zip(other.pixels.chunks(4)).filter(|&(a, b)| {
+            a.iter().zip(b.iter()).any(|(&x, &y)| {
+                let d = if x > y { x - y } else { y - x };
+                d > tolerance
+            })
+        }).count()
+    }
+
+    // Uncompressed (stored deflate blocks) PNG. Big, but we don't need
+    // anything fancier for test output.
+    pub fn write_png(&self, filename: &str) -> io::Result<()> {
+        let mut raw = Vec::with_capacity((self.width * 4 + 1) * self.height);
+        for row in self.pixels.chunks(self.width * 4) {
+            raw.push(0); // filter type: none
+            raw.extend(row.iter().cloned());
+        }
+
+        let mut header = Vec::with_capacity(13);
+        push_u32_be(&mut header, self.width as u32);
+        push_u32_be(&mut header, self.height as u32);
+        header.extend([8, 6, 0, 0, 0].iter().cloned()); // 8 bit RGBA
+
+        let mut file = try!(File::create(filename));
+        try!(file.write_all(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]));
+        try!(write_chunk(&mut file, b"IHDR", &header));
+        try!(write_chunk(&mut file, b"IDAT", &zlib_stored(&raw)));
+        write_chunk(&mut file, b"IEND", &[])
+    }
+}
+
+// Compares a capture against assets/golden/<name>.png. Failed comparisons get
+// written next to it as <name>.actual.png. A missing golden image is a
+// failure too -- run with AF_UPDATE_GOLDEN set to (re)write them from the
+// captures instead of comparing.
+pub fn check_golden(name: &str, image: &Image, tolerance: u8) -> Result<(), String> {
+    let golden_path = format!("assets/golden/{}.png", name);
+    let actual_path = format!("assets/golden/{}.actual.png", name);
+
+    if ::std::env::var("AF_UPDATE_GOLDEN").is_ok() {
+        let _ = fs::create_dir_all("assets/golden");
+        println!("Writing new golden image {}", golden_path);
+        return image.write_png(&golden_path).map_err(|e| format!("Couldn't write {}: {}", golden_path, e));
+    }
+
+    match Image::load(&golden_path) {
+        None => {
+            let _ = image.write_png(&actual_path);
+            Err(format!(
+                "No golden image {} (see {}, and set AF_UPDATE_GOLDEN to accept it)",
+                golden_path, actual_path
+            ))
+        }
+
+        Some(golden) => match image.diff(&golden, tolerance) {
+            0 => Ok(()),
+            different => {
+                let _ = image.write_png(&actual_path);
+                Err(format!(
+                    "{} pixels differ from {} (see {})",
+                    different, golden_path, actual_path
+                ))
+            }
+        }
+    }
+}
+
+// A hidden window's GL context with the game's shaders and images loaded,
+// for drawing without the host. It still needs an X server, but not a GPU --
+// golden-tests.sh runs the tests below under Xvfb with Mesa's llvmpipe.
+pub struct Offscreen {
+    pub gl_data: Box<GLData>,
+    // Backs the render queue, like GameData::scratch does in the game.
+    scratch: Vec<u8>,
+    _window: glfw::Window,
+    _glfw:   glfw::Glfw
+}
+
+impl Offscreen {
+    pub unsafe fn new() -> Offscreen {
+        // Assets are relative to the repo root, and tests run from af/.
+        if fs::metadata(MANIFEST_PATH).is_err() {
+            let _ = ::std::env::set_current_dir("..");
+        }
+
+        let mut glfw = glfw::init(glfw::FAIL_ON_ERRORS).unwrap();
+        glfw.window_hint(glfw::WindowHint::ContextVersion(3, 3));
+        glfw.window_hint(glfw::WindowHint::OpenglProfile(glfw::OpenGlProfileHint::Core));
+        glfw.window_hint(glfw::WindowHint::OpenglForwardCompat(true));
+        glfw.window_hint(glfw::WindowHint::Visible(false));
+        let (mut window, _events) = glfw
+            .create_window(64, 64, "offscreen", glfw::WindowMode::Windowed)
+            .expect("Couldn't create a GL context (is there an X server?)");
+        window.make_current();
+        gl::load_with(|s| window.get_proc_address(s));
+
+        let mut gl_data: Box<GLData> = Box::new(zeroed());
+        gl_data.debug.install(gl_debug::Mode::Panic);
+        let manifest = match Manifest::read(MANIFEST_PATH) {
+            Ok(manifest) => manifest,
+            Err(e) => panic!("Couldn't load images: {}", e)
+        };
+        ::init_gl(&mut gl_data, &window, &manifest);
+
+        Offscreen { gl_data: gl_data, scratch: vec![0u8; 64 * 1024], _window: window, _glfw: glfw }
+    }
+
+    // Captures whatever gets submitted to `queue` in `draw`, on a clear
+    // background, with the camera at (0, 0).
+    pub unsafe fn draw<F>(&mut self, width: i32, height: i32, draw: F) -> Result<Image, GLenum>
+        where F: FnOnce(&mut RenderQueue, &Sprites)
+    {
+        let mut result = Ok(());
+        let mut arena = Arena::new(&mut self.scratch[0], self.scratch.len());
+        let image = capture(&mut self.gl_data, width, height, |gl_data| {
+            gl_data.shaders.each_shader(|shader, _name| {
+                gl::UseProgram(shader.program);
+                gl::Uniform2f(shader.cam_pos_uniform, 0.0, 0.0);
+            });
+            gl::ClearColor(0.0, 0.0, 0.0, 0.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+
+            let mut queue = RenderQueue::new(&mut arena);
+            draw(&mut queue, &gl_data.sprites);
+            result = queue.draw(&mut gl_data.images, &mut gl_data.debug);
+        });
+        result.map(|_| image)
+    }
+}
+
+fn push_u32_be(out: &mut Vec<u8>, value: u32) {
+    out.push((value >> 24) as u8);
+    out.push((value >> 16) as u8);
+    out.push((value >> 8)  as u8);
+    out.push(value as u8);
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in bytes.iter() {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { 0xEDB88320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+    for &byte in bytes.iter() {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 65535 * 5 + 16);
+    out.push(0x78); out.push(0x01);
+
+    let blocks: Vec<&[u8]> = if data.len() == 0 { vec![data] } else { data.chunks(65535).collect() };
+    let last = blocks.len() - 1;
+    for (i, block) in blocks.iter().enumerate() {
+        let len = block.len() as u16;
+        out.push(if i == last { 1 } else { 0 });
+        out.push(len as u8); out.push((len >> 8) as u8);
+        out.push(!len as u8); out.push((!len >> 8) as u8);
+        out.extend(block.iter().cloned());
+    }
+
+    push_u32_be(&mut out, adler32(data));
+    out
+}
+
+fn write_chunk(file: &mut File, kind: &[u8], data: &[u8]) -> io::Result<()> {
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+    push_u32_be(&mut chunk, data.len() as u32);
+    chunk.extend(kind.iter().cloned());
+    chunk.extend(data.iter().cloned());
+    let crc = crc32(&chunk[4..]);
+    push_u32_be(&mut chunk, crc);
+    file.write_all(&chunk)
+}
+
+#[test]
+fn pngs_load_back_the_way_they_were_written() {
+    // Check values from the PNG spec's CRC and a real IEND chunk.
+    assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    assert_eq!(crc32(b"IEND"), 0xAE426082);
+    assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+
+    let mut pixels = Vec::new();
+    for i in 0..5 * 3u8 {
+        let alpha = if i % 2 == 0 { 255 } else { 128 };
+        pixels.extend([i * 10, 255 - i, 7, alpha].iter().cloned());
+    }
+    let image = Image { width: 5, height: 3, pixels: pixels };
+
+    let path = ::std::env::temp_dir().join("af-capture-round-trip.png");
+    let path = path.to_str().unwrap();
+    image.write_png(path).unwrap();
+    let loaded = Image::load(path).unwrap();
+    let _ = fs::remove_file(path);
+
+    assert_eq!((loaded.width, loaded.height), (5, 3));
+    assert_eq!(loaded.pixels, image.pixels);
+}
+
+#[test]
+fn diff_counts_pixels_past_the_tolerance() {
+    let a = Image { width: 2, height: 2, pixels: vec![100; 16] };
+    let mut b = Image { width: 2, height: 2, pixels: vec![100; 16] };
+    b.pixels[1]  = 103; // one channel of the first pixel
+    b.pixels[14] = 90;  // and of the last
+
+    assert_eq!(a.diff(&a, 0), 0);
+    assert_eq!(a.diff(&b, 0), 2);
+    assert_eq!(a.diff(&b, 3), 1);
+    assert_eq!(a.diff(&b, 10), 0);
+    assert_eq!(a.diff(&Image { width: 4, height: 1, pixels: vec![100; 16] }, 255), 4);
+}
+
+// Needs a GL context, so it only runs through golden-tests.sh.
+#[test]
+#[ignore]
+fn sprite_shaders_match_golden_images() {
+    use vecmath::Vec2;
+    use assets::{SpriteType1, SpriteType2Color2, SpriteType3Color1};
+    use render_queue::LAYER_WORLD;
+    use std::f32::consts::PI;
+
+    let mut offscreen = unsafe { Offscreen::new() };
+    let mut failures = Vec::new();
+
+    // Frames and flipping. Pixels are drawn at 2x, so this is 64x32 world
+    // pixels around the camera.
+    let image = unsafe { offscreen.draw(128, 64, |queue, sprites| {
+        queue.submit(sprites.dirt_1, LAYER_WORLD, 0.0, SpriteType1 {
+            position: Vec2::new(-24.0, -8.0), frame: 0, flipped: false as GLint
+        });
+        queue.submit(sprites.dirt_1, LAYER_WORLD, 0.0, SpriteType1 {
+            position: Vec2::new(8.0, -8.0), frame: 0, flipped: true as GLint
+        });
+    })}.unwrap();
+    failures.extend(check_golden("sprite_type_1", &image, 2).err());
+
+    // Both color swaps on one, flipped and rotated about its middle on another.
+    let image = unsafe { offscreen.draw(384, 256, |queue, sprites| {
+        queue.submit(sprites.crattlecrute_body, LAYER_WORLD, 0.0, SpriteType2Color2 {
+            position: Vec2::new(-48.0, 0.0), frame: 0, flipped: false as GLint, angle: 0.0,
+            color_swap_1: Vec2::new(0x0094FFFF, 0x20C020FF),
+            color_swap_2: Vec2::new(0x00C7FFFF, 0xF0E000FF)
+        });
+        queue.submit(sprites.crattlecrute_body, LAYER_WORLD, 0.0, SpriteType2Color2 {
+            position: Vec2::new(48.0, 0.0), frame: 3, flipped: true as GLint, angle: PI / 4.0,
+            color_swap_1: Vec2::new(0, 0),
+            color_swap_2: Vec2::new(0, 0)
+        });
+    })}.unwrap();
+    failures.extend(check_golden("sprite_type_2_color_2", &image, 2).err());
+
+    // Color swap on one, rotated about its focus and flipped on another.
+    let image = unsafe { offscreen.draw(64, 64, |queue, sprites| {
+        queue.submit(sprites.eye_1, LAYER_WORLD, 0.0, SpriteType3Color1 {
+            position: Vec2::new(-8.0, 0.0), frame: 0, flipped: false as GLint, angle: 0.0,
+            focus: Vec2::new(2, 0),
+            color_swap: Vec2::new(0x5900FFFF, 0xFF8000FF)
+        });
+        queue.submit(sprites.eye_1, LAYER_WORLD, 0.0, SpriteType3Color1 {
+            position: Vec2::new(6.0, 0.0), frame: 0, flipped: true as GLint, angle: PI / 2.0,
+            focus: Vec2::new(2, 0),
+            color_swap: Vec2::new(0, 0)
+        });
+    })}.unwrap();
+    failures.extend(check_golden("sprite_type_3_color_1", &image, 2).err());
+
+    for failure in failures.iter() { println!("{}", failure); }
+    assert!(failures.is_empty());
+}
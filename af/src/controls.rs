@@ -17,7 +17,7 @@ impl Control {
 }
 
 pub struct Controls {
-    pub up: Control,
+    pub jump: Control,
     pub down: Control,
     pub left: Control,
     pub right: Control,
@@ -0,0 +1,225 @@
+use gl::types::*;
+use libc::c_void;
+use std::env;
+use std::ffi::CStr;
+use std::fmt;
+use std::mem::transmute;
+use std::slice;
+use assets::registry::AssetString;
+
+// GL error reporting. When the driver has KHR_debug we get a callback for
+// every error as it happens; otherwise gl_check! (which calls glGetError)
+// after each render pass is all we've got. Either way, what happens to an
+// error depends on the Mode, picked with AF_GL_DEBUG=log|collect|panic
+// (log by default).
+//
+// Lives in GLData, so everything is fixed size. The callback is a function
+// in the game lib, so it gets re-installed on every load.
+
+pub const MAX_REPORTS: usize = 16;
+pub const MAX_MESSAGE_LEN: usize = 191;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Mode {
+    Log,     // println and carry on
+    Collect, // keep this frame's errors in GLDebug::reports
+    Panic    // blow up at the first error
+}
+
+impl Mode {
+    pub fn from_env() -> Mode {
+        match env::var("AF_GL_DEBUG") {
+            Ok(ref mode) if *mode == "collect" => Mode::Collect,
+            Ok(ref mode) if *mode == "panic"   => Mode::Panic,
+            Ok(ref mode) if *mode != "log" => {
+                println!("AF_GL_DEBUG={} isn't log, collect or panic -- logging.", mode);
+                Mode::Log
+            }
+            _ => Mode::Log
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Report {
+    // glGetError's code, or the debug message's id.
+    pub code:        GLenum,
+    // gl::DEBUG_SEVERITY_*, or 0 for glGetError.
+    pub severity:    GLenum,
+    // file:line of the gl_check! that caught it. Empty for debug messages,
+    // which come in while the offending call is still running.
+    pub call_site:   AssetString,
+    pub sprite_type: AssetString,
+    message_len: u8,
+    message: [u8; MAX_MESSAGE_LEN]
+}
+
+impl Report {
+    fn new(code: GLenum, severity: GLenum, call_site: &str, sprite_type: AssetString, message: &str) -> Report {
+        let mut report = Report {
+            code: code,
+            severity: severity,
+            call_site: AssetString::truncated(call_site),
+            sprite_type: sprite_type,
+            message_len: 0,
+            message: [0; MAX_MESSAGE_LEN]
+        };
+        let mut len = if message.len() > MAX_MESSAGE_LEN { MAX_MESSAGE_LEN } else { message.len() };
+        while !message.is_char_boundary(len) { len -= 1; }
+        for (i, &b) in message.as_bytes()[..len].iter().enumerate() { report.message[i] = b; }
+        report.message_len = len as u8;
+        report
+    }
+
+    pub fn message(&self) -> &str {
+        ::std::str::from_utf8(&self.message[..self.message_len as usize]).unwrap()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "GL error 0x{:X}", self.code));
+        if !self.call_site.as_str().is_empty() {
+            try!(write!(f, " at {}", self.call_site.as_str()));
+        }
+        if !self.sprite_type.as_str().is_empty() {
+            try!(write!(f, " (drawing {})", self.sprite_type.as_str()));
+        }
+        write!(f, ": {}", self.message())
+    }
+}
+
+pub struct GLDebug {
+    pub mode: Mode,
+    // Whether the KHR_debug callback is installed.
+    pub debug_output: bool,
+    // What's being drawn right now (see ImageAsset::set).
+    pub sprite_type: AssetString,
+    pub reports: [Report; MAX_REPORTS],
+    pub report_count: usize,
+    // Reports that didn't fit this frame.
+    pub dropped: usize
+}
+
+pub fn error_name(code: GLenum) -> &'static str {
+    match code {
+        gl::INVALID_ENUM                  => "Invalid enum",
+        gl::INVALID_VALUE                 => "Invalid value",
+        gl::INVALID_OPERATION             => "Invalid operation",
+        gl::INVALID_FRAMEBUFFER_OPERATION => "Invalid framebuffer operation",
+        gl::OUT_OF_MEMORY                 => "Out of memory",
+        _                                 => "Unknown error"
+    }
+}
+
+extern "system" fn debug_callback(
+    _source:    GLenum,
+    gltype:     GLenum,
+    id:         GLuint,
+    severity:   GLenum,
+    length:     GLsizei,
+    message:    *const GLchar,
+    user_param: *mut c_void
+) {
+    // Notifications are mostly drivers telling us where buffers live.
+    if severity == gl::DEBUG_SEVERITY_NOTIFICATION { return }
+    if gltype != gl::DEBUG_TYPE_ERROR && gltype != gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR { return }
+
+    unsafe {
+        let debug: &mut GLDebug = transmute(user_param);
+        let message =
+            if length >= 0 {
+                String::from_utf8_lossy(slice::from_raw_parts(message as *const u8, length as usize)).into_owned()
+            } else {
+                CStr::from_ptr(message).to_string_lossy().into_owned()
+            };
+        let sprite_type = debug.sprite_type;
+        // Can't unwind through the driver, so Panic mode waits for the next
+        // gl_check! to actually panic.
+        debug.report(Report::new(id, severity, "", sprite_type, &message), false);
+    }
+}
+
+impl GLDebug {
+    // Call on every load: the callback lives in the game lib.
+    pub unsafe fn install(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.sprite_type = AssetString::truncated("");
+        self.report_count = 0;
+        self.dropped = 0;
+
+        self.debug_output = gl::DebugMessageCallback::is_loaded();
+        if self.debug_output {
+            gl::Enable(gl::DEBUG_OUTPUT);
+            // So the callback runs before the offending call returns, and
+            // sprite_type is still right.
+            gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+            gl::DebugMessageCallback(debug_callback, transmute(self as *mut GLDebug));
+        }
+        else {
+            println!("No KHR_debug -- GL errors only get caught by gl_check!.");
+        }
+        // Don't blame the first pass for anything that happened before now.
+        while gl::GetError() != gl::NO_ERROR {}
+    }
+
+    // Forgets last frame's reports.
+    pub fn begin_frame(&mut self) {
+        self.report_count = 0;
+        self.dropped = 0;
+        self.sprite_type = AssetString::truncated("");
+    }
+
+    pub fn set_sprite_type(&mut self, sprite_type: AssetString) {
+        self.sprite_type = sprite_type;
+    }
+
+    pub fn reports(&self) -> &[Report] { &self.reports[..self.report_count] }
+
+    fn report(&mut self, report: Report, can_panic: bool) {
+        match self.mode {
+            Mode::Log => println!("{}", report),
+            Mode::Panic if can_panic => panic!("{}", report),
+            _ => {}
+        }
+        if self.report_count < MAX_REPORTS {
+            self.reports[self.report_count] = report;
+            self.report_count += 1;
+        }
+        else {
+            self.dropped += 1;
+        }
+    }
+
+    // Drains glGetError. Use gl_check! instead, which fills in call_site.
+    pub fn check(&mut self, call_site: &str) -> Result<(), GLenum> {
+        let mut first_error = None;
+        loop {
+            let code = unsafe { gl::GetError() };
+            if code == gl::NO_ERROR { break }
+            if first_error.is_none() { first_error = Some(code); }
+
+            // With debug output on, the callback already reported this one.
+            if !self.debug_output {
+                let sprite_type = self.sprite_type;
+                self.report(Report::new(code, 0, call_site, sprite_type, error_name(code)), true);
+            }
+        }
+
+        if self.mode == Mode::Panic && self.report_count > 0 {
+            panic!("{}", self.reports[0]);
+        }
+        match first_error {
+            Some(code) => Err(code),
+            None => Ok(())
+        }
+    }
+}
+
+// Checks for GL errors since the last check, reporting them with this call
+// site. Evaluates to Result<(), GLenum>.
+#[macro_export]
+macro_rules! gl_check {
+    ($debug:expr) => ($debug.check(concat!(file!(), ":", line!())))
+}
+
@@ -0,0 +1,232 @@
+extern crate glfw;
+
+use glfw::{Action, Key};
+use std::mem::transmute;
+use memory::GameMemory;
+use assets::registry::{Manifest, MANIFEST_PATH};
+use {GameData, game_data_from, attach_memory, sim};
+
+// Drives the simulation without a window or GL context, for running gameplay
+// code on machines without a GPU (CI, mostly).
+//
+//     let mut game = Headless::new();
+//     game.run(60, 1.0 / 60.0, &[Scripted::press(0, Key::D), Scripted::release(30, Key::D)]);
+//     assert!(game.data().player.position.x > 0.0);
+
+pub static PERMANENT_SIZE: usize = 1024 * 1024;
+pub static TRANSIENT_SIZE: usize = 256 * 1024;
+
+// A key event to feed in at the start of a given frame.
+pub struct Scripted {
+    pub frame:  usize,
+    pub key:    Key,
+    pub action: Action
+}
+
+impl Scripted {
+    pub fn press(frame: usize, key: Key) -> Scripted {
+        Scripted { frame: frame, key: key, action: Action::Press }
+    }
+    pub fn release(frame: usize, key: Key) -> Scripted {
+        Scripted { frame: frame, key: key, action: Action::Release }
+    }
+}
+
+pub struct Headless {
+    // Backing storage for `memory` -- never touched directly.
+    _permanent: Vec<u8>,
+    _transient: Vec<u8>,
+    memory: GameMemory,
+    // Frames run so far. Scripted frame numbers are relative to this.
+    pub frame: usize
+}
+
+impl Headless {
+    pub fn new() -> Headless {
+        let mut permanent = vec![0u8; PERMANENT_SIZE];
+        let mut transient = vec![0u8; TRANSIENT_SIZE];
+        let memory = unsafe {
+            GameMemory {
+                permanent:      transmute(&mut permanent[0]),
+                permanent_size: permanent.len(),
+                transient:      transmute(&mut transient[0]),
+                transient_size: transient.len()
+            }
+        };
+
+        let mut headless = Headless {
+            _permanent: permanent,
+            _transient: transient,
+            memory: memory,
+            frame: 0
+        };
+        unsafe {
+            let game = game_data_from(&headless.memory);
+            attach_memory(game, &headless.memory, true);
+            // Only Tiled maps need this, so going without is fine (tests run
+            // from af/, where there's no manifest to read).
+            game.manifest = Manifest::read(MANIFEST_PATH).unwrap_or(Manifest::empty());
+            sim::tune(game);
+            sim::init_game(game);
+        }
+        headless
+    }
+
+    pub fn data(&mut self) -> &mut GameData {
+        unsafe { game_data_from(&self.memory) }
+    }
+
+    pub fn key(&mut self, key: Key, action: Action) {
+        sim::apply_key(&mut self.data().controls, key, action);
+    }
+
+    // Runs `frames` frames of `delta_t` seconds each, feeding in any scripted
+    // events whose frame comes up.
+    pub fn run(&mut self, frames: usize, delta_t: f32, script: &[Scripted]) {
+        let start = self.frame;
+        for frame in start..start + frames {
+            for event in script.iter().filter(|e| e.frame == frame - start) {
+                self.key(event.key, event.action);
+            }
+
+            {
+                let game = self.data();
+                game.scratch.reset();
+                sim::advance(game, delta_t);
+            }
+
+            self.frame += 1;
+        }
+    }
+}
+
+#[test]
+fn walking_right_moves_the_player_right() {
+    let mut game = Headless::new();
+    let start_x = game.data().player.position.x;
+
+    game.run(30, 1.0 / 60.0, &[Scripted::press(0, Key::D)]);
+
+    assert!(game.data().player.position.x > start_x);
+    assert!(!game.data().player.flipped);
+}
+
+#[test]
+fn frame_rate_does_not_change_the_outcome() {
+    let mut fast = Headless::new();
+    let mut slow = Headless::new();
+    // Powers of two keep the float math exact.
+    fast.data().sim_hz = 64.0;
+    slow.data().sim_hz = 64.0;
+
+    fast.run(64, 1.0 / 128.0, &[Scripted::press(0, Key::A)]);
+    slow.run(16, 1.0 / 32.0,  &[Scripted::press(0, Key::A)]);
+
+    let difference = fast.data().player.position.x - slow.data().player.position.x;
+    assert!(difference.abs() < 0.001);
+}
+
+#[test]
+fn player_lands_on_the_ground() {
+    let mut game = Headless::new();
+    game.run(120, 1.0 / 60.0, &[]);
+
+    let data = game.data();
+    let feet = data.player.position.y + data.crattlecrute_hitbox.y1;
+    assert!(data.player.grounded);
+    assert!((feet - data.tilemap.bounds().y2).abs() < 0.1);
+}
+
+#[test]
+fn letting_go_of_jump_early_cuts_it_short() {
+    let peak = |hold_frames: usize| {
+        let mut game = Headless::new();
+        game.run(60, 1.0 / 60.0, &[]);
+        game.key(Key::Space, Action::Press);
+
+        let mut peak = game.data().player.position.y;
+        for frame in 0..60 {
+            if frame == hold_frames { game.key(Key::Space, Action::Release); }
+            game.run(1, 1.0 / 60.0, &[]);
+            let y = game.data().player.position.y;
+            if y > peak { peak = y; }
+        }
+        peak
+    };
+    assert!(peak(30) > peak(3) + 10.0);
+}
+
+#[test]
+fn coyote_time_still_jumps_just_after_walking_off_the_edge() {
+    // Walks right off the end of the ground, waits `late_frames`, then jumps.
+    let jumped = |late_frames: usize| {
+        let mut game = Headless::new();
+        game.run(60, 1.0 / 60.0, &[]);
+        game.key(Key::D, Action::Press);
+        while game.data().player.grounded {
+            game.run(1, 1.0 / 60.0, &[]);
+            assert!(game.frame < 300);
+        }
+
+        game.run(late_frames, 1.0 / 60.0, &[]);
+        game.key(Key::Space, Action::Press);
+        game.run(1, 1.0 / 60.0, &[]);
+        game.data().player.velocity.y > 0.0
+    };
+    // Coyote time is 0.1 seconds.
+    assert!(jumped(3));
+    assert!(!jumped(15));
+}
+
+#[test]
+fn jumping_just_before_landing_jumps_on_landing() {
+    let drop = || {
+        let mut game = Headless::new();
+        game.data().player.position.y = 100.0;
+        game.data().player.save_previous();
+        game
+    };
+    let mut falling = drop();
+    while !falling.data().player.grounded {
+        falling.run(1, 1.0 / 60.0, &[]);
+        assert!(falling.frame < 300);
+    }
+    let landing_frame = falling.frame;
+
+    // Presses jump `early_frames` before landing and holds it.
+    let jumped = |early_frames: usize| {
+        let mut game = drop();
+        game.run(landing_frame - early_frames, 1.0 / 60.0, &[]);
+        game.key(Key::Space, Action::Press);
+        let mut went_up = false;
+        for _ in 0..early_frames + 5 {
+            game.run(1, 1.0 / 60.0, &[]);
+            if game.data().player.velocity.y > 0.0 { went_up = true; }
+        }
+        went_up
+    };
+    // The jump buffer is 0.1 seconds.
+    assert!(jumped(3));
+    assert!(!jumped(15));
+}
+
+#[test]
+fn reloading_the_level_reuses_its_memory() {
+    use level::{self, LevelFile};
+
+    let mut game = Headless::new();
+    let level = LevelFile::parse(level::BUILT_IN).unwrap();
+    let used = game.data().arena.used;
+    for _ in 0..1000 {
+        sim::enter_level(game.data(), &level, true).unwrap();
+    }
+    assert_eq!(game.data().arena.used, used);
+
+    // A level too big for memory is an error, and the old one stays.
+    let mut huge = LevelFile::parse(level::BUILT_IN).unwrap();
+    huge.width  = 100000;
+    huge.height = 100000;
+    assert!(sim::enter_level(game.data(), &huge, true).is_err());
+    assert_eq!(game.data().tilemap.width, level.width);
+    assert!(game.data().tilemap.tiles(0).iter().any(|&id| id != 0));
+}
@@ -0,0 +1,220 @@
+use std::str;
+
+// Just enough JSON to read what other tools export (sprite sheet metadata
+// and the like). Objects keep their keys in file order, since exporters
+// tend to list frames in order as object keys.
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>)
+}
+
+impl Json {
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref pairs) => pairs.iter().find(|p| p.0 == key).map(|p| &p.1),
+            _ => None
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self { Json::Number(n) => Some(n), _ => None }
+    }
+
+    pub fn as_i32(&self) -> Option<i32> { self.as_f64().map(|n| n as i32) }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self { Json::Bool(b) => Some(b), _ => None }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self { Json::String(ref s) => Some(&s[..]), _ => None }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match *self { Json::Array(ref a) => Some(&a[..]), _ => None }
+    }
+
+    pub fn as_object(&self) -> Option<&[(String, Json)]> {
+        match *self { Json::Object(ref o) => Some(&o[..]), _ => None }
+    }
+
+    // get(key) as a number, with an error that says what was missing.
+    pub fn number(&self, key: &str) -> Result<f64, String> {
+        self.get(key).and_then(|v| v.as_f64()).ok_or(format!("expected a number for \"{}\"", key))
+    }
+}
+
+pub fn parse(text: &str) -> Result<Json, String> {
+    let mut parser = Parser { bytes: text.as_bytes(), pos: 0 };
+    let value = try!(parser.value());
+    parser.skip_whitespace();
+    if parser.pos != parser.bytes.len() {
+        return Err(parser.error("trailing characters"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> String {
+        let line = self.bytes[..self.pos].iter().filter(|&&b| b == b'\n').count() + 1;
+        format!("JSON line {}: {}", line, message)
+    }
+
+    fn peek(&self) -> Option<u8> { self.bytes.get(self.pos).cloned() }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b) = self.peek() {
+            if b == b' ' || b == b'\t' || b == b'\n' || b == b'\r' { self.pos += 1 } else { break }
+        }
+    }
+
+    fn expect(&mut self, b: u8) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.peek() == Some(b) { self.pos += 1; Ok(()) }
+        else { Err(self.error(&format!("expected '{}'", b as char))) }
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        if self.bytes[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            Ok(value)
+        }
+        else { Err(self.error("unexpected character")) }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b) if b == b'-' || (b >= b'0' && b <= b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of file"))
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.pos += 1;
+        let mut pairs = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') { self.pos += 1; return Ok(Json::Object(pairs)) }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') { return Err(self.error("expected a key")) }
+            let key = try!(self.string());
+            try!(self.expect(b':'));
+            let value = try!(self.value());
+            pairs.push((key, value));
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => { self.pos += 1; return Ok(Json::Object(pairs)) }
+                _ => return Err(self.error("expected ',' or '}'"))
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.pos += 1;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') { self.pos += 1; return Ok(Json::Array(values)) }
+        loop {
+            values.push(try!(self.value()));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => { self.pos += 1; return Ok(Json::Array(values)) }
+                _ => return Err(self.error("expected ',' or ']'"))
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        while let Some(b) = self.peek() {
+            match b {
+                b'0'...b'9' | b'-' | b'+' | b'.' | b'e' | b'E' => self.pos += 1,
+                _ => break
+            }
+        }
+        let text = str::from_utf8(&self.bytes[start..self.pos]).unwrap();
+        match text.parse::<f64>() {
+            Ok(n) => Ok(Json::Number(n)),
+            Err(_) => Err(self.error(&format!("bad number {}", text)))
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            let b = match self.peek() {
+                Some(b) => b,
+                None => return Err(self.error("unterminated string"))
+            };
+            self.pos += 1;
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = match self.peek() {
+                        Some(e) => e,
+                        None => return Err(self.error("unterminated string"))
+                    };
+                    self.pos += 1;
+                    match escaped {
+                        b'n' => bytes.push(b'\n'),
+                        b't' => bytes.push(b'\t'),
+                        b'r' => bytes.push(b'\r'),
+                        b'b' => bytes.push(8),
+                        b'f' => bytes.push(12),
+                        b'u' => {
+                            let end = if self.pos + 4 <= self.bytes.len() { self.pos + 4 } else { self.pos };
+                            let hex = str::from_utf8(&self.bytes[self.pos..end]).ok()
+                                .and_then(|h| if h.len() == 4 { Some(h) } else { None })
+                                .and_then(|h| u32::from_str_radix(h, 16).ok());
+                            // Surrogate pairs come out as U+FFFD; nobody
+                            // names frames in emoji.
+                            let c = match hex.and_then(::std::char::from_u32) {
+                                Some(c) => c,
+                                None if hex.is_some() => '\u{FFFD}',
+                                None => return Err(self.error("bad \\u escape"))
+                            };
+                            self.pos += 4;
+                            bytes.extend(c.to_string().bytes());
+                        }
+                        other => bytes.push(other)
+                    }
+                }
+                _ => bytes.push(b)
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("string isn't UTF-8"))
+    }
+}
+
+#[test]
+fn objects_keep_their_key_order() {
+    let json = parse("{\"b\": [1, -2.5e1], \"a\": {\"s\": \"x\\\"\\u0041\"}, \"n\": null}").unwrap();
+    let keys: Vec<&str> = json.as_object().unwrap().iter().map(|p| &p.0[..]).collect();
+    assert_eq!(keys, vec!["b", "a", "n"]);
+    assert_eq!(json.get("b"), Some(&Json::Array(vec![Json::Number(1.0), Json::Number(-25.0)])));
+    assert_eq!(json.get("a").and_then(|a| a.get("s")).and_then(|s| s.as_str()), Some("x\"A"));
+    assert!(parse("{\"a\": 1,}").is_err());
+}
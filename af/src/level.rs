@@ -0,0 +1,406 @@
+use gl::types::*;
+use std::fs::File;
+use std::io::{Read, Write};
+use vecmath::{Vec2, Rect};
+use animation::{Name, MAX_NAME_LEN};
+use assets::registry::{AssetString, Manifest};
+use memory::Arena;
+use tilemap::{Tilemap, TileId, Collision, MAX_TILE_KINDS};
+use tiled;
+use GameData;
+
+// A level on disk, like assets/levels/start.level:
+//
+//     tile size = 16
+//     origin = -40 -70               # where the bottom left tile goes
+//     size = 6 2                     # in tiles
+//     camera bounds = -100 0 200 50  # x y width height the camera's middle stays in
+//     collision 2 = one way          # ids are solid unless it says otherwise
+//
+//     layer = ground dirt_1 100 collides   # name, tileset, render layer
+//     row = 0 0 2 2 0 0              # top row first, 0 is no tile
+//     row = 1 1 1 1 1 1
+//
+//     spawn = crattlecrute -40 -40
+//         body color = D66FC8FF D693E4FF   # properties of the spawn above
+//
+// Lines starting with `#` are comments. Saving writes the level back out
+// from GameData (see from_game), so comments don't survive it.
+//
+// Levels can also be Tiled maps, which get turned into one of these.
+
+pub const MAX_SPAWN_PROPERTIES: usize = 8;
+
+// Where saving a level loaded from `path` goes. Tiled maps don't get
+// overwritten; the level is saved as a .level file next to them.
+pub fn save_path(path: &str) -> String {
+    if tiled::is_map(path) { format!("{}.level", &path[..path.len() - ".tmj".len()]) }
+    else { path.to_string() }
+}
+
+// The same level, built in, for when the file's missing or broken.
+pub static BUILT_IN: &'static str = include_str!("../../assets/levels/start.level");
+
+#[derive(Copy, Clone)]
+pub struct Property {
+    pub key:   AssetString,
+    pub value: AssetString
+}
+
+// Something to put in the level when it starts, like "player" or
+// "crattlecrute" (see sim::enter_level).
+#[derive(Copy, Clone)]
+pub struct Spawn {
+    pub kind:     Name,
+    pub position: Vec2<GLfloat>,
+    properties:     [Property; MAX_SPAWN_PROPERTIES],
+    property_count: usize
+}
+
+impl Spawn {
+    pub fn new(kind: &str, position: Vec2<GLfloat>) -> Result<Spawn, String> {
+        if kind.len() > MAX_NAME_LEN {
+            return Err(format!("spawn kind {} is longer than {} bytes", kind, MAX_NAME_LEN));
+        }
+        // It's saved as one word after `spawn =`.
+        if kind.is_empty() || kind.contains(char::is_whitespace) {
+            return Err(format!("spawn kind `{}` isn't one word", kind));
+        }
+        let empty = AssetString::new("").unwrap();
+        Ok(Spawn {
+            kind: Name::new(kind),
+            position: position,
+            properties: [Property { key: empty, value: empty }; MAX_SPAWN_PROPERTIES],
+            property_count: 0
+        })
+    }
+
+    pub fn properties(&self) -> &[Property] { &self.properties[..self.property_count] }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.properties().iter().find(|p| p.key.as_str() == key).map(|p| p.value.as_str())
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        // Each property is saved as a `key = value` line, so anything that
+        // wouldn't read back the same way is turned away here.
+        if key.trim().is_empty() || key.starts_with('#') || key.contains('=') || key.contains(is_newline) {
+            return Err(format!("`{}` can't be a property name", key));
+        }
+        if value.contains(is_newline) {
+            return Err(format!("value of {} has a line break", key));
+        }
+        let key_string   = try!(AssetString::new(key).ok_or(format!("property name {} is too long", key)));
+        let value_string = try!(AssetString::new(value).ok_or(format!("value of {} is too long", key)));
+
+        match self.properties().iter().position(|p| p.key.as_str() == key) {
+            Some(i) => self.properties[i].value = value_string,
+            None => {
+                if self.property_count == MAX_SPAWN_PROPERTIES {
+                    return Err(format!("more than {} properties", MAX_SPAWN_PROPERTIES));
+                }
+                self.properties[self.property_count] = Property { key: key_string, value: value_string };
+                self.property_count += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_newline(c: char) -> bool { c == '\n' || c == '\r' }
+
+pub struct LayerFile {
+    pub name:         String,
+    pub tileset:      String,
+    pub render_layer: i32,
+    pub collides:     bool,
+    // Top row first, like in the file.
+    pub rows: Vec<Vec<TileId>>
+}
+
+pub struct LevelFile {
+    pub tile_size:     GLfloat,
+    pub origin:        Vec2<GLfloat>,
+    pub width:         i32,
+    pub height:        i32,
+    pub camera_bounds: Rect,
+    // Only the ids that aren't solid.
+    pub collision: Vec<(TileId, Collision)>,
+    pub layers:    Vec<LayerFile>,
+    pub spawns:    Vec<Spawn>
+}
+
+pub fn collision_name(collision: Collision) -> &'static str {
+    match collision {
+        Collision::Empty     => "empty",
+        Collision::Solid     => "solid",
+        Collision::OneWay    => "one way",
+        Collision::SlopeUp   => "slope up",
+        Collision::SlopeDown => "slope down"
+    }
+}
+
+// The other way around, for whatever says which tiles do what (here and in
+// Tiled tilesets).
+pub fn collision_named(name: &str) -> Option<Collision> {
+    let words: Vec<&str> = name.split(' ').filter(|word| !word.is_empty()).collect();
+    if      words == ["solid"]         { Some(Collision::Solid) }
+    else if words == ["empty"]         { Some(Collision::Empty) }
+    else if words == ["one", "way"]    { Some(Collision::OneWay) }
+    else if words == ["slope", "up"]   { Some(Collision::SlopeUp) }
+    else if words == ["slope", "down"] { Some(Collision::SlopeDown) }
+    else { None }
+}
+
+fn numbers(words: &[&str]) -> Option<Vec<GLfloat>> {
+    let mut result = Vec::with_capacity(words.len());
+    for word in words.iter() {
+        match word.parse::<GLfloat>() {
+            Ok(n) => result.push(n),
+            Err(_) => return None
+        }
+    }
+    Some(result)
+}
+
+impl LevelFile {
+    // .level files, or Tiled maps (see tiled.rs).
+    pub fn load(filename: &str, manifest: &Manifest) -> Result<LevelFile, String> {
+        if tiled::is_map(filename) { return tiled::load(filename, manifest) }
+
+        let mut contents = String::new();
+        match File::open(filename).and_then(|mut f| f.read_to_string(&mut contents)) {
+            Ok(_) => {}
+            Err(e) => return Err(format!("Couldn't read {}: {}", filename, e))
+        }
+        LevelFile::parse(&contents).map_err(|e| format!("{}: {}", filename, e))
+    }
+
+    pub fn save(&self, filename: &str) -> Result<(), String> {
+        match File::create(filename).and_then(|mut f| f.write_all(self.to_text().as_bytes())) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(format!("Couldn't write {}: {}", filename, e))
+        }
+    }
+
+    pub fn parse(contents: &str) -> Result<LevelFile, String> {
+        let mut level = LevelFile {
+            tile_size: 16.0, origin: Vec2::s(0.0), width: 0, height: 0,
+            camera_bounds: Rect::new(0.0, 0.0, 0.0, 0.0),
+            collision: Vec::new(), layers: Vec::new(), spawns: Vec::new()
+        };
+
+        for (line_number, line) in contents.lines().enumerate() {
+            let line_number = line_number + 1;
+            let indented = line.starts_with(' ') || line.starts_with('\t');
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue }
+            let error = |what: &str| format!("line {}: {} in `{}`", line_number, what, line);
+
+            let mut sides = line.splitn(2, '=');
+            let left = sides.next().unwrap().trim();
+            let value = match sides.next() {
+                Some(value) => value.trim(),
+                None => return Err(error("expected `=`"))
+            };
+            let key:   Vec<&str> = left.split(' ').filter(|word| !word.is_empty()).collect();
+            let words: Vec<&str> = value.split(' ').filter(|word| !word.is_empty()).collect();
+
+            if indented {
+                let spawn = match level.spawns.last_mut() {
+                    Some(spawn) => spawn,
+                    None => return Err(error("property with no spawn above it"))
+                };
+                try!(spawn.set(left, value).map_err(|e| error(&e)));
+            }
+            else if key == ["tile", "size"] {
+                level.tile_size = match numbers(&words) {
+                    Some(ref n) if n.len() == 1 && n[0] > 0.0 => n[0],
+                    _ => return Err(error("expected a size"))
+                };
+            }
+            else if key == ["origin"] {
+                level.origin = match numbers(&words) {
+                    Some(ref n) if n.len() == 2 => Vec2::new(n[0], n[1]),
+                    _ => return Err(error("expected x y"))
+                };
+            }
+            else if key == ["size"] {
+                let (width, height) = match numbers(&words) {
+                    Some(ref n) if n.len() == 2 && n[0] >= 0.0 && n[1] >= 0.0 => (n[0] as i32, n[1] as i32),
+                    _ => return Err(error("expected width height"))
+                };
+                level.width = width;
+                level.height = height;
+            }
+            else if key == ["camera", "bounds"] {
+                level.camera_bounds = match numbers(&words) {
+                    Some(ref n) if n.len() == 4 => Rect::new(n[0], n[1], n[2], n[3]),
+                    _ => return Err(error("expected x y width height"))
+                };
+            }
+            else if key.len() == 2 && key[0] == "collision" {
+                let id = match key[1].parse::<TileId>() {
+                    Ok(id) if id != 0 && (id as usize) < MAX_TILE_KINDS => id,
+                    _ => return Err(error(&format!("tile ids with collision go from 1 to {}", MAX_TILE_KINDS - 1)))
+                };
+                let collision = match collision_named(value) {
+                    Some(collision) => collision,
+                    None => return Err(error("expected solid, empty, one way, slope up or slope down"))
+                };
+                level.collision.push((id, collision));
+            }
+            else if key == ["layer"] {
+                if words.len() != 3 && !(words.len() == 4 && words[3] == "collides") {
+                    return Err(error("expected name tileset render-layer [collides]"));
+                }
+                let render_layer = match words[2].parse::<i32>() {
+                    Ok(l) => l,
+                    Err(_) => return Err(error("bad render layer"))
+                };
+                level.layers.push(LayerFile {
+                    name: words[0].to_string(), tileset: words[1].to_string(),
+                    render_layer: render_layer, collides: words.len() == 4,
+                    rows: Vec::new()
+                });
+            }
+            else if key == ["row"] {
+                let mut row = Vec::with_capacity(words.len());
+                for word in words.iter() {
+                    match word.parse::<TileId>() {
+                        Ok(id) => row.push(id),
+                        Err(_) => return Err(error(&format!("bad tile id {}", word)))
+                    }
+                }
+                match level.layers.last_mut() {
+                    Some(layer) => layer.rows.push(row),
+                    None => return Err(error("row with no layer above it"))
+                }
+            }
+            else if key == ["spawn"] {
+                let position = match if words.len() == 3 { numbers(&words[1..]) } else { None } {
+                    Some(ref n) => Vec2::new(n[0], n[1]),
+                    None => return Err(error("expected kind x y"))
+                };
+                level.spawns.push(try!(Spawn::new(words[0], position).map_err(|e| error(&e))));
+            }
+            else {
+                return Err(error("don't know what this is"));
+            }
+        }
+
+        for layer in level.layers.iter() {
+            if layer.rows.len() != level.height as usize ||
+               layer.rows.iter().any(|row| row.len() != level.width as usize) {
+                return Err(format!("layer {} should be {} rows of {} tiles", layer.name, level.height, level.width));
+            }
+        }
+        Ok(level)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        text.push_str(&format!("tile size = {}\n", self.tile_size));
+        text.push_str(&format!("origin = {} {}\n", self.origin.x, self.origin.y));
+        text.push_str(&format!("size = {} {}\n", self.width, self.height));
+        let camera = &self.camera_bounds;
+        text.push_str(&format!("camera bounds = {} {} {} {}\n", camera.x1, camera.y1, camera.width(), camera.height()));
+        for &(id, collision) in self.collision.iter() {
+            text.push_str(&format!("collision {} = {}\n", id, collision_name(collision)));
+        }
+
+        for layer in self.layers.iter() {
+            text.push_str(&format!(
+                "\nlayer = {} {} {}{}\n", layer.name, layer.tileset, layer.render_layer,
+                if layer.collides { " collides" } else { "" }
+            ));
+            for row in layer.rows.iter() {
+                text.push_str("row =");
+                for id in row.iter() { text.push_str(&format!(" {}", id)); }
+                text.push_str("\n");
+            }
+        }
+
+        if !self.spawns.is_empty() { text.push_str("\n"); }
+        for spawn in self.spawns.iter() {
+            text.push_str(&format!("spawn = {} {} {}\n", spawn.kind.as_str(), spawn.position.x, spawn.position.y));
+            for property in spawn.properties().iter() {
+                text.push_str(&format!("    {} = {}\n", property.key.as_str(), property.value.as_str()));
+            }
+        }
+        text
+    }
+
+    // Tiles go in `arena`.
+    pub fn tilemap(&self, arena: &mut Arena) -> Result<Tilemap, String> {
+        let mut tilemap = Tilemap::new(self.origin, self.tile_size, self.width, self.height);
+        for &(id, collision) in self.collision.iter() {
+            tilemap.collision[id as usize] = collision;
+        }
+
+        for layer in self.layers.iter() {
+            let index = try!(tilemap.add_layer(arena, &layer.name, &layer.tileset, layer.render_layer, layer.collides));
+            for (row, ids) in layer.rows.iter().enumerate() {
+                let y = self.height - 1 - row as i32;
+                for (x, &id) in ids.iter().enumerate() {
+                    tilemap.set(index, x as i32, y, id);
+                }
+            }
+        }
+        Ok(tilemap)
+    }
+
+    // The level as it is in the game right now, for saving.
+    pub fn from_game(game: &GameData) -> LevelFile {
+        let map = &game.tilemap;
+        let mut level = LevelFile {
+            tile_size: map.tile_size, origin: map.origin,
+            width: map.width, height: map.height,
+            camera_bounds: game.camera_bounds,
+            collision: Vec::new(), layers: Vec::new(),
+            spawns: game.spawns.as_slice().to_vec()
+        };
+
+        for id in 1..MAX_TILE_KINDS {
+            if map.collision[id] != Collision::Solid {
+                level.collision.push((id as TileId, map.collision[id]));
+            }
+        }
+        for (index, layer) in map.layers().iter().enumerate() {
+            level.layers.push(LayerFile {
+                name: layer.name.as_str().to_string(),
+                tileset: layer.tileset.as_str().to_string(),
+                render_layer: layer.render_layer,
+                collides: layer.collides,
+                rows: (0..map.height).rev().map(|y|
+                    (0..map.width).map(|x| map.get(index, x, y)).collect()
+                ).collect()
+            });
+        }
+        level
+    }
+}
+
+#[test]
+fn levels_save_the_way_they_load() {
+    let text = "tile size = 16\norigin = -40 -70.5\nsize = 3 2\ncamera bounds = -10 0 20 5\ncollision 2 = one way\n\n\
+                layer = ground dirt_1 100 collides\nrow = 0 2 0\nrow = 1 1 1\n\n\
+                spawn = crattlecrute -40 -40\n    body color = D66FC8FF D693E4FF\n";
+    let level = LevelFile::parse(text).unwrap();
+    assert_eq!(level.to_text(), text);
+    assert_eq!(level.spawns[0].get("body color"), Some("D66FC8FF D693E4FF"));
+    assert!(LevelFile::parse("size = 2 1\nlayer = ground dirt_1 0\nrow = 1 1 1\n").is_err());
+}
+
+#[test]
+fn spawns_refuse_what_they_cant_save() {
+    assert!(Spawn::new("", Vec2::new(0.0, 0.0)).is_err());
+    assert!(Spawn::new("two words", Vec2::new(0.0, 0.0)).is_err());
+
+    let mut spawn = Spawn::new("crattlecrute", Vec2::new(0.0, 0.0)).unwrap();
+    assert!(spawn.set("a = b", "c").is_err());
+    assert!(spawn.set("# note", "c").is_err());
+    assert!(spawn.set("note", "line one\nline two").is_err());
+    assert!(spawn.set("note", "line one").is_ok());
+    assert_eq!(spawn.properties().len(), 1);
+}
@@ -3,9 +3,25 @@ extern crate gl;
 extern crate libc;
 
 pub mod vecmath;
+pub mod json;
+pub mod xml;
+#[macro_use]
+pub mod gl_debug;
 pub mod render;
+pub mod render_queue;
 pub mod assets;
 pub mod controls;
+pub mod memory;
+pub mod physics;
+pub mod tilemap;
+pub mod movement;
+pub mod level;
+pub mod tiled;
+pub mod sim;
+pub mod headless;
+pub mod capture;
+pub mod animation;
+pub mod attachment;
 
 use gl::types::*;
 use std::sync::mpsc::Receiver;
@@ -15,25 +31,20 @@ use vecmath::{Vec2, Rect};
 use std::mem::{size_of, size_of_val, transmute};
 use std::ptr;
 use std::slice;
+use std::str;
 use render::{GLData};
 use assets::{SpriteType2Color2, SpriteType3Color1, SpriteType1};
 use controls::Controls;
+use movement::Movement;
+use tilemap::Tilemap;
+use level::Spawn;
+use assets::registry::{AssetString, Manifest, MANIFEST_PATH};
+use render_queue::{RenderQueue, LAYER_WORLD};
+use memory::{GameMemory, Arena, ArenaList};
+use animation::{Animations, AnimationPlayer};
+use attachment::{Attachment, AttachmentPoints};
 use std::f32::consts::PI;
 
-macro_rules! check_error(
-    () => (
-        match gl::GetError() {
-            gl::NO_ERROR => {}
-            gl::INVALID_ENUM => panic!("Invalid enum!"),
-            gl::INVALID_VALUE => panic!("Invalid value!"),
-            gl::INVALID_OPERATION => panic!("Invalid operation!"),
-            gl::INVALID_FRAMEBUFFER_OPERATION => panic!("Invalid framebuffer operation?!"),
-            gl::OUT_OF_MEMORY => panic!("Out of memory bro!!!!!!!"),
-            e => panic!("OpenGL Error: {:?}", e)
-        }
-    )
-);
-
 static SQUARE_VERTICES: [GLfloat; 8] = [
 //    position
      2.0,  2.0, //   1.0, 1.0, // Top right
@@ -50,20 +61,80 @@ macro_rules! stride(
     ($val:expr) => (($val * size_of::<GLfloat>() as i32))
 );
 
+#[derive(Copy, Clone)]
 pub struct CrattleCrute {
-    position: Vec2<GLfloat>,
-    frame:    GLint,
-    flipped:  bool,
-    angle:    GLfloat,
+    pub position: Vec2<GLfloat>,
+    pub frame:    GLint,
+    pub flipped:  bool,
+    pub angle:    GLfloat,
+
+    // See physics::integrate.
+    pub velocity:     Vec2<GLfloat>, // pixels per second
+    pub acceleration: Vec2<GLfloat>, // pixels per second per second
+    // Stood on something as of the last step.
+    pub grounded:     bool,
+    // Seconds left to still jump after leaving the ground, and for a jump
+    // press to still count when landing.
+    pub coyote_left:      GLfloat,
+    pub jump_buffer_left: GLfloat,
+    // Going up from a jump that letting go of the button can still cut.
+    pub jumping:          bool,
+
+    // State as of the last simulation step, for interpolating while rendering.
+    pub prev_position: Vec2<GLfloat>,
+    pub prev_angle:    GLfloat,
+
+    // Picks `frame` every step. Clips come from GameData::crattlecrute_animations.
+    pub animation: AnimationPlayer,
+    // Where the eye goes on the current frame.
+    pub eye: Attachment,
 
     // Vec2 colors are x=primary, y=secondary
-    body_color:       Vec2<u32>,
-    left_foot_color:  Vec2<u32>,
-    right_foot_color: Vec2<u32>,
-    eye_color: u32
+    pub body_color:       Vec2<u32>,
+    pub left_foot_color:  Vec2<u32>,
+    pub right_foot_color: Vec2<u32>,
+    pub eye_color: u32
 }
 
 impl CrattleCrute {
+    // Standing still at `position`, in the sprites' own colors.
+    pub fn new(position: Vec2<GLfloat>) -> CrattleCrute {
+        CrattleCrute {
+            position: position,
+            frame:    0,
+            flipped:  false,
+            angle:    0.0,
+            velocity:     Vec2::s(0.0),
+            acceleration: Vec2::s(0.0),
+            grounded:     false,
+            coyote_left:      0.0,
+            jump_buffer_left: 0.0,
+            jumping:          false,
+            prev_position: position,
+            prev_angle:    0.0,
+            animation: AnimationPlayer::new(0),
+            eye:       Attachment::none(),
+            body_color:       Vec2::new(0x0094FFFF, 0x00C7FFFF),
+            left_foot_color:  Vec2::new(0xFF0000FF, 0xDB002FFF),
+            right_foot_color: Vec2::new(0xFF0000FF, 0xDB002FFF),
+            eye_color: 0x5900FFFF
+        }
+    }
+
+    pub fn save_previous(&mut self) {
+        self.prev_position = self.position;
+        self.prev_angle    = self.angle;
+    }
+
+    // A copy of this crattlecrute `alpha` of the way from its previous state
+    // to its current one.
+    pub fn interpolated(&self, alpha: GLfloat) -> CrattleCrute {
+        let mut result = *self;
+        result.position = sim::lerp(self.prev_position, self.position, alpha);
+        result.angle    = self.prev_angle + (self.angle - self.prev_angle) * alpha;
+        result
+    }
+
     fn sprite(&self, color_swap_1: Vec2<u32>, color_swap_2: Vec2<u32>) -> SpriteType2Color2 {
         SpriteType2Color2 {
             position: self.position,
@@ -93,31 +164,126 @@ impl CrattleCrute {
         )
     }
     pub fn eye_sprite(&self) -> SpriteType3Color1 {
-        let offset = &TEST_OFFSETS[self.frame as usize];
-
         SpriteType3Color1 {
             position: self.position,
             frame:    0,
             flipped:  self.flipped as GLint,
-            angle:    self.angle + offset.angle,
-            focus:    Vec2::new(2, 0) - offset.pos,
+            angle:    self.angle + self.eye.angle,
+            focus:    Vec2::new(2, 0) - self.eye.pos,
             color_swap: Vec2::new(0x5900FFFF, self.eye_color)
         }
     }
 }
 
+// Bump these whenever GameData or GLData change shape. The host holds on to
+// the layout it last loaded with, so a hot reload can tell that the bytes it
+// is holding were written with a different layout.
+pub const GAME_DATA_VERSION: u32 = 11;
+pub const GL_DATA_VERSION:   u32 = 9;
+
+// Shared with the host (see MemoryLayout in src/main.rs) -- keep them in sync!
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq)]
+pub struct MemoryLayout {
+    pub game_data_version: u32,
+    pub game_data_size:    usize,
+    pub gl_data_version:   u32,
+    pub gl_data_size:      usize
+}
+
+#[no_mangle]
+pub extern "C" fn memory_layout() -> MemoryLayout {
+    MemoryLayout {
+        game_data_version: GAME_DATA_VERSION,
+        game_data_size:    size_of::<GameData>(),
+        gl_data_version:   GL_DATA_VERSION,
+        gl_data_size:      size_of::<GLData>()
+    }
+}
+
+// Called when hot reloading into memory that was written with an older
+// layout. Return false if there's no way to bring it up to date, in which
+// case the host wipes the memory and does a cold load instead.
+//
+// When bumping a version, add a case here that shuffles the old fields
+// into the new ones if the old state is worth keeping.
+unsafe fn migrate(from: &MemoryLayout, _game: &mut GameData, _gl_data: &mut GLData) -> bool {
+    match (from.game_data_version, from.gl_data_version) {
+        _ => false
+    }
+}
+
 pub struct GameData {
     pub fps: i32,
     pub time_counter: f32,
     pub frame_counter: i32,
 
+    // Simulation runs in fixed steps of 1/sim_hz seconds; whatever real time
+    // is left over waits in the accumulator for the next frame.
+    pub sim_hz: GLfloat,
+    pub sim_accumulator: GLfloat,
+
     pub cam_pos: Vec2<GLfloat>,
+    pub prev_cam_pos: Vec2<GLfloat>,
     pub controls: Controls,
     pub player: CrattleCrute,
-    pub player2: CrattleCrute,
+    // Everyone else, as spawned by the level.
+    pub crattlecrutes: ArenaList<CrattleCrute>,
+    // From assets/crattlecrute/body.png.info -- all body parts share these.
+    pub crattlecrute_animations: Animations,
+    // From assets/crattlecrute/body.png.points.
+    pub crattlecrute_points: AttachmentPoints,
+
+    // From assets/crattlecrute/movement.info.
+    pub movement: Movement,
+    // Relative to CrattleCrute::position, which is the middle of the sprite.
+    pub crattlecrute_hitbox: Rect,
+    // The level, from `level_path` (see level.rs). Tiles, spawns and
+    // crattlecrutes live in `level_arena`.
+    pub level_path: AssetString,
+    // assets/images.manifest, read on first load. Tiled maps find their
+    // tilesets' images in here.
+    pub manifest: Manifest,
+    pub tilemap: Tilemap,
+    // Where the camera's middle can go.
+    pub camera_bounds: Rect,
+    pub spawns: ArenaList<Spawn>,
+
+    // Permanent allocations (everything after GameData in permanent memory).
+    pub arena: Arena,
+    // Two chunks of `arena` for levels. A level gets built in the spare one,
+    // then they trade places, so a reload never touches the level in play
+    // until the new one is all there.
+    pub level_arena:       Arena,
+    pub spare_level_arena: Arena,
+    // Per-frame scratch. Reset at the start of every update.
+    pub scratch: Arena
+}
+
+// GameData lives at the very start of permanent memory. The lifetime is
+// unbound since the host never moves that memory.
+pub unsafe fn game_data_from<'a>(memory: &GameMemory) -> &'a mut GameData {
+    transmute(memory.permanent)
+}
 
-    pub gravity: GLfloat, // pixels per second
-    pub ground_rect: Rect
+// Points the arenas in GameData at the host's memory. The permanent arena
+// only gets set up on first load, since it has to remember what's in use.
+pub unsafe fn attach_memory(game: &mut GameData, memory: &GameMemory, first_load: bool) {
+    // The scratch arena holds nothing worth keeping, so just point it at
+    // whatever the host gave us this time around.
+    game.scratch = Arena::new(memory.transient, memory.transient_size);
+
+    if first_load {
+        let game_data_size = size_of::<GameData>();
+        game.arena = Arena::new(
+            memory.permanent.offset(game_data_size as isize),
+            memory.permanent_size - game_data_size
+        );
+        // Half of what's left goes to levels.
+        let level_size = game.arena.remaining() / 4;
+        game.level_arena       = Arena::new(game.arena.alloc::<u8>(level_size), level_size);
+        game.spare_level_arena = Arena::new(game.arena.alloc::<u8>(level_size), level_size);
+    }
 }
 
 extern "C" {
@@ -127,152 +293,154 @@ extern "C" {
 
 #[no_mangle]
 pub unsafe extern "C" fn load(
-    first_load: bool,
-    game:       &mut GameData,
-    gl_data:    &mut GLData,
-    glfw:       &glfw::Glfw,
-    window:     &mut glfw::Window,
-    glfw_data:  *const c_void,
-) {
+    first_load:  bool,
+    last_layout: &MemoryLayout,
+    memory:      &mut GameMemory,
+    gl_data:     &mut GLData,
+    glfw:        &glfw::Glfw,
+    window:      &mut glfw::Window,
+    glfw_data:   *const c_void,
+) -> bool {
+    let game = game_data_from(memory);
     println!("LOAD!");
     glfwSet(glfw_data);
     gl::load_with(|s| window.get_proc_address(s));
+    gl_data.debug.install(gl_debug::Mode::from_env());
+
+    if !first_load && *last_layout != memory_layout() {
+        println!(
+            "Memory layout changed (GameData v{} -> v{}, GLData v{} -> v{}). Migrating...",
+            last_layout.game_data_version, GAME_DATA_VERSION,
+            last_layout.gl_data_version, GL_DATA_VERSION
+        );
+        if !migrate(last_layout, game, gl_data) {
+            return false;
+        }
+    }
+
+    attach_memory(game, memory, first_load);
+    sim::tune(game);
 
-    game.gravity = 100.0; // update gravity on every load
     if first_load {
         // ============== Game ================
-        game.cam_pos.x = 0.0;
-        game.cam_pos.y = 0.0;
-
-        let p1footcolor = Vec2::new(0xBB98E2FF, 0x9F67E0FF);
-        game.player.body_color       = Vec2::new(0x0026FFFF, 0x1979FFFF);
-        game.player.left_foot_color  = p1footcolor;
-        game.player.right_foot_color = p1footcolor;
-        game.player.eye_color        = 0xDD304AFF;
-
-        let goddamn_color2 = Vec2::new(0xD66FC8FF, 0xD693E4FF);
-        game.player2.body_color       = goddamn_color2;
-        game.player2.left_foot_color  = goddamn_color2;
-        game.player2.right_foot_color = goddamn_color2;
-        game.player2.eye_color        = goddamn_color2.x;
-        game.player2.position = Vec2::new(-40.0, -40.0);
-
-
-        // ============== OpenGL ================
-        // Defying borrow checker here:
-        let gl_data_ptr: usize = transmute(gl_data as *const GLData);
-        gl_data.images.init(transmute(gl_data_ptr));
-
-        // === Blending for alpha ===
-        gl::Enable(gl::BLEND);
-        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-
-        // === Global VAO ===
-        gl::GenVertexArrays(1, &mut gl_data.vao);
-        gl::BindVertexArray(gl_data.vao);
-
-        // === Basic sprite square vertex buffer ===
-        gl::GenBuffers(1, &mut gl_data.square_vbo);
-        gl::BindBuffer(gl::ARRAY_BUFFER, gl_data.square_vbo);
-        gl::BufferData(
-            gl::ARRAY_BUFFER,
-            size_of_val(&SQUARE_VERTICES) as GLsizeiptr,
-            transmute(&SQUARE_VERTICES[0]),
-            gl::STATIC_DRAW
-        );
-        gl::EnableVertexAttribArray(render::ATTR_VERTEX_POS);
-        gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE as GLboolean,
-                                stride!(2), ptr::null());
-
-        // === Basic sprite square element buffer ===
-        gl::GenBuffers(1, &mut gl_data.square_ebo);
-        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, gl_data.square_ebo);
-        gl::BufferData(
-            gl::ELEMENT_ARRAY_BUFFER,
-            size_of_val(&SQUARE_INDICES) as GLsizeiptr,
-            transmute(&SQUARE_INDICES[0]),
-            gl::STATIC_DRAW
-        );
+        game.manifest = match Manifest::read(MANIFEST_PATH) {
+            Ok(manifest) => manifest,
+            Err(e) => panic!("Couldn't load images: {}", e)
+        };
+        sim::init_game(game);
 
-        // === Shaders and texcoords ===
-        let failed = assets::Shaders::compile(gl_data, window);
-        if failed.len() > 0 {
-            panic!("Failed to compile: {:?}", failed);
-        }
+        init_gl(gl_data, window, &game.manifest);
         gl_data.shaders.each_shader(|shader, _name| {
             gl::Uniform2f(shader.cam_pos_uniform, game.cam_pos.x, game.cam_pos.y);
         });
-
-        gl_data.images.crattlecrute_front_foot.load();
-        gl_data.images.crattlecrute_body.load();
-        gl_data.images.crattlecrute_back_foot.load();
-        gl_data.images.eye_1.load();
-        gl_data.images.test_spin.load();
-        // TODO Just TWO players for now
-        let plr_count = 2;
-        gl_data.images.crattlecrute_front_foot.empty_buffer_data(plr_count, gl::DYNAMIC_DRAW);
-        gl_data.images.crattlecrute_body.empty_buffer_data(plr_count, gl::DYNAMIC_DRAW);
-        gl_data.images.crattlecrute_back_foot.empty_buffer_data(plr_count, gl::DYNAMIC_DRAW);
-        gl_data.images.eye_1.empty_buffer_data(plr_count, gl::DYNAMIC_DRAW);
-        gl_data.images.test_spin.empty_buffer_data(plr_count, gl::DYNAMIC_DRAW);
-
-        // Fill static dirt:
-        gl_data.images.dirt_1.load();
-        gl_data.images.dirt_1.empty_buffer_data(6, gl::STATIC_DRAW);
-
-        gl::BindBuffer(gl::ARRAY_BUFFER, gl_data.images.dirt_1.vbo);
-        let buffer = gl::MapBuffer(gl::ARRAY_BUFFER, gl::WRITE_ONLY);
-        let sprites = slice::from_raw_parts_mut::<SpriteType1>(
-            transmute(buffer), 6
-        );
-
-        game.ground_rect = Rect::new(-40.0, -70.0, 16.0 * 6.0, 16.0);
-        for i in 0..(game.ground_rect.width() / 16.0) as usize {
-            sprites[i] = SpriteType1 {
-                position: Vec2::new(
-                    (16 * i) as GLfloat + game.ground_rect.x1,
-                    game.ground_rect.y1
-                ),
-                frame: 0,
-                flipped: false as GLint
-            }
-        }
-        gl::UnmapBuffer(gl::ARRAY_BUFFER);
-        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
     }
     else {
         // Re-load whatever needs to be reloaded.
+        gl_data.images.relink();
         let failed = assets::Shaders::compile(gl_data, window);
-        if failed.len() > 0 {
-            println!("Shaders: {:?} failed to compile and were not reloaded.", failed);
+        for error in failed.iter() {
+            println!("{}\n(keeping the old {} program)", error, error.sprite_type);
         }
         gl_data.shaders.each_shader(|shader, _name| {
             gl::Uniform2f(shader.cam_pos_uniform, game.cam_pos.x, game.cam_pos.y);
         });
     }
+
+    true
 }
 
-struct Offset {
-    pub pos: Vec2<GLint>,
-    pub angle: GLfloat
+// Everything GL needs before anything can be drawn: buffers, shaders and
+// the images in `manifest`. Also used to draw without the host (see
+// capture.rs).
+pub unsafe fn init_gl(gl_data: &mut GLData, window: &glfw::Window, manifest: &Manifest) {
+    // === Blending for alpha ===
+    gl::Enable(gl::BLEND);
+    gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+
+    // === Global VAO ===
+    gl::GenVertexArrays(1, &mut gl_data.vao);
+    gl::BindVertexArray(gl_data.vao);
+
+    // === Basic sprite square vertex buffer ===
+    gl::GenBuffers(1, &mut gl_data.square_vbo);
+    gl::BindBuffer(gl::ARRAY_BUFFER, gl_data.square_vbo);
+    gl::BufferData(
+        gl::ARRAY_BUFFER,
+        size_of_val(&SQUARE_VERTICES) as GLsizeiptr,
+        transmute(&SQUARE_VERTICES[0]),
+        gl::STATIC_DRAW
+    );
+    gl::EnableVertexAttribArray(render::ATTR_VERTEX_POS);
+    gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE as GLboolean,
+                            stride!(2), ptr::null());
+
+    // === Basic sprite square element buffer ===
+    gl::GenBuffers(1, &mut gl_data.square_ebo);
+    gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, gl_data.square_ebo);
+    gl::BufferData(
+        gl::ELEMENT_ARRAY_BUFFER,
+        size_of_val(&SQUARE_INDICES) as GLsizeiptr,
+        transmute(&SQUARE_INDICES[0]),
+        gl::STATIC_DRAW
+    );
+
+    // === Shaders and texcoords ===
+    let failed = assets::Shaders::compile(gl_data, window);
+    if failed.len() > 0 {
+        for error in failed.iter() { println!("{}", error); }
+        panic!("{} shaders failed to compile.", failed.len());
+    }
+
+    // === Images ===
+    // Defying borrow checker here:
+    let gl_data_ptr: usize = transmute(gl_data as *const GLData);
+    match gl_data.images.load_manifest(manifest, transmute(gl_data_ptr)) {
+        Ok(()) => {}
+        Err(e) => panic!("Couldn't load images: {}", e)
+    }
+    gl_data.sprites = match assets::Sprites::find(&gl_data.images) {
+        Ok(sprites) => sprites,
+        Err(e) => panic!("{}", e)
+    };
+    // (Instance buffers start empty and grow as sprites get pushed.)
 }
 
-// THIS IS GENERATED
-static TEST_OFFSETS: [Offset; 9] = [
-    Offset { pos: Vec2::<GLint> { x: 16, y: 21 }, angle: 0.000000 },
-    Offset { pos: Vec2::<GLint> { x: 17, y: 21 }, angle: 0.000000 },
-    Offset { pos: Vec2::<GLint> { x: 18, y: 20 }, angle: 0.000000 },
-    Offset { pos: Vec2::<GLint> { x: 18, y: 20 }, angle: 0.000000 },
-    Offset { pos: Vec2::<GLint> { x: 17, y: 20 }, angle: 0.000000 },
-    Offset { pos: Vec2::<GLint> { x: 17, y: 21 }, angle: 0.000000 },
-    Offset { pos: Vec2::<GLint> { x: 18, y: 20 }, angle: 0.000000 },
-    Offset { pos: Vec2::<GLint> { x: 18, y: 20 }, angle: 0.000000 },
-    Offset { pos: Vec2::<GLint> { x: 17, y: 20 }, angle: 0.000000 },
-];
+// The host calls this for every file under assets/ that changed on disk,
+// with paths like "assets/crattlecrute/body.png".
+#[no_mangle]
+pub unsafe extern "C" fn asset_changed(
+    path:     *const u8,
+    path_len: usize,
+    memory:   &mut GameMemory,
+    gl_data:  &mut GLData,
+    window:   &glfw::Window
+) {
+    let path = match str::from_utf8(slice::from_raw_parts(path, path_len)) {
+        Ok(p) => p,
+        Err(_) => return
+    };
+    let game = game_data_from(memory);
+    if sim::asset_changed(game, path) { return }
+
+    if path.starts_with(assets::glsl::SHADER_DIR) {
+        // Anything could be including the file, so just redo all of them.
+        // Shaders that fail keep their old program.
+        let failed = assets::Shaders::compile(gl_data, window);
+        for error in failed.iter() {
+            println!("{}\n(keeping the old {} program)", error, error.sprite_type);
+        }
+        gl_data.shaders.each_shader(|shader, _name| {
+            gl::Uniform2f(shader.cam_pos_uniform, game.cam_pos.x, game.cam_pos.y);
+        });
+        return;
+    }
+
+    gl_data.images.reload(path);
+}
 
 #[no_mangle]
 pub extern "C" fn update(
-    game:    &mut GameData,
+    memory:  &mut GameMemory,
     gl_data: &mut GLData,
     delta_t: f32,
     glfw:    &mut glfw::Glfw,
@@ -280,6 +448,10 @@ pub extern "C" fn update(
     events:  &Receiver<(f64, glfw::WindowEvent)>,
     time:    i64
 ) {
+    let game = unsafe { game_data_from(memory) };
+    game.scratch.reset();
+    gl_data.debug.begin_frame();
+
     // === Count Frames Per Second ===
     game.time_counter += delta_t;
     game.frame_counter += 1;
@@ -288,18 +460,14 @@ pub extern "C" fn update(
         game.fps = game.frame_counter;
         game.frame_counter = 0;
         game.time_counter = 0.0;
-        game.player2.frame += 1;
-        if game.player2.frame >= 9 { game.player2.frame = 1 }
     }
 
-    glfw.poll_events();
-
     // === INPUT ===
+    // (The host polls glfw, so it can record and play back these events.)
     let mut new_window_size: Option<(GLfloat, GLfloat)> = None;
 
-    for control in game.controls.iter_mut() {
-        control.last_frame = control.this_frame;
-    }
+    // NOTE last_frame gets updated after each simulation step, not here --
+    // otherwise a press and release between two steps would never be seen.
     for (_, event) in glfw::flush_messages(&events) {
         match event {
             // TODO maybe keep this around but this is actually stupid
@@ -307,30 +475,14 @@ pub extern "C" fn update(
                 window.set_should_close(true)
             }
 
-            glfw::WindowEvent::Key(key, _, action, _) => {
-                let control = match key {
-                    Key::W  => &mut game.controls.up,
-                    Key::Up => &mut game.controls.up,
-
-                    Key::S    => &mut game.controls.down,
-                    Key::Down => &mut game.controls.down,
-
-                    Key::A    => &mut game.controls.left,
-                    Key::Left => &mut game.controls.left,
-
-                    Key::D     => &mut game.controls.right,
-                    Key::Right => &mut game.controls.right,
-
-                    Key::B => &mut game.controls.debug,
-
-                    _ => break
-                };
+            // (F5 and F6 are taken by the host's replay recorder, which
+            // also leaves F9 out of recordings.)
+            glfw::WindowEvent::Key(Key::F9, _, Action::Press, _) => {
+                sim::save_level(game);
+            }
 
-                match action {
-                    Action::Press => control.this_frame = true,
-                    Action::Release => control.this_frame = false,
-                    _ => {}
-                }
+            glfw::WindowEvent::Key(key, _, action, _) => {
+                sim::apply_key(&mut game.controls, key, action);
             }
 
             glfw::WindowEvent::Size(width, height) => unsafe {
@@ -342,134 +494,91 @@ pub extern "C" fn update(
         }
     }
 
-    // === PROCESSING? ===
-    unsafe {
-        if game.controls.left.down() {
-            game.player.position.x -= 100.0 * delta_t;
-            game.player.flipped = true;
-        }
-        if game.controls.right.down() {
-            game.player.position.x += 100.0 * delta_t;
-            game.player.flipped = false;
-        }
-        if game.controls.up.down() {
-            if game.player.flipped {
-                game.player.angle -= 3.14159 * delta_t;
-            }
-            else {
-                game.player.angle += 3.14159 * delta_t;
-            }
-        }
-        if game.controls.down.down() {
-            if game.player.flipped {
-                game.player.angle += 3.14159 * delta_t;
-            }
-            else {
-                game.player.angle -= 3.14159 * delta_t;
-            }
-        }
-        if game.controls.debug.just_down() {
-            // println!("Time: {}", time);
-
-            // game.player.angle = 0.0;
-            game.player.frame += 1;
-            if game.player.frame >= 9 { game.player.frame = 1 }
-        }
-        if delta_t < 0.0 {
-            println!("Delta time < 0!!! {}", delta_t);
-        }
-
-    }
-
-    // === PHYSICS! ===
-    // make player fall and bounce back up after awhile -- gravity is a velocity here.
-    game.player.position.y -= game.gravity * delta_t;
-    if game.player.position.y < -100.0 {
-        game.player.position.y = 200.0;
-    }
+    // === SIMULATION ===
+    let alpha = sim::advance(game, delta_t);
 
     // === RENDER ===
-    unsafe {
-        gl_data.shaders.each_shader(|shader, _name| {
-            gl::UseProgram(shader.program);
-            match new_window_size {
-                Some((width, height)) =>
-                    gl::Uniform2f(shader.screen_size_uniform, width, height),
-                None => {}
-            }
-            gl::Uniform2f(shader.cam_pos_uniform, game.cam_pos.x, game.cam_pos.y);
-        });
-
-
-        macro_rules! plrdata {
-            ($($img:ident|$render:ident|$sprite:ty),+) => {
-                $({
-                    gl::BindBuffer(gl::ARRAY_BUFFER, gl_data.images.$img.vbo);
-                    let buffer  = gl::MapBuffer(gl::ARRAY_BUFFER, gl::WRITE_ONLY);
-                    let sprites = slice::from_raw_parts_mut::<$sprite>(
-                        transmute(buffer),
-                        // NOTE
-                        // This is contingent on how large the buffer we made in the
-                        // loading routine is.
-                        2 // TODO <- number of players
-                    );
-                    // This should be a loop through sorted draw calls on for this texture.
-                    sprites[0] = game.player.$render();
-                    sprites[1] = game.player2.$render();
-                    gl::UnmapBuffer(gl::ARRAY_BUFFER);
-                });*
-            }
-        };
-        plrdata!(
-            eye_1                   | eye_sprite        | SpriteType3Color1,
-            crattlecrute_front_foot | right_foot_sprite | SpriteType2Color2,
-            crattlecrute_body       | body_sprite       | SpriteType2Color2,
-            crattlecrute_back_foot  | left_foot_sprite  | SpriteType2Color2
-        );
-
-        // === Draw test spinning body ===
-        /*
-        gl::BindBuffer(gl::ARRAY_BUFFER, gl_data.images.test_spin.vbo);
-        let buffer = gl::MapBuffer(gl::ARRAY_BUFFER, gl::WRITE_ONLY);
-        let sprites = slice::from_raw_parts_mut::<SpriteType3Color1>(
-            transmute(buffer),
-            1
-        );
-
-        sprites[0] = SpriteType3Color1 {
-            position: Vec2::new(game.player.angle, game.player.position.y),
-            frame:    0,
-            flipped:  !game.player.flipped as GLint,
-            angle:    game.player.angle,
-            focus:    Vec2::new(21, 52),
-            color_swap: Vec2::new(0x0094FFFF, game.player.eye_color)
-        };
-        gl::UnmapBuffer(gl::ARRAY_BUFFER);
-        */
+    // Log and Panic modes have already said what went wrong; Collect mode
+    // only keeps it in gl_data.debug's reports.
+    match unsafe { render(game, gl_data, new_window_size, alpha) } {
+        Err(code) if gl_data.debug.mode == gl_debug::Mode::Collect => println!(
+            "GL error this frame: {} ({} reports)", gl_debug::error_name(code), gl_data.debug.reports().len()
+        ),
+        _ => {}
+    }
 
-        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+    window.swap_buffers();
+}
 
-        gl::ClearColor(0.2, 0.2, 0.3, 1.0);
-        gl::Clear(gl::COLOR_BUFFER_BIT);
+// Draws everything `alpha` of the way between the previous and current
+// simulation states. Gives back the first GL error, if there was one.
+pub unsafe fn render(
+    game:            &mut GameData,
+    gl_data:         &mut GLData,
+    new_window_size: Option<(GLfloat, GLfloat)>,
+    alpha:           GLfloat
+) -> Result<(), GLenum> {
+    let cam_pos  = sim::lerp(game.prev_cam_pos, game.cam_pos, alpha);
+
+    gl_data.shaders.each_shader(|shader, _name| {
+        gl::UseProgram(shader.program);
+        match new_window_size {
+            Some((width, height)) =>
+                gl::Uniform2f(shader.screen_size_uniform, width, height),
+            None => {}
+        }
+        gl::Uniform2f(shader.cam_pos_uniform, cam_pos.x, cam_pos.y);
+    });
+
+    let mut queue = RenderQueue::new(&mut game.scratch);
+
+    // Back foot behind the body, front foot and eye in front of it.
+    let everyone = Some(&game.player).into_iter().chain(game.crattlecrutes.as_slice().iter());
+    for crattlecrute in everyone.map(|c| c.interpolated(alpha)) {
+        queue.submit(gl_data.sprites.crattlecrute_back_foot,  LAYER_WORLD, 0.0, crattlecrute.left_foot_sprite());
+        queue.submit(gl_data.sprites.crattlecrute_body,       LAYER_WORLD, 1.0, crattlecrute.body_sprite());
+        queue.submit(gl_data.sprites.crattlecrute_front_foot, LAYER_WORLD, 2.0, crattlecrute.right_foot_sprite());
+        queue.submit(gl_data.sprites.eye_1,                   LAYER_WORLD, 3.0, crattlecrute.eye_sprite());
+    }
 
-        macro_rules! renderthing {
-            ($img:expr, $count:expr) => {
-                $img.set();
-                gl::DrawElementsInstanced(
-                // TODO last argument here will be number of render calls counted for player!
-                    gl::TRIANGLES, 6, gl::UNSIGNED_INT, ptr::null(), $count
-                );
-            }
+    // === Draw test spinning body ===
+    /*
+    queue.submit(gl_data.sprites.test_spin, LAYER_WORLD, 4.0, SpriteType3Color1 {
+        position: Vec2::new(game.player.angle, game.player.position.y),
+        frame:    0,
+        flipped:  !game.player.flipped as GLint,
+        angle:    game.player.angle,
+        focus:    Vec2::new(21, 52),
+        color_swap: Vec2::new(0x0094FFFF, game.player.eye_color)
+    });
+    */
+
+    // Only the chunks on screen. Pixels are drawn at 2x, so the screen is
+    // half its size in world pixels.
+    let mut viewport = [0 as GLint; 4];
+    gl::GetIntegerv(gl::VIEWPORT, &mut viewport[0]);
+    let (half_width, half_height) = (viewport[2] as GLfloat / 4.0, viewport[3] as GLfloat / 4.0);
+    let view = Rect::new(cam_pos.x - half_width, cam_pos.y - half_height, half_width * 2.0, half_height * 2.0);
+
+    for (index, layer) in game.tilemap.layers().iter().enumerate() {
+        // (Layers with a tileset that isn't in the manifest just don't draw.)
+        let tileset = match gl_data.images.handle::<SpriteType1>(layer.tileset.as_str()) {
+            Ok(handle) => handle,
+            Err(_) => continue
         };
-
-        renderthing!(gl_data.images.crattlecrute_back_foot, 2);
-        renderthing!(gl_data.images.crattlecrute_body, 2);
-        renderthing!(gl_data.images.crattlecrute_front_foot, 2);
-        renderthing!(gl_data.images.eye_1, 2);
-        renderthing!(gl_data.images.dirt_1, 6);
+        game.tilemap.each_visible(index, &view, |position, id| {
+            queue.submit(tileset, layer.render_layer, 0.0, SpriteType1 {
+                position: position,
+                frame: id as GLint - 1,
+                flipped: false as GLint
+            });
+        });
     }
 
-    window.swap_buffers();
+    gl::ClearColor(0.2, 0.2, 0.3, 1.0);
+    gl::Clear(gl::COLOR_BUFFER_BIT);
+
+    queue.draw(&mut gl_data.images, &mut gl_data.debug)
 }
 
 #[test]
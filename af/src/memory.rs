@@ -0,0 +1,124 @@
+use std::mem::{size_of, align_of, transmute};
+use std::ptr;
+use std::slice;
+
+// Handed to us by the host on every load and update. The pointers stay put
+// for the whole run, so anything allocated out of them survives a reload.
+//
+// Must match GameMemory in src/memory.rs!
+#[repr(C)]
+pub struct GameMemory {
+    // GameData sits at the start of this, the permanent arena gets the rest.
+    pub permanent:      *mut u8,
+    pub permanent_size: usize,
+    // Scratch space, reset every frame.
+    pub transient:      *mut u8,
+    pub transient_size: usize
+}
+
+// Bump allocator over a chunk of host memory. Nothing is ever freed
+// individually -- reset the whole thing or leave it be.
+pub struct Arena {
+    pub base: *mut u8,
+    pub size: usize,
+    pub used: usize
+}
+
+impl Arena {
+    pub fn new(base: *mut u8, size: usize) -> Arena {
+        Arena { base: base, size: size, used: 0 }
+    }
+
+    pub fn remaining(&self) -> usize { self.size - self.used }
+
+    pub fn reset(&mut self) { self.used = 0; }
+
+    // Space for `count` T's, uninitialized.
+    pub unsafe fn alloc<T>(&mut self, count: usize) -> *mut T {
+        match self.try_alloc::<T>(count) {
+            Ok(items) => items,
+            Err(e) => panic!("{}", e)
+        }
+    }
+
+    // Same as alloc, but running out is an Err instead of a panic. For
+    // things that come from files, like levels.
+    pub unsafe fn try_alloc<T>(&mut self, count: usize) -> Result<*mut T, String> {
+        let align = align_of::<T>();
+        let start = self.base as usize + self.used;
+        let padding = (align - start % align) % align;
+        match size_of::<T>().checked_mul(count).and_then(|size| size.checked_add(padding)) {
+            Some(bytes) if bytes <= self.remaining() => {
+                self.used += bytes;
+                Ok(transmute(start + padding))
+            }
+            _ => Err(format!(
+                "Arena out of memory! Wanted {} {}-byte items, only {} of {} bytes left.",
+                count, size_of::<T>(), self.remaining(), self.size
+            ))
+        }
+    }
+}
+
+// A growable list living in an Arena. Growing copies everything into a fresh
+// allocation twice the size; the old space is wasted until the arena resets.
+//
+// The list doesn't remember its arena (so it can sit inside GameData across
+// reloads), which means you have to hand it the same one every time.
+pub struct ArenaList<T: Copy> {
+    items:    *mut T,
+    len:      usize,
+    capacity: usize
+}
+
+impl<T: Copy> ArenaList<T> {
+    pub fn new() -> ArenaList<T> {
+        ArenaList { items: ptr::null_mut(), len: 0, capacity: 0 }
+    }
+
+    pub fn with_capacity(arena: &mut Arena, capacity: usize) -> ArenaList<T> {
+        unsafe {
+            ArenaList { items: arena.alloc::<T>(capacity), len: 0, capacity: capacity }
+        }
+    }
+
+    pub fn len(&self) -> usize { self.len }
+    pub fn capacity(&self) -> usize { self.capacity }
+    pub fn clear(&mut self) { self.len = 0; }
+
+    pub fn push(&mut self, arena: &mut Arena, item: T) {
+        match self.try_push(arena, item) {
+            Ok(()) => {}
+            Err(e) => panic!("{}", e)
+        }
+    }
+
+    // Push, with running out of arena as an Err. The list is left as it was.
+    pub fn try_push(&mut self, arena: &mut Arena, item: T) -> Result<(), String> {
+        if self.len == self.capacity {
+            let new_capacity = if self.capacity == 0 { 8 } else { self.capacity * 2 };
+            unsafe {
+                let new_items = try!(arena.try_alloc::<T>(new_capacity));
+                if self.len > 0 {
+                    ptr::copy_nonoverlapping(self.items, new_items, self.len);
+                }
+                self.items = new_items;
+            }
+            self.capacity = new_capacity;
+        }
+
+        unsafe { ptr::write(self.items.offset(self.len as isize), item); }
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        if self.len == 0 { return &[] }
+        unsafe { slice::from_raw_parts(self.items, self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        if self.len == 0 { return &mut [] }
+        unsafe { slice::from_raw_parts_mut(self.items, self.len) }
+    }
+}
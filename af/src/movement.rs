@@ -0,0 +1,92 @@
+use gl::types::*;
+use std::fs::File;
+use std::io::Read;
+
+// How the player moves, read from assets/crattlecrute/movement.info so the
+// feel can be tweaked while the game runs (see sim::asset_changed):
+//
+//     walk speed = 100        # pixels per second
+//     gravity = 900           # pixels per second per second
+//     max fall speed = 600
+//     jump speed = 330        # upward velocity a jump starts with
+//     jump cut = 0.4          # velocity kept when letting go of jump early
+//     coyote time = 0.1       # seconds after walking off a ledge you can still jump
+//     jump buffer = 0.1       # seconds a jump press waits for the ground
+//
+// `#` starts a comment. Anything left out keeps its default.
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Movement {
+    pub walk_speed:     GLfloat,
+    pub gravity:        GLfloat,
+    pub max_fall_speed: GLfloat,
+    pub jump_speed:     GLfloat,
+    pub jump_cut:       GLfloat,
+    pub coyote_time:    GLfloat,
+    pub jump_buffer:    GLfloat
+}
+
+impl Movement {
+    pub fn defaults() -> Movement {
+        Movement {
+            walk_speed:     100.0,
+            gravity:        900.0,
+            max_fall_speed: 600.0,
+            jump_speed:     330.0,
+            jump_cut:       0.4,
+            coyote_time:    0.1,
+            jump_buffer:    0.1
+        }
+    }
+
+    pub fn load(filename: &str) -> Result<Movement, String> {
+        let mut contents = String::new();
+        match File::open(filename).and_then(|mut f| f.read_to_string(&mut contents)) {
+            Ok(_) => {}
+            Err(e) => return Err(format!("Couldn't read {}: {}", filename, e))
+        }
+        Movement::parse(&contents).map_err(|e| format!("{}: {}", filename, e))
+    }
+
+    pub fn parse(contents: &str) -> Result<Movement, String> {
+        let mut movement = Movement::defaults();
+
+        for (line_number, line) in contents.lines().enumerate() {
+            let line_number = line_number + 1;
+            let line = match line.find('#') { Some(i) => &line[..i], None => line }.trim();
+            if line.is_empty() { continue }
+
+            let mut sides = line.splitn(2, '=');
+            let key: Vec<&str> = sides.next().unwrap()
+                .split(' ').filter(|word| !word.is_empty()).collect();
+            let value = match sides.next().map(|v| v.trim().parse::<GLfloat>()) {
+                Some(Ok(v)) => v,
+                Some(Err(_)) => return Err(format!("line {}: bad value in `{}`", line_number, line)),
+                None => return Err(format!("line {}: expected `=`", line_number))
+            };
+
+            let field =
+                if      key == ["walk", "speed"]         { &mut movement.walk_speed }
+                else if key == ["gravity"]               { &mut movement.gravity }
+                else if key == ["max", "fall", "speed"]  { &mut movement.max_fall_speed }
+                else if key == ["jump", "speed"]         { &mut movement.jump_speed }
+                else if key == ["jump", "cut"]           { &mut movement.jump_cut }
+                else if key == ["coyote", "time"]        { &mut movement.coyote_time }
+                else if key == ["jump", "buffer"]        { &mut movement.jump_buffer }
+                else {
+                    return Err(format!("line {}: don't know what `{}` is", line_number, line));
+                };
+            *field = value;
+        }
+        Ok(movement)
+    }
+}
+
+#[test]
+fn missing_values_keep_their_defaults() {
+    let movement = Movement::parse("# floaty\njump speed = 200\ncoyote time = 0.25 # generous\n").unwrap();
+    assert_eq!(movement.jump_speed, 200.0);
+    assert_eq!(movement.coyote_time, 0.25);
+    assert_eq!(movement.gravity, Movement::defaults().gravity);
+    assert!(Movement::parse("jump height = 3").is_err());
+}
@@ -0,0 +1,234 @@
+use gl::types::*;
+use std::f32;
+use vecmath::{Vec2, Rect};
+
+// Axis aligned boxes moving through a world of shapes that don't move (see
+// Tilemap::shapes_in). Moving boxes are swept along their whole step, so
+// nothing tunnels through thin ground no matter how fast it's falling, and
+// they slide along whatever they hit instead of stopping dead.
+//
+// Plain game logic like sim.rs -- no GL in here.
+
+// Gap left between a box and whatever it ran into, so float error doesn't
+// leave it overlapping next step.
+pub const SKIN: GLfloat = 0.01;
+// Hits handled per move. Three covers a floor, a wall and a corner.
+const MAX_SLIDES: usize = 3;
+
+#[derive(Copy, Clone)]
+pub struct Hit {
+    // Fraction of the move before touching.
+    pub time: GLfloat,
+    // Which way the solid pushes back; one axis only.
+    pub normal: Vec2<GLfloat>
+}
+
+#[derive(Copy, Clone)]
+pub enum Shape {
+    Solid(Rect),
+    // Only stops boxes coming down onto it from above.
+    OneWay(Rect),
+    // Ground going from the bottom left corner up to the top right one if
+    // `rising`, otherwise from the top left down to the bottom right. Only
+    // the surface counts, so the high side isn't a wall -- put something
+    // solid next to it (tilemaps do).
+    Slope(Rect, bool)
+}
+
+#[derive(Copy, Clone)]
+pub struct Moved {
+    pub delta: Vec2<GLfloat>,
+    pub hit_floor:   bool,
+    pub hit_ceiling: bool,
+    pub hit_wall:    bool
+}
+
+// When along one axis a span moving by `delta` starts and stops overlapping
+// another span.
+fn axis_times(a1: GLfloat, a2: GLfloat, delta: GLfloat, b1: GLfloat, b2: GLfloat) -> (GLfloat, GLfloat) {
+    if delta > 0.0 {
+        ((b1 - a2) / delta, (b2 - a1) / delta)
+    }
+    else if delta < 0.0 {
+        ((b2 - a1) / delta, (b1 - a2) / delta)
+    }
+    else if a2 <= b1 || a1 >= b2 {
+        (f32::INFINITY, f32::NEG_INFINITY) // never
+    }
+    else {
+        (f32::NEG_INFINITY, f32::INFINITY) // always
+    }
+}
+
+// Where `moving`, going `delta`, first touches `solid`. Boxes that already
+// overlap don't hit (see push_out).
+pub fn sweep(moving: &Rect, delta: Vec2<GLfloat>, solid: &Rect) -> Option<Hit> {
+    let (x_entry, x_exit) = axis_times(moving.x1, moving.x2, delta.x, solid.x1, solid.x2);
+    let (y_entry, y_exit) = axis_times(moving.y1, moving.y2, delta.y, solid.y1, solid.y2);
+    let entry = if x_entry > y_entry { x_entry } else { y_entry };
+    let exit  = if x_exit  < y_exit  { x_exit  } else { y_exit  };
+
+    if entry > exit || entry < 0.0 || entry > 1.0 { return None }
+
+    let normal =
+        if x_entry > y_entry { Vec2::new(-delta.x.signum(), 0.0) }
+        else                 { Vec2::new(0.0, -delta.y.signum()) };
+    Some(Hit { time: entry, normal: normal })
+}
+
+// Shortest way out of `solid`, for boxes that start a move inside one
+// (spawned there, or the level changed under them).
+pub fn push_out(moving: &Rect, solid: &Rect) -> Vec2<GLfloat> {
+    if !moving.overlaps(solid) { return Vec2::s(0.0) }
+    let left  = solid.x1 - moving.x2 - SKIN;
+    let right = solid.x2 - moving.x1 + SKIN;
+    let down  = solid.y1 - moving.y2 - SKIN;
+    let up    = solid.y2 - moving.y1 + SKIN;
+
+    let x = if -left < right { left } else { right };
+    let y = if -down < up    { down } else { up };
+    if x.abs() < y.abs() { Vec2::new(x, 0.0) } else { Vec2::new(0.0, y) }
+}
+
+// Height of a slope's surface at `x`, which gets clamped to the slope.
+pub fn slope_height(slope: &Rect, rising: bool, x: GLfloat) -> GLfloat {
+    let mut t = (x - slope.x1) / slope.width();
+    if t < 0.0 { t = 0.0; }
+    if t > 1.0 { t = 1.0; }
+    if !rising { t = 1.0 - t; }
+    slope.y1 + t * slope.height()
+}
+
+// Highest point of a slope's surface under any part of `moving`.
+fn surface_under(moving: &Rect, slope: &Rect, rising: bool) -> GLfloat {
+    let x = if rising { moving.x2 } else { moving.x1 };
+    slope_height(slope, rising, x)
+}
+
+// Moves `hitbox` by `delta` as far as the shapes allow, sliding along
+// whatever it hits. With `stick`, a box walking down a slope stays on it
+// instead of skipping down it in little falls.
+pub fn move_through(hitbox: &Rect, delta: Vec2<GLfloat>, shapes: &[Shape], stick: bool) -> Moved {
+    let mut moved = Moved { delta: Vec2::s(0.0), hit_floor: false, hit_ceiling: false, hit_wall: false };
+    let mut current = *hitbox;
+
+    for shape in shapes.iter() {
+        if let Shape::Solid(ref solid) = *shape {
+            let out = push_out(&current, solid);
+            current = current.offset(out);
+            moved.delta = moved.delta + out;
+        }
+    }
+    let start = current;
+
+    let mut remaining = delta;
+    for _ in 0..MAX_SLIDES {
+        let mut first: Option<Hit> = None;
+        for shape in shapes.iter() {
+            let hit = match *shape {
+                Shape::Solid(ref solid) => sweep(&current, remaining, solid),
+                // Only if we're falling and were above it to begin with.
+                Shape::OneWay(ref platform) if remaining.y < 0.0 && current.y1 >= platform.y2 - SKIN =>
+                    sweep(&current, remaining, platform).and_then(|hit|
+                        if hit.normal.y > 0.0 { Some(hit) } else { None }
+                    ),
+                _ => None
+            };
+            match (hit, first) {
+                (Some(hit), Some(f)) if hit.time >= f.time => {}
+                (Some(hit), _) => first = Some(hit),
+                (None, _) => {}
+            }
+        }
+
+        let hit = match first {
+            Some(hit) => hit,
+            None => {
+                current = current.offset(remaining);
+                moved.delta = moved.delta + remaining;
+                break;
+            }
+        };
+
+        let step = remaining * Vec2::s(hit.time) + hit.normal * Vec2::s(SKIN);
+        current = current.offset(step);
+        moved.delta = moved.delta + step;
+
+        if hit.normal.y > 0.0 { moved.hit_floor = true; }
+        if hit.normal.y < 0.0 { moved.hit_ceiling = true; }
+        if hit.normal.x != 0.0 { moved.hit_wall = true; }
+
+        // Whatever's left of the move, minus the part going into the solid.
+        remaining = remaining * Vec2::s(1.0 - hit.time);
+        if hit.normal.x != 0.0 { remaining.x = 0.0; }
+        if hit.normal.y != 0.0 { remaining.y = 0.0; }
+    }
+
+    // Slopes get settled after the fact: a box that was on or above one and
+    // ended up under its surface goes back on top of it.
+    let mut floor: Option<GLfloat> = None;
+    for shape in shapes.iter() {
+        let (slope, rising) = match *shape {
+            Shape::Slope(ref slope, rising) => (slope, rising),
+            _ => continue
+        };
+        if current.x2 <= slope.x1 || current.x1 >= slope.x2 { continue }
+
+        let surface = surface_under(&current, slope, rising);
+        let was_above = start.y1 >= surface_under(&start, slope, rising) - SKIN;
+        let landed = current.y1 < surface + SKIN && was_above;
+        // As far down as walking across the slope could have taken us.
+        let snap = delta.x.abs() * slope.height() / slope.width() + SKIN * 2.0;
+        let stuck = stick && current.y1 >= surface && current.y1 - surface <= snap;
+
+        if (landed || stuck) && floor.map_or(true, |f| surface > f) {
+            floor = Some(surface);
+        }
+    }
+    if let Some(surface) = floor {
+        let lift = Vec2::new(0.0, surface + SKIN - current.y1);
+        moved.delta = moved.delta + lift;
+        moved.hit_floor = true;
+    }
+    moved
+}
+
+// One step of semi-implicit Euler, then a swept move. `hitbox` is relative
+// to `position`. Velocity into anything that got hit is dropped. Boxes that
+// were `grounded` stick to slopes they walk down.
+pub fn integrate(
+    position:       &mut Vec2<GLfloat>,
+    velocity:       &mut Vec2<GLfloat>,
+    acceleration:   Vec2<GLfloat>,
+    hitbox:         &Rect,
+    max_fall_speed: GLfloat,
+    shapes:         &[Shape],
+    grounded:       bool,
+    dt:             GLfloat
+) -> Moved {
+    *velocity = *velocity + acceleration * Vec2::s(dt);
+    if velocity.y < -max_fall_speed { velocity.y = -max_fall_speed; }
+
+    let stick = grounded && velocity.y <= 0.0;
+    let moved = move_through(&hitbox.offset(*position), *velocity * Vec2::s(dt), shapes, stick);
+    *position = *position + moved.delta;
+
+    if moved.hit_floor   && velocity.y < 0.0 { velocity.y = 0.0; }
+    if moved.hit_ceiling && velocity.y > 0.0 { velocity.y = 0.0; }
+    if moved.hit_wall { velocity.x = 0.0; }
+    moved
+}
+
+#[test]
+fn fast_boxes_land_on_thin_ground() {
+    let ground = Rect::new(-50.0, -10.0, 100.0, 2.0);
+    let falling = Rect::new(-5.0, 0.0, 10.0, 10.0);
+
+    // Would end up well past the ground without the sweep.
+    let moved = move_through(&falling, Vec2::new(3.0, -100.0), &[Shape::Solid(ground)], false);
+    assert!(moved.hit_floor && !moved.hit_wall);
+    let landed = falling.offset(moved.delta);
+    assert!((landed.y1 - (ground.y2 + SKIN)).abs() < 0.001);
+    // Slid along the ground instead of stopping.
+    assert!((moved.delta.x - 3.0).abs() < 0.001);
+}
@@ -3,27 +3,17 @@ extern crate libc;
 
 use vecmath::Vec2;
 use gl::types::*;
-use std::ffi::CString;
+use std::ffi::{CString, CStr};
+use std::fmt;
 use libc::{c_char, c_int};
-use std::mem::{uninitialized, transmute, size_of};
+use std::mem::{transmute, size_of};
 use std::ptr;
 use std::slice;
 use std::vec::Vec;
 use assets;
-
-macro_rules! check_error(
-    () => (
-        match gl::GetError() {
-            gl::NO_ERROR => {}
-            gl::INVALID_ENUM => panic!("Invalid enum!"),
-            gl::INVALID_VALUE => panic!("Invalid value!"),
-            gl::INVALID_OPERATION => panic!("Invalid operation!"),
-            gl::INVALID_FRAMEBUFFER_OPERATION => panic!("Invalid framebuffer operation?!"),
-            gl::OUT_OF_MEMORY => panic!("Out of memory bro!!!!!!!"),
-            _ => panic!("I DON'T KNOW. FULL BANANNACAKES.")
-        }
-    )
-);
+use assets::registry::AssetString;
+use assets::glsl::GlslSource;
+use gl_debug;
 
 extern "C" {
     fn stbi_load(
@@ -35,6 +25,10 @@ extern "C" {
     ) -> *const u8;
 
     fn stbi_image_free(ptr: *const u8);
+
+    fn stbi_failure_reason() -> *const c_char;
+
+    fn stbi_zlib_decode_malloc(buffer: *const c_char, len: c_int, outlen: *mut c_int) -> *mut c_char;
 }
 
 // Global GL game state
@@ -44,7 +38,9 @@ pub struct GLData {
     pub square_ebo: GLuint,
 
     pub images: assets::Images,
-    pub shaders: assets::Shaders
+    pub sprites: assets::Sprites,
+    pub shaders: assets::Shaders,
+    pub debug: gl_debug::GLDebug
 }
 
 
@@ -54,8 +50,6 @@ pub static ATTR_POSITION: u32 = 1;
 pub static ATTR_FRAME: u32 = 2;
 pub static ATTR_FLIPPED: u32 = 3;
 
-pub static FRAME_UNIFORM_MAX: i64 = 256;
-
 pub static STANDARD_VERTEX: &'static str = "
         #version 330 core
 
@@ -116,43 +110,187 @@ pub static STANDARD_FRAGMENT: &'static str = "
         }
     ";
 
-macro_rules! check_log(
-    ($typ:expr, $get_iv:ident | $get_log:ident $val:ident $status:ident $on_error:ident) => (
-        unsafe {
-            let mut status = 0;
-            gl::$get_iv($val, gl::$status, &mut status);
-            if status == 0 {
-                let mut len = 0;
-                gl::$get_iv($val, gl::INFO_LOG_LENGTH, &mut len);
-
-                let mut buf = Vec::with_capacity(len as usize - 1);
-                for _ in (0..len-1) { buf.push(0); }
-                gl::$get_log($val, len, ptr::null_mut(), buf.as_mut_ptr() as *mut GLchar);
-
-                match String::from_utf8(buf) {
-                    Ok(error_message) => $on_error!("{}", error_message),
-                    Err(e) => $on_error!("Error parsing OpenGL error message: {}", e)
+// Which part of building a shader program went wrong.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ShaderStage { Vertex, Fragment, Link }
+
+// One line of a GL info log. file is None when the line points at the
+// generated part of the source (or at nothing in particular).
+#[derive(Clone, Debug)]
+pub struct ShaderMessage {
+    pub file:   Option<String>,
+    pub line:   Option<u32>,
+    pub column: Option<u32>,
+    pub text:   String
+}
+
+#[derive(Clone, Debug)]
+pub struct ShaderError {
+    pub stage:       ShaderStage,
+    pub sprite_type: &'static str,
+    pub messages:    Vec<ShaderMessage>
+}
+
+impl ShaderError {
+    // For when there's no GL log, like a shader file that couldn't be read.
+    pub fn new(stage: ShaderStage, sprite_type: &'static str, text: String) -> ShaderError {
+        ShaderError {
+            stage: stage,
+            sprite_type: sprite_type,
+            messages: vec![ShaderMessage { file: None, line: None, column: None, text: text }]
+        }
+    }
+}
+
+impl fmt::Display for ShaderMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.file {
+            Some(ref file) => try!(write!(f, "{}", file)),
+            None => try!(write!(f, "<generated>"))
+        }
+        match self.line {
+            Some(line) => try!(write!(f, ":{}", line)),
+            None => {}
+        }
+        match self.column {
+            Some(column) => try!(write!(f, ":{}", column)),
+            None => {}
+        }
+        write!(f, ": {}", self.text)
+    }
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stage = match self.stage {
+            ShaderStage::Vertex   => "vertex shader",
+            ShaderStage::Fragment => "fragment shader",
+            ShaderStage::Link     => "program link"
+        };
+        try!(write!(f, "{} {} failed:", self.sprite_type, stage));
+        for message in self.messages.iter() {
+            try!(write!(f, "\n    {}", message));
+        }
+        Ok(())
+    }
+}
+
+fn take_number(s: &str) -> Option<(u32, &str)> {
+    let digits = s.chars().take_while(|c| c.is_digit(10)).count();
+    if digits == 0 { return None }
+    s[..digits].parse::<u32>().ok().map(|n| (n, &s[digits..]))
+}
+
+// Pulls the location off the front of an info log line. Drivers can't agree
+// on a format:
+//
+//     0:12(5): error: ...           -- Mesa
+//     ERROR: 0:12: ...              -- AMD, Intel on Windows, Apple
+//     0(12) : error C0000: ...      -- NVIDIA
+//
+// Returns (source string number, line, column, message).
+fn parse_log_line(line: &str) -> Option<(usize, u32, Option<u32>, &str)> {
+    let mut rest = line.trim();
+    for &prefix in ["ERROR:", "WARNING:"].iter() {
+        if rest.starts_with(prefix) { rest = rest[prefix.len()..].trim_left(); }
+    }
+
+    let (source, rest) = match take_number(rest) { Some(n) => n, None => return None };
+    let (line, column, rest) =
+        if rest.starts_with(':') {
+            let (line, rest) = match take_number(&rest[1..]) { Some(n) => n, None => return None };
+            if rest.starts_with('(') {
+                match take_number(&rest[1..]) {
+                    Some((column, rest)) if rest.starts_with(')') => (line, Some(column), &rest[1..]),
+                    _ => return None
                 }
-                false
-            } else {
-                println!("I THINK THE {} COMPILED", $typ);
-                true
             }
+            else { (line, None, rest) }
         }
-    )
-);
+        else if rest.starts_with('(') {
+            match take_number(&rest[1..]) {
+                Some((line, rest)) if rest.starts_with(')') => (line, None, &rest[1..]),
+                _ => return None
+            }
+        }
+        else { return None };
 
-macro_rules! make_shader(
-    (($name:expr): $shader_type:ident) => (
-        unsafe {
-            let sh = gl::CreateShader(gl::$shader_type);
-            let shader_src_str = CString::new($name).unwrap();
-            gl::ShaderSource(sh, 1, &shader_src_str.as_ptr(), ptr::null());
-            gl::CompileShader(sh);
-            sh
+    let rest = rest.trim_left();
+    if !rest.starts_with(':') { return None }
+    Some((source as usize, line, column, rest[1..].trim()))
+}
+
+// Splits an info log into messages, pointing each one at the file it came
+// from (see assets::glsl for how source numbers map to files).
+pub fn parse_info_log(log: &str, source: Option<&GlslSource>) -> Vec<ShaderMessage> {
+    let mut messages: Vec<ShaderMessage> = Vec::new();
+    for line in log.lines() {
+        if line.trim().is_empty() { continue }
+
+        match parse_log_line(line) {
+            Some((source_number, line, column, text)) => messages.push(ShaderMessage {
+                file:   source.and_then(|s| s.file(source_number)).map(|f| f.to_string()),
+                line:   Some(line),
+                column: column,
+                text:   text.to_string()
+            }),
+            // Continuation of the last message, or something with no location.
+            None => {
+                let continued = messages.last().map(|m| m.line.is_some()).unwrap_or(false);
+                if continued {
+                    let last = messages.last_mut().unwrap();
+                    last.text.push_str("\n");
+                    last.text.push_str(line.trim());
+                }
+                else {
+                    messages.push(ShaderMessage {
+                        file: None, line: None, column: None, text: line.trim().to_string()
+                    });
+                }
+            }
         }
-    )
-);
+    }
+    messages
+}
+
+unsafe fn info_log(id: GLuint, program: bool) -> String {
+    let mut len = 0;
+    if program { gl::GetProgramiv(id, gl::INFO_LOG_LENGTH, &mut len); }
+    else       { gl::GetShaderiv(id, gl::INFO_LOG_LENGTH, &mut len); }
+    if len <= 1 { return String::new() }
+
+    let mut buf = vec![0u8; len as usize];
+    if program { gl::GetProgramInfoLog(id, len, ptr::null_mut(), buf.as_mut_ptr() as *mut GLchar); }
+    else       { gl::GetShaderInfoLog(id, len, ptr::null_mut(), buf.as_mut_ptr() as *mut GLchar); }
+    buf.pop(); // null terminator
+
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+unsafe fn compile_shader(
+    source:      &GlslSource,
+    shader_type: GLenum,
+    stage:       ShaderStage,
+    sprite_type: &'static str
+) -> Result<GLuint, ShaderError> {
+    let sh = gl::CreateShader(shader_type);
+    let source_cstr = CString::new(source.text.clone()).unwrap();
+    gl::ShaderSource(sh, 1, &source_cstr.as_ptr(), ptr::null());
+    gl::CompileShader(sh);
+
+    let mut status = 0;
+    gl::GetShaderiv(sh, gl::COMPILE_STATUS, &mut status);
+    if status == 0 {
+        let log = info_log(sh, false);
+        gl::DeleteShader(sh);
+        return Err(ShaderError {
+            stage: stage,
+            sprite_type: sprite_type,
+            messages: parse_info_log(&log, Some(source))
+        });
+    }
+    Ok(sh)
+}
 
 pub struct Texcoords {
     pub top_right:    Vec2<GLfloat>,
@@ -211,119 +349,16 @@ impl Frame {
 pub struct Texture {
     pub id: GLuint,
     pub width: i32,
-    pub height: i32,
-    pub filename: &'static str,
-    pub frame_texcoords_size: i64,
-    pub texcoords_space: *mut [Texcoords]
+    pub height: i32
 }
 
 impl Texture {
-    pub fn set_full(&self, sampler_uniform: GLint, sprite_size_uniform: GLint) {
+    // Onto unit 0, where the shaders' `tex` sampler looks.
+    pub fn bind(&self) {
         unsafe {
             gl::ActiveTexture(gl::TEXTURE0);
             gl::BindTexture(gl::TEXTURE_2D, self.id);
-            gl::Uniform1i(sampler_uniform, 0);
-            gl::Uniform2f(sprite_size_uniform, self.width as f32, self.height as f32);
-        }
-    }
-
-    #[inline]
-    pub fn texcoords(&self) -> &[Texcoords] {
-        unsafe { transmute(self.texcoords_space) }
-    }
-
-    #[inline]
-    pub fn texcoords_mut(&mut self) -> &mut [Texcoords] {
-        unsafe { transmute(self.texcoords_space) }
-    }
-
-    // NOTE this expects #generate_texcoords_buffer to have been called
-    // if there are frames.
-    pub fn set(&self, sampler_uniform:     GLint,
-                      sprite_size_uniform: GLint,
-                      frames_uniform:      GLint,
-                      width: f32, height: f32) {
-        unsafe {
-            assert!(self.frame_texcoords_size / 8 < FRAME_UNIFORM_MAX);
-
-            gl::ActiveTexture(gl::TEXTURE0);
-            gl::BindTexture(gl::TEXTURE_2D, self.id);
-            gl::Uniform1i(sampler_uniform, 0);
-            gl::Uniform2f(sprite_size_uniform, width as f32, height as f32);
-
-            let frames_len = self.texcoords().len();
-
-            if frames_len > 0 {
-                gl::Uniform2fv(
-                    frames_uniform,
-                    frames_len as GLint * 4,
-                    transmute(&(&*self.texcoords_space)[0])
-                );
-            }
-        }
-    }
-
-    /*
-    fn put_texcoord(&mut self, index: usize, texcoord: Texcoords) {
-        self.texcoords_mut()[index] = texcoord;
-    }
-    */
-
-    // NOTE this should be properly merged with add_frames.
-    pub fn generate_texcoords_buffer(
-        &mut self, frame_width: usize, frame_height: usize, space: *mut [Texcoords]
-    ) {
-        unsafe {
-            let frames_len = (*space).len();
-
-            let mut frames = Vec::<Frame>::with_capacity(frames_len);
-            self.add_frames(&mut frames, frame_width, frame_height);
-            assert_eq!(frames.len(), frames_len); // PLZ
-
-            self.texcoords_space = space;
-            for i in (0..frames_len) {
-                frames[i].texcoords.copy_to(&mut self.texcoords_mut()[i]);
-            }
-        }
-    }
-
-    // Fill the given slice with frames of the given width and height. "
-    // So this is now called only by #generate_texcoords_buffer
-    pub fn add_frames(&mut self, space: &mut Vec<Frame>, uwidth: usize, uheight: usize) {
-        let count = space.capacity();
-        let tex_width  = self.width as f32;
-        let tex_height = self.height as f32;
-        let width  = uwidth as f32;
-        let height = uheight as f32;
-
-        {
-            let mut current_pos = Vec2::<f32>::new(0.0, tex_height - height);
-
-            for _ in (0..count) {
-                if current_pos.x + width > tex_width {
-                    current_pos.x = 0.0;
-                    current_pos.y -= height;
-                }
-                if current_pos.y < 0.0 {
-                    panic!(
-                        "Too many frames! Asked for {} {}x{} frames on a {}x{} texture.",
-                        count, width, height, tex_width, tex_height
-                    );
-                }
-
-                let mut frame = Frame {
-                    position:  current_pos,
-                    size:      Vec2::new(width, height),
-                    texcoords: unsafe { uninitialized() }
-                };
-                frame.generate_texcoords(tex_width, tex_height);
-                space.push(frame);
-
-                current_pos.x += width;
-            }
         }
-
-        self.frame_texcoords_size = size_of::<Texcoords>() as i64 * count as i64;
     }
 
     // TODO man, should this be a destructor?
@@ -335,167 +370,294 @@ impl Texture {
     }
 }
 
-// NOTE don't instantiate these willy nilly!
+// NOTE don't instantiate these willy nilly! They come from the
+// manifest, see assets::registry.
 pub struct ImageAsset {
     // I don't like wasting space with the pointer here, but
     // it's hard to pass gl_data to a method called on this
     // because of the borrow checker...
     pub gl_data:         *const GLData,
-    pub filename:        &'static str,
+    pub name:            AssetString,
+    pub filename:        AssetString,
+    // The sheet metadata filename, or empty if the frames are a grid of
+    // frame_width x frame_height.
+    pub sheet:           AssetString,
+    // Which layer of an .aseprite filename to draw, or empty for all of them.
+    pub layer:           AssetString,
+    pub sprite_type:     AssetString,
     pub vbo:             GLuint,
-    pub set_attributes:  extern "Rust" fn(GLuint),
+    // How many instances the VBO has room for, and how many are in it.
+    pub instance_capacity: usize,
+    pub instance_count:    usize,
+    pub set_attributes:  extern "Rust" fn(GLuint, usize),
     pub shader:          extern "Rust" fn(&GLData) -> &assets::Shader,
     pub attributes_size: usize,
     pub texture:         Texture,
+    // The biggest frame's, for images with sheet metadata.
     pub frame_width:     usize,
     pub frame_height:    usize,
     pub texcoord_count:  usize,
-    // The next texcoord_count * size_of::<Texcoords>() bytes
-    // should be free for this struct to use.
+    // texcoord_count of these, in the registry's shared texcoord space,
+    // starting at index first_texcoord.
+    pub first_texcoord:  usize,
+    pub texcoords:       *mut Texcoords
 }
 
 impl ImageAsset {
     pub unsafe fn texcoords(&mut self) -> &mut [Texcoords] {
-        let count_ptr: *mut usize = &mut self.texcoord_count;
-        slice::from_raw_parts_mut::<Texcoords>(
-            transmute(count_ptr.offset(1)),
-            self.texcoord_count
-        )
+        slice::from_raw_parts_mut::<Texcoords>(self.texcoords, self.texcoord_count)
     }
 
     pub fn loaded(&self) -> bool { self.vbo != 0 }
 
-    pub unsafe fn load(&mut self) {
-        let mut texture = load_texture(self.filename);
-        texture.generate_texcoords_buffer(self.frame_width, self.frame_height, self.texcoords());
-        self.texture = texture;
+    pub fn instance_count(&self) -> usize { self.instance_count }
+
+    // Forgets all instances. The buffer gets orphaned, so drawing from it
+    // again this frame doesn't have to wait on last frame's draws.
+    pub fn clear(&mut self) {
+        self.instance_count = 0;
+        if self.instance_capacity == 0 { return }
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                (self.instance_capacity * self.attributes_size) as GLsizeiptr,
+                ptr::null(),
+                gl::DYNAMIC_DRAW
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+    }
+
+    // Makes room for `additional` more instances, keeping the ones already
+    // pushed. Doubles so a growing crowd doesn't reallocate every frame.
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = self.instance_count + additional;
+        if needed <= self.instance_capacity { return }
+
+        let mut capacity = if self.instance_capacity == 0 { 8 } else { self.instance_capacity };
+        while capacity < needed { capacity *= 2; }
+
+        unsafe {
+            let mut new_vbo = 0;
+            gl::GenBuffers(1, &mut new_vbo);
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, new_vbo);
+            gl::BufferData(
+                gl::COPY_WRITE_BUFFER,
+                (capacity * self.attributes_size) as GLsizeiptr,
+                ptr::null(),
+                gl::DYNAMIC_DRAW
+            );
+            if self.instance_count > 0 {
+                gl::BindBuffer(gl::COPY_READ_BUFFER, self.vbo);
+                gl::CopyBufferSubData(
+                    gl::COPY_READ_BUFFER, gl::COPY_WRITE_BUFFER, 0, 0,
+                    (self.instance_count * self.attributes_size) as GLsizeiptr
+                );
+                gl::BindBuffer(gl::COPY_READ_BUFFER, 0);
+            }
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, 0);
+
+            gl::DeleteBuffers(1, &self.vbo);
+            self.vbo = new_vbo;
+        }
+        self.instance_capacity = capacity;
+    }
 
-        gl::GenBuffers(1, &mut self.vbo);
+    // Adds an instance to draw. T has to be this image's sprite type.
+    pub fn push<T: assets::SpriteType + Copy>(&mut self, instance: T) {
+        self.push_all(&[instance]);
+    }
+
+    pub fn push_all<T: assets::SpriteType + Copy>(&mut self, instances: &[T]) {
+        if T::name() != self.sprite_type.as_str() {
+            panic!("Pushed a {} to {}, which is a {}!",
+                   T::name(), self.name.as_str(), self.sprite_type.as_str());
+        }
+        unsafe {
+            let bytes = slice::from_raw_parts(
+                instances.as_ptr() as *const u8, instances.len() * size_of::<T>()
+            );
+            self.push_bytes(bytes);
+        }
     }
 
-    pub unsafe fn empty_buffer_data(&mut self, count: i64, draw: GLenum) {
+    // For callers that lost track of the type, like the render queue. The
+    // bytes have to be whole instances of this image's sprite type.
+    pub unsafe fn push_bytes(&mut self, bytes: &[u8]) {
+        if bytes.len() % self.attributes_size != 0 {
+            panic!("{} bytes isn't a whole number of {} byte {} instances!",
+                   bytes.len(), self.attributes_size, self.sprite_type.as_str());
+        }
+        let count = bytes.len() / self.attributes_size;
+        if count == 0 { return }
+        self.reserve(count);
+
         gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
-        gl::BufferData(
+        gl::BufferSubData(
             gl::ARRAY_BUFFER,
-            count * self.attributes_size as GLsizeiptr,
-            ptr::null(),
-            draw
+            (self.instance_count * self.attributes_size) as GLintptr,
+            bytes.len() as GLsizeiptr,
+            transmute(bytes.as_ptr())
         );
+        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        self.instance_count += count;
     }
 
-    // Sets the texture, and the attributes
-    pub unsafe fn set(&mut self) {
+    // Sets the texture, and the attributes starting at the given instance.
+    pub unsafe fn set(&mut self, first_instance: usize) {
         let set_attributes = self.set_attributes;
         let get_shader     = self.shader;
-        let shader = get_shader(transmute(self.gl_data));
-
-        gl::UseProgram(shader.program);
-        self.texture.set(
-            shader.tex_uniform,
-            shader.sprite_size_uniform,
-            shader.frames_uniform,
-            self.frame_width as f32, self.frame_height as f32
-        );
-        set_attributes(self.vbo);
+        let gl_data: &mut GLData = transmute(self.gl_data);
+        gl_data.debug.set_sprite_type(self.sprite_type);
+        let (program, first_frame_uniform) = {
+            let shader = get_shader(gl_data);
+            (shader.program, shader.first_frame_uniform)
+        };
+
+        gl::UseProgram(program);
+        if gl_data.images.bound_texture != self.texture.id {
+            self.texture.bind();
+            gl_data.images.bound_texture = self.texture.id;
+        }
+        // Frames are looked up in the frame table from here.
+        gl::Uniform1i(first_frame_uniform, self.first_texcoord as GLint);
+        set_attributes(self.vbo, first_instance);
     }
 
+    pub fn has_sheet(&self) -> bool { !self.sheet.as_str().is_empty() }
+
+    // The texture is an atlas, which belongs to the registry.
     pub unsafe fn unload(&mut self) {
-        panic!("Unloading doesn't work yet hahahaha!");
+        gl::DeleteBuffers(1, &self.vbo);
+        self.vbo = 0;
+        self.instance_capacity = 0;
+        self.instance_count = 0;
     }
 }
 
-// Load a texture from the given filename into the GPU
-// memory, returning a struct holding the OpenGL ID and
-// dimensions.
-pub fn load_texture(filename: &'static str) -> Texture {
+// Reads an image with stb_image, forcing RGBA. The pixels have to be freed
+// with free_pixels.
+pub unsafe fn read_pixels(filename: &str) -> Result<(*const u8, i32, i32), String> {
     let mut width = 0; let mut height = 0; let mut comp = 0;
-    let mut tex_id: GLuint = 0;
+    let cfilename = CString::new(filename.to_string()).unwrap();
+    let img = stbi_load(cfilename.as_ptr(), &mut width, &mut height, &mut comp, 4);
+    if img.is_null() {
+        let reason = CStr::from_ptr(stbi_failure_reason()).to_string_lossy().into_owned();
+        return Err(format!("Couldn't load {}: {}", filename, reason));
+    }
+    Ok((img, width, height))
+}
 
+pub unsafe fn free_pixels(pixels: *const u8) {
+    stbi_image_free(pixels);
+}
+
+// Inflates zlib data (with the header) using stb_image's PNG inflater.
+pub fn zlib_decode(data: &[u8]) -> Option<Vec<u8>> {
     unsafe {
-        let cfilename = CString::new(filename.to_string()).unwrap();
-        let img = stbi_load(cfilename.as_ptr(), &mut width, &mut height, &mut comp, 4);
-        assert_eq!(comp, 4);
+        let mut len = 0;
+        let out = stbi_zlib_decode_malloc(data.as_ptr() as *const c_char, data.len() as c_int, &mut len);
+        if out.is_null() { return None }
+        let result = slice::from_raw_parts(out as *const u8, len as usize).to_vec();
+        stbi_image_free(out as *const u8);
+        Some(result)
+    }
+}
 
-        gl::GenTextures(1, &mut tex_id);
-        gl::BindTexture(gl::TEXTURE_2D, tex_id);
+// (Re)fills the given texture with RGBA pixels.
+unsafe fn upload_pixels(tex_id: GLuint, pixels: *const u8, width: i32, height: i32) {
+    gl::BindTexture(gl::TEXTURE_2D, tex_id);
+
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
 
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
 
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+    gl::TexImage2D(
+        gl::TEXTURE_2D, 0, gl::RGBA as i32,
+        width, height, 0, gl::RGBA,
+        gl::UNSIGNED_BYTE, transmute(pixels)
+    );
+}
 
+// Load a texture from the given filename into the GPU
+// memory, returning a struct holding the OpenGL ID and
+// dimensions.
+pub fn load_texture(filename: &str) -> Texture {
+    let mut tex_id: GLuint = 0;
+
+    let (width, height) = unsafe {
+        let (img, width, height) = match read_pixels(filename) {
+            Ok(p) => p,
+            Err(e) => panic!("{}", e)
+        };
+
+        gl::GenTextures(1, &mut tex_id);
         println!("Sending {} to GPU. Width: {} Height: {}", filename, width, height);
-        gl::TexImage2D(
-            gl::TEXTURE_2D, 0, gl::RGBA as i32,
-            width, height, 0, gl::RGBA,
-            gl::UNSIGNED_BYTE, transmute(img)
-        );
+        upload_pixels(tex_id, img, width, height);
 
         stbi_image_free(img);
-    }
+        (width, height)
+    };
 
     Texture {
         id: tex_id,
         width: width,
-        height: height,
-        filename: filename,
-        frame_texcoords_size: 0,
-        texcoords_space: &mut []
+        height: height
     }
 }
 
-pub fn create_program(vert: String, frag: String) -> Option<GLuint> {
-    let vert_id = make_shader!((vert): VERTEX_SHADER);
-    let vert_result: bool = check_log!(
-        "VERTEX SHADER",
-        GetShaderiv | GetShaderInfoLog
-        vert_id COMPILE_STATUS
-        println
-    );
-    if !vert_result {
-        unsafe { gl::DeleteShader(vert_id); }
-        return None;
-    }
-
-    let frag_id = make_shader!((frag): FRAGMENT_SHADER);
-    let frag_result: bool = check_log!(
-        "FRAGMENT SHADER",
-        GetShaderiv | GetShaderInfoLog
-        vert_id COMPILE_STATUS
-        println
-    );
-    if !frag_result {
-        unsafe { gl::DeleteShader(vert_id); }
-        unsafe { gl::DeleteShader(frag_id); }
-        return None;
-    }
-
-    let program_id = unsafe { gl::CreateProgram() };
+pub fn create_program(
+    vert: &GlslSource, frag: &GlslSource, sprite_type: &'static str
+) -> Result<GLuint, ShaderError> {
     unsafe {
+        let vert_id = try!(compile_shader(vert, gl::VERTEX_SHADER, ShaderStage::Vertex, sprite_type));
+        let frag_id = match compile_shader(frag, gl::FRAGMENT_SHADER, ShaderStage::Fragment, sprite_type) {
+            Ok(id) => id,
+            Err(e) => { gl::DeleteShader(vert_id); return Err(e) }
+        };
+
+        let program_id = gl::CreateProgram();
         gl::AttachShader(program_id, vert_id);
         gl::AttachShader(program_id, frag_id);
         gl::LinkProgram(program_id);
-    }
 
-    let link_result = check_log!(
-        "SHADER PROGRAM",
-        GetProgramiv | GetProgramInfoLog
-        program_id LINK_STATUS
-        println
-    );
-    if !link_result {
-        unsafe { gl::DeleteProgram(program_id); }
-        unsafe { gl::DeleteShader(vert_id); }
-        unsafe { gl::DeleteShader(frag_id); }
-        return None;
-    }
-
-    unsafe {
         gl::DeleteShader(vert_id);
         gl::DeleteShader(frag_id);
-    }
 
-    Some(program_id)
+        let mut status = 0;
+        gl::GetProgramiv(program_id, gl::LINK_STATUS, &mut status);
+        if status == 0 {
+            let log = info_log(program_id, true);
+            gl::DeleteProgram(program_id);
+            // Link errors don't come with usable locations.
+            return Err(ShaderError {
+                stage: ShaderStage::Link,
+                sprite_type: sprite_type,
+                messages: parse_info_log(&log, None)
+            });
+        }
+
+        Ok(program_id)
+    }
 }
 
+#[test]
+fn info_logs_point_at_files() {
+    let source = GlslSource {
+        text: String::new(),
+        files: vec!["a.vert".to_string(), "include/b.glsl".to_string()]
+    };
+    let log = "0:12(5): error: `foo' undeclared\nERROR: 2:3: 'bar' : syntax error\n1(7) : error C0000: nope\n";
+    let messages = parse_info_log(log, Some(&source));
+
+    assert_eq!(messages.len(), 3);
+    assert_eq!(messages[0].file, None);
+    assert_eq!((messages[0].line, messages[0].column), (Some(12), Some(5)));
+    assert_eq!(messages[1].file, Some("include/b.glsl".to_string()));
+    assert_eq!(messages[1].text, "'bar' : syntax error");
+    assert_eq!(messages[2].file, Some("a.vert".to_string()));
+    assert_eq!(messages[2].line, Some(7));
+}
@@ -0,0 +1,159 @@
+use gl::types::*;
+use std::cmp::Ordering;
+use std::mem::{size_of, transmute};
+use std::ptr;
+use std::slice;
+use assets::{SpriteType, Images, ImageHandle};
+use assets::registry::MAX_IMAGES;
+use gl_debug::GLDebug;
+use memory::{Arena, ArenaList};
+
+// Game code submits sprites here instead of poking at VBOs. At the end of
+// the frame everything gets sorted by layer, then depth (lowest first, so
+// higher draws on top), and runs of the same image are drawn with one
+// DrawElementsInstanced each.
+//
+// Everything it needs goes in the arena it's made with (normally
+// GameData::scratch), so a frame doesn't touch the heap.
+
+// Rough guide for layers; anything in between works too.
+pub const LAYER_BACKGROUND: i32 = -100;
+pub const LAYER_WORLD:      i32 = 0;
+pub const LAYER_FOREGROUND: i32 = 100;
+
+#[derive(Copy, Clone)]
+struct Entry {
+    image: usize,
+    // Filled in at draw time, so images sharing an atlas end up together.
+    texture: GLuint,
+    layer: i32,
+    depth: GLfloat,
+    // Submission order, so equal layer/depth keeps the order things came in.
+    order: usize,
+    // Where the instance's bytes are in RenderQueue::data.
+    offset: usize,
+    size:   usize
+}
+
+#[derive(Copy, Clone)]
+struct Batch {
+    image: usize,
+    // Within the image's instance buffer.
+    first: usize,
+    count: usize
+}
+
+pub struct RenderQueue<'a> {
+    arena:   &'a mut Arena,
+    entries: ArenaList<Entry>,
+    data:    ArenaList<u8>
+}
+
+impl<'a> RenderQueue<'a> {
+    pub fn new(arena: &'a mut Arena) -> RenderQueue<'a> {
+        RenderQueue { arena: arena, entries: ArenaList::new(), data: ArenaList::new() }
+    }
+
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    pub fn submit<T: SpriteType + Copy>(&mut self, image: ImageHandle<T>, layer: i32, depth: GLfloat, instance: T) {
+        let size = size_of::<T>();
+        let offset = self.data.len();
+        let bytes: &[u8] = unsafe { slice::from_raw_parts(transmute(&instance), size) };
+        for &byte in bytes.iter() { self.data.push(self.arena, byte); }
+
+        let order = self.entries.len();
+        self.entries.push(self.arena, Entry {
+            image: image.index, texture: 0, layer: layer, depth: depth, order: order,
+            offset: offset, size: size
+        });
+    }
+
+    fn sort(&mut self) {
+        self.entries.as_mut_slice().sort_by(|a, b| {
+            let orderings = [
+                a.layer.cmp(&b.layer),
+                a.depth.partial_cmp(&b.depth).unwrap_or(Ordering::Equal),
+                // Group textures, then images, together when nothing says
+                // which goes first.
+                a.texture.cmp(&b.texture),
+                a.image.cmp(&b.image),
+                a.order.cmp(&b.order)
+            ];
+            orderings.iter().cloned().find(|&o| o != Ordering::Equal).unwrap_or(Ordering::Equal)
+        });
+    }
+
+    // Runs of the same image, in draw order. Also gives how many instances
+    // each image has in total.
+    fn batches(&mut self, counts: &mut [usize; MAX_IMAGES]) -> ArenaList<Batch> {
+        let mut batches: ArenaList<Batch> = ArenaList::new();
+        for entry in self.entries.as_slice().iter() {
+            let continues = match batches.as_slice().last() {
+                Some(last) => last.image == entry.image,
+                None => false
+            };
+            if continues {
+                batches.as_mut_slice().last_mut().unwrap().count += 1;
+            }
+            else {
+                batches.push(self.arena, Batch { image: entry.image, first: counts[entry.image], count: 1 });
+            }
+            counts[entry.image] += 1;
+        }
+        batches
+    }
+
+    // Uploads and draws everything that was submitted, then empties the
+    // queue for the next frame. A GL error doesn't stop the rest from
+    // drawing, but the first one comes back.
+    pub unsafe fn draw(&mut self, images: &mut Images, debug: &mut GLDebug) -> Result<(), GLenum> {
+        for entry in self.entries.as_mut_slice().iter_mut() {
+            entry.texture = images.assets[entry.image].texture.id;
+        }
+        self.sort();
+        let mut counts = [0usize; MAX_IMAGES];
+        let batches = self.batches(&mut counts);
+
+        // === Upload ===
+        // Each image's instances go into its buffer in draw order, so every
+        // batch is a contiguous range.
+        let mut bytes: ArenaList<u8> = ArenaList::new();
+        for (index, &count) in counts.iter().enumerate() {
+            if count == 0 { continue }
+            let image = &mut images.assets[index];
+
+            bytes.clear();
+            for entry in self.entries.as_slice().iter().filter(|e| e.image == index) {
+                if entry.size != image.attributes_size {
+                    panic!("Submitted a {} byte instance of {}, which takes {} byte instances!",
+                           entry.size, image.name.as_str(), image.attributes_size);
+                }
+                for &byte in self.data.as_slice()[entry.offset..entry.offset + entry.size].iter() {
+                    bytes.push(self.arena, byte);
+                }
+            }
+
+            image.clear();
+            image.push_bytes(bytes.as_slice());
+        }
+        let mut result = gl_check!(debug);
+
+        // === Draw ===
+        // Who knows what got bound since last frame.
+        images.bound_texture = 0;
+        images.bind_frame_table();
+        for batch in batches.as_slice().iter() {
+            images.assets[batch.image].set(batch.first);
+            gl::DrawElementsInstanced(
+                gl::TRIANGLES, 6, gl::UNSIGNED_INT, ptr::null(), batch.count as GLsizei
+            );
+            let checked = gl_check!(debug);
+            if result.is_ok() { result = checked; }
+        }
+
+        self.entries.clear();
+        self.data.clear();
+        result
+    }
+}
@@ -0,0 +1,407 @@
+extern crate glfw;
+
+use gl::types::*;
+use std::env;
+use std::mem::swap;
+use glfw::{Action, Key};
+use vecmath::{Vec2, Rect};
+use controls::{Control, Controls};
+use movement::Movement;
+use physics;
+use memory::ArenaList;
+use level;
+use level::{LevelFile, Spawn};
+use tiled;
+use assets::registry::AssetString;
+use animation::Animations;
+use attachment::{Attachment, AttachmentPoints};
+use {GameData, CrattleCrute};
+
+// Everything in here is plain game logic -- no GL, no window. Keep it that
+// way so it can run headless (see headless.rs).
+
+// Longest frame the simulation will try to catch up on, in seconds.
+pub const MAX_FRAME_TIME: f32 = 0.25;
+// Fall below this and you start over from the top.
+pub const FALL_OUT_Y: GLfloat = -300.0;
+
+pub static CRATTLECRUTE_ANIMATIONS: &'static str = "assets/crattlecrute/body.png.info";
+pub static CRATTLECRUTE_POINTS: &'static str = "assets/crattlecrute/body.png.points";
+pub static CRATTLECRUTE_MOVEMENT: &'static str = "assets/crattlecrute/movement.info";
+pub static START_LEVEL: &'static str = "assets/levels/start.level";
+pub const CRATTLECRUTE_FRAME_COUNT: GLint = 9;
+
+pub fn lerp(from: Vec2<GLfloat>, to: Vec2<GLfloat>, alpha: GLfloat) -> Vec2<GLfloat> {
+    from + (to - from) * Vec2::s(alpha)
+}
+
+// Values that get refreshed on every load, so they can be tweaked with a
+// hot reload.
+pub fn tune(game: &mut GameData) {
+    game.sim_hz = 60.0;
+    // Roughly the body and feet of a 90x90 frame.
+    game.crattlecrute_hitbox = Rect::new(-16.0, -26.0, 32.0, 36.0);
+
+    game.crattlecrute_animations = match Animations::load(CRATTLECRUTE_ANIMATIONS) {
+        Ok(animations) => animations,
+        Err(e) => {
+            println!("Couldn't load crattlecrute animations -- {}", e);
+            Animations::single(CRATTLECRUTE_FRAME_COUNT)
+        }
+    };
+    game.movement = match Movement::load(CRATTLECRUTE_MOVEMENT) {
+        Ok(movement) => movement,
+        Err(e) => {
+            println!("Couldn't load crattlecrute movement -- {}", e);
+            Movement::defaults()
+        }
+    };
+    game.crattlecrute_points = match AttachmentPoints::load(CRATTLECRUTE_POINTS) {
+        Ok(points) => points,
+        Err(e) => {
+            println!("Couldn't load crattlecrute attachment points -- {}", e);
+            AttachmentPoints::empty()
+        }
+    };
+}
+
+// Called when a file under assets/ changes on disk. Returns false if it isn't
+// game data (images are handled by the registry). A broken file leaves the
+// old data in place, so a half-finished edit doesn't take the game down.
+pub fn asset_changed(game: &mut GameData, path: &str) -> bool {
+    if path == CRATTLECRUTE_ANIMATIONS {
+        match Animations::load(CRATTLECRUTE_ANIMATIONS) {
+            Ok(animations) => {
+                println!("Reloaded crattlecrute animations from {}", path);
+                game.crattlecrute_animations = animations;
+            }
+            Err(e) => println!("{} -- keeping the old animations.", e)
+        }
+        true
+    }
+    else if path == CRATTLECRUTE_MOVEMENT {
+        match Movement::load(CRATTLECRUTE_MOVEMENT) {
+            Ok(movement) => {
+                println!("Reloaded crattlecrute movement from {}", path);
+                game.movement = movement;
+            }
+            Err(e) => println!("{} -- keeping the old movement.", e)
+        }
+        true
+    }
+    else if path == CRATTLECRUTE_POINTS {
+        game.crattlecrute_points.reload_if_changed(CRATTLECRUTE_POINTS);
+        true
+    }
+    // (Tiled maps can keep their tilesets in other files.)
+    else if path == game.level_path.as_str() ||
+            (tiled::is_tileset(path) && tiled::is_map(game.level_path.as_str())) {
+        let level_path = game.level_path;
+        let path = level_path.as_str();
+        // The player stays where they are; everything else starts over.
+        match LevelFile::load(path, &game.manifest).and_then(|level| enter_level(game, &level, true)) {
+            Ok(()) => println!("Reloaded level from {}", path),
+            Err(e) => println!("{} -- keeping the old level.", e)
+        }
+        true
+    }
+    else { false }
+}
+
+// "RRGGBBAA" hex, or two of them for primary and secondary.
+fn colors(value: &str) -> Result<Vec2<u32>, String> {
+    let mut colors = Vec::new();
+    for word in value.split(' ').filter(|word| !word.is_empty()) {
+        match u32::from_str_radix(word, 16) {
+            Ok(color) => colors.push(color),
+            Err(_) => return Err(format!("{} isn't a color", word))
+        }
+    }
+    match colors.len() {
+        1 => Ok(Vec2::s(colors[0])),
+        2 => Ok(Vec2::new(colors[0], colors[1])),
+        _ => Err(format!("expected one or two colors, not `{}`", value))
+    }
+}
+
+// A crattlecrute standing at the spawn point, colored the way the spawn's
+// properties say. Properties it doesn't know about are left alone.
+fn spawn_crattlecrute(spawn: &Spawn) -> Result<CrattleCrute, String> {
+    let mut crattlecrute = CrattleCrute::new(spawn.position);
+    if let Some(value) = spawn.get("body color") {
+        crattlecrute.body_color = try!(colors(value));
+    }
+    if let Some(value) = spawn.get("feet color") {
+        crattlecrute.left_foot_color  = try!(colors(value));
+        crattlecrute.right_foot_color = crattlecrute.left_foot_color;
+    }
+    if let Some(value) = spawn.get("eye color") {
+        crattlecrute.eye_color = try!(colors(value)).x;
+    }
+    Ok(crattlecrute)
+}
+
+// Swaps in `level`: tiles, camera bounds, and everyone in it, standing at
+// their spawn points. With `keep_player` the player is left where they are
+// (for hot reloads). Nothing changes unless the whole level is good.
+pub fn enter_level(game: &mut GameData, level: &LevelFile, keep_player: bool) -> Result<(), String> {
+    // All of it goes in the spare arena until it's done.
+    game.spare_level_arena.reset();
+
+    let mut player = game.player;
+    let mut others = ArenaList::new();
+    for spawn in level.spawns.iter() {
+        let crattlecrute = match spawn.kind.as_str() {
+            "player" | "crattlecrute" => try!(
+                spawn_crattlecrute(spawn).map_err(|e| format!("{} at {} {}: {}", spawn.kind.as_str(), spawn.position.x, spawn.position.y, e))
+            ),
+            other => {
+                println!("Don't know how to spawn a {} -- skipping it.", other);
+                continue
+            }
+        };
+        if spawn.kind.is("player") {
+            if !keep_player { player = crattlecrute; }
+        }
+        else { try!(others.try_push(&mut game.spare_level_arena, crattlecrute)); }
+    }
+    let mut spawns = ArenaList::new();
+    for spawn in level.spawns.iter() {
+        try!(spawns.try_push(&mut game.spare_level_arena, *spawn));
+    }
+    let tilemap = try!(level.tilemap(&mut game.spare_level_arena));
+
+    game.tilemap = tilemap;
+    game.camera_bounds = level.camera_bounds;
+    game.player = player;
+    game.crattlecrutes = others;
+    game.spawns = spawns;
+    swap(&mut game.level_arena, &mut game.spare_level_arena);
+    Ok(())
+}
+
+// Writes the level as it is now back to where it came from (or next to it,
+// for Tiled maps). The file watcher then reloads it, which is harmless.
+pub fn save_level(game: &GameData) {
+    let path = level::save_path(game.level_path.as_str());
+    match LevelFile::from_game(game).save(&path) {
+        Ok(()) => println!("Saved level to {}", path),
+        Err(e) => println!("{}", e)
+    }
+}
+
+// Idle when standing still, walking otherwise.
+fn animate(
+    crattlecrute: &mut CrattleCrute,
+    animations:   &Animations,
+    points:       &AttachmentPoints,
+    walking:      bool,
+    dt:           GLfloat
+) {
+    let wanted = if walking { "walk" } else { "idle" };
+    match animations.find(wanted) {
+        Some(clip) => crattlecrute.animation.play(clip),
+        None => {}
+    }
+
+    crattlecrute.animation.update(animations, dt, |_event| {
+        // Nothing listens for animation events yet (footstep sounds, dust...).
+    });
+    crattlecrute.frame = crattlecrute.animation.sheet_frame(animations);
+
+    crattlecrute.eye = match points.find("eye") {
+        Some(eye) => points.get(eye, crattlecrute.frame),
+        None => Attachment::none()
+    };
+}
+
+// Fresh game state for a cold start.
+pub fn init_game(game: &mut GameData) {
+    game.sim_accumulator = 0.0;
+
+    game.player = CrattleCrute::new(Vec2::s(0.0));
+    // AF_LEVEL=assets/levels/whatever.tmj to start somewhere else.
+    let start = env::var("AF_LEVEL").unwrap_or(START_LEVEL.to_string());
+    game.level_path = AssetString::truncated(&start);
+    let entered =
+        if game.level_path.as_str() != start { Err(format!("{} is too long a path", start)) }
+        else { LevelFile::load(&start, &game.manifest).and_then(|level| enter_level(game, &level, false)) };
+    match entered {
+        Ok(()) => {}
+        Err(e) => {
+            println!("Couldn't load the level -- {}. Using the built in one.", e);
+            let level = LevelFile::parse(level::BUILT_IN).unwrap();
+            enter_level(game, &level, false).unwrap();
+        }
+    }
+    game.cam_pos = camera_target(game.player.position, &game.camera_bounds);
+    game.prev_cam_pos = game.cam_pos;
+}
+
+// Where the camera wants to be: on `target`, as far as `bounds` allow.
+pub fn camera_target(target: Vec2<GLfloat>, bounds: &Rect) -> Vec2<GLfloat> {
+    let clamp = |v: GLfloat, low: GLfloat, high: GLfloat| if v < low { low } else if v > high { high } else { v };
+    Vec2::new(clamp(target.x, bounds.x1, bounds.x2), clamp(target.y, bounds.y1, bounds.y2))
+}
+
+pub fn apply_key(controls: &mut Controls, key: Key, action: Action) {
+    let control = match key {
+        Key::W     => &mut controls.jump,
+        Key::Up    => &mut controls.jump,
+        Key::Space => &mut controls.jump,
+
+        Key::S    => &mut controls.down,
+        Key::Down => &mut controls.down,
+
+        Key::A    => &mut controls.left,
+        Key::Left => &mut controls.left,
+
+        Key::D     => &mut controls.right,
+        Key::Right => &mut controls.right,
+
+        Key::B => &mut controls.debug,
+
+        _ => return
+    };
+
+    match action {
+        Action::Press => control.this_frame = true,
+        Action::Release => control.this_frame = false,
+        _ => {}
+    }
+}
+
+// Runs as many fixed steps as `delta_t` (plus whatever was left over last
+// time) allows. Returns how far we are between the last step and the next
+// one, for interpolating while rendering.
+pub fn advance(game: &mut GameData, delta_t: f32) -> GLfloat {
+    if delta_t < 0.0 {
+        println!("Delta time < 0!!! {} -- treating it as 0.", delta_t);
+    }
+    else {
+        // Clamp so a long hitch (breakpoint, hot reload) doesn't leave us
+        // running hundreds of steps to catch up.
+        game.sim_accumulator += if delta_t > MAX_FRAME_TIME { MAX_FRAME_TIME } else { delta_t };
+    }
+
+    let dt = 1.0 / game.sim_hz;
+    while game.sim_accumulator >= dt {
+        game.player.save_previous();
+        for crattlecrute in game.crattlecrutes.as_mut_slice().iter_mut() {
+            crattlecrute.save_previous();
+        }
+        game.prev_cam_pos = game.cam_pos;
+
+        step(game, dt);
+        game.sim_accumulator -= dt;
+
+        // Edges (just_down/just_up) are only seen by one simulation step.
+        for control in game.controls.iter_mut() {
+            control.last_frame = control.this_frame;
+        }
+    }
+
+    game.sim_accumulator / dt
+}
+
+// Starts, buffers and cuts jumps. Runs before physics, so `grounded` is
+// from the last step.
+fn jump(crattlecrute: &mut CrattleCrute, button: &Control, movement: &Movement, dt: GLfloat) {
+    if crattlecrute.grounded {
+        crattlecrute.coyote_left = movement.coyote_time;
+    }
+    else {
+        crattlecrute.coyote_left -= dt;
+    }
+
+    if button.just_down() {
+        crattlecrute.jump_buffer_left = movement.jump_buffer;
+    }
+    else {
+        crattlecrute.jump_buffer_left -= dt;
+    }
+
+    if crattlecrute.jump_buffer_left > 0.0 && crattlecrute.coyote_left > 0.0 {
+        crattlecrute.velocity.y = movement.jump_speed;
+        crattlecrute.jumping = true;
+        crattlecrute.jump_buffer_left = 0.0;
+        crattlecrute.coyote_left = 0.0;
+        // A buffered tap that got let go of before landing is a short hop.
+        if !button.down() {
+            crattlecrute.velocity.y *= movement.jump_cut;
+            crattlecrute.jumping = false;
+        }
+    }
+    else if crattlecrute.jumping && button.just_up() {
+        crattlecrute.velocity.y *= movement.jump_cut;
+        crattlecrute.jumping = false;
+    }
+
+    if crattlecrute.velocity.y <= 0.0 {
+        crattlecrute.jumping = false;
+    }
+}
+
+// One fixed step of game logic. `dt` is always 1 / game.sim_hz.
+pub fn step(game: &mut GameData, dt: GLfloat) {
+    game.player.velocity.x = 0.0;
+    if game.controls.left.down() {
+        game.player.velocity.x -= game.movement.walk_speed;
+        game.player.flipped = true;
+    }
+    if game.controls.right.down() {
+        game.player.velocity.x += game.movement.walk_speed;
+        game.player.flipped = false;
+    }
+    jump(&mut game.player, &game.controls.jump, &game.movement, dt);
+    if game.controls.down.down() {
+        if game.player.flipped {
+            game.player.angle += 3.14159 * dt;
+        }
+        else {
+            game.player.angle -= 3.14159 * dt;
+        }
+    }
+
+    // === ANIMATION ===
+    let walking = game.controls.left.down() || game.controls.right.down();
+    let animations = &game.crattlecrute_animations;
+    let points     = &game.crattlecrute_points;
+    animate(&mut game.player, animations, points, walking, dt);
+    // Everyone else just walks in place to show off.
+    for crattlecrute in game.crattlecrutes.as_mut_slice().iter_mut() {
+        animate(crattlecrute, animations, points, true, dt);
+    }
+
+    // === PHYSICS! ===
+    let ref mut player = game.player;
+    player.acceleration = Vec2::new(0.0, -game.movement.gravity);
+
+    // Whatever the player could reach this step, plus a tile for good measure.
+    let mut shapes = ArenaList::new();
+    let reach = game.tilemap.tile_size +
+        (player.velocity.x.abs() + player.velocity.y.abs() + game.movement.gravity * dt) * dt;
+    let near = game.crattlecrute_hitbox.offset(player.position);
+    let near = Rect { x1: near.x1 - reach, y1: near.y1 - reach, x2: near.x2 + reach, y2: near.y2 + reach };
+    let scratch = &mut game.scratch;
+    game.tilemap.shapes_in(&near, |shape| shapes.push(scratch, shape));
+
+    let moved = physics::integrate(
+        &mut player.position, &mut player.velocity, player.acceleration,
+        &game.crattlecrute_hitbox, game.movement.max_fall_speed, shapes.as_slice(), player.grounded, dt
+    );
+    player.grounded = moved.hit_floor;
+
+    if player.position.y < FALL_OUT_Y {
+        player.position = match game.spawns.as_slice().iter().find(|s| s.kind.is("player")) {
+            Some(spawn) => spawn.position,
+            None => Vec2::s(0.0)
+        };
+        player.velocity = Vec2::s(0.0);
+        player.jumping = false;
+        // Don't interpolate across the teleport.
+        player.save_previous();
+    }
+
+    game.cam_pos = camera_target(player.position, &game.camera_bounds);
+}
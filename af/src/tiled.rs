@@ -0,0 +1,651 @@
+use gl::types::*;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use json;
+use json::Json;
+use xml;
+use xml::Element;
+use vecmath::{Vec2, Rect};
+use level::{LevelFile, LayerFile, Spawn, collision_named, collision_name};
+use tilemap::{TileId, Collision, MAX_TILE_KINDS};
+use animation::MAX_NAME_LEN;
+use render_queue::LAYER_FOREGROUND;
+use render::zlib_decode;
+use assets::registry::Manifest;
+
+// Reads maps made with Tiled (https://www.mapeditor.org), .tmx or .tmj, into
+// a LevelFile like any other level:
+//
+// - Tile layers become tile layers, one per tileset they use. Each tileset
+//   image has to be a SpriteType1 image in the manifest (like
+//   assets/terrain/dirt1.png, which is dirt_1), cut into frames the size of
+//   the map's tiles, with no margin or spacing.
+// - A tile's "collision" property says what it does (solid, empty, one way,
+//   slope up or slope down -- see tilemap.rs); tiles are solid by default.
+//   Collision goes by tile number, so tilesets sharing a map should agree.
+// - A tile layer's "collides" property can turn collision off for it, and
+//   "render layer" says where it draws (see render_queue.rs). By default
+//   layers draw over the crattlecrutes, later ones on top.
+// - Objects are spawns, with their type (or class) as what to spawn, like
+//   "player" or "crattlecrute", and their custom properties as the spawn's.
+//   An object of type "camera" is the level's camera bounds instead.
+//
+// The bottom left corner of the map ends up at (0, 0). Flipped tiles come out
+// unflipped, and infinite maps aren't supported.
+//
+// To start the game in one: AF_LEVEL=assets/levels/tiled-example.tmj
+
+// What's left of a gid once the flip flags are off.
+const GID_MASK: u32 = 0x0FFFFFFF;
+
+struct Tileset {
+    first_gid:   u32,
+    // Relative to the working directory, like the manifest's paths.
+    image:       String,
+    tile_width:  i32,
+    tile_height: i32,
+    tile_count:  u32,
+    // By the tile's number within the tileset.
+    collision:   Vec<(u32, Collision)>
+}
+
+struct TileLayer {
+    name:       String,
+    // Rows from the top, flip flags and all.
+    gids:       Vec<u32>,
+    properties: Vec<(String, String)>
+}
+
+struct Object {
+    kind: String,
+    x: GLfloat, y: GLfloat,
+    width: GLfloat, height: GLfloat,
+    // Tile objects are positioned by their bottom left corner instead of
+    // their top left.
+    tile: bool,
+    properties: Vec<(String, String)>
+}
+
+struct Map {
+    width:       i32,
+    height:      i32,
+    tile_width:  i32,
+    tile_height: i32,
+    tilesets:    Vec<Tileset>,
+    layers:      Vec<TileLayer>,
+    objects:     Vec<Object>
+}
+
+pub fn is_map(path: &str) -> bool {
+    path.ends_with(".tmx") || path.ends_with(".tmj")
+}
+
+pub fn is_tileset(path: &str) -> bool {
+    path.ends_with(".tsx") || path.ends_with(".tsj")
+}
+
+// Tilesets get drawn with whichever image in `manifest` has their file.
+pub fn load(filename: &str, manifest: &Manifest) -> Result<LevelFile, String> {
+    let text = try!(read(filename));
+    let map = if filename.ends_with(".tmx") {
+        try!(xml::parse(&text).and_then(|root| map_from_xml(filename, &root)).map_err(|e| format!("{}: {}", filename, e)))
+    } else {
+        try!(json::parse(&text).and_then(|root| map_from_json(filename, &root)).map_err(|e| format!("{}: {}", filename, e)))
+    };
+    convert(&map, &|tileset: &Tileset| manifest.image(
+        &tileset.image, tileset.tile_width as usize, tileset.tile_height as usize, tileset.tile_count as usize
+    )).map_err(|e| format!("{}: {}", filename, e))
+}
+
+fn read(filename: &str) -> Result<String, String> {
+    let mut contents = String::new();
+    match File::open(filename).and_then(|mut f| f.read_to_string(&mut contents)) {
+        Ok(_) => Ok(contents),
+        Err(e) => Err(format!("Couldn't read {}: {}", filename, e))
+    }
+}
+
+// `relative` as seen from the directory `base_file` is in, minus any "."s
+// and ".."s (so it matches paths in the manifest).
+fn resolve(base_file: &str, relative: &str) -> String {
+    let mut parts: Vec<&str> = match Path::new(base_file).parent().and_then(|dir| dir.to_str()) {
+        Some(dir) => dir.split('/').filter(|part| !part.is_empty()).collect(),
+        None => Vec::new()
+    };
+    for part in relative.split('/') {
+        match part {
+            "" | "." => {}
+            ".." if !parts.is_empty() && parts[parts.len() - 1] != ".." => { parts.pop(); }
+            _ => parts.push(part)
+        }
+    }
+
+    let mut result = String::new();
+    for (i, part) in parts.iter().enumerate() {
+        if i > 0 { result.push('/'); }
+        result.push_str(part);
+    }
+    result
+}
+
+// Tiled colors are #AARRGGBB (or #RRGGBB); ours are RRGGBBAA.
+fn property_value(kind: &str, value: &str) -> String {
+    if kind == "color" && value.starts_with("#") {
+        let hex = &value[1..];
+        if hex.len() == 8 { return format!("{}{}", &hex[2..], &hex[..2]).to_uppercase() }
+        if hex.len() == 6 { return format!("{}FF", hex).to_uppercase() }
+    }
+    value.to_string()
+}
+
+fn property<'a>(properties: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    properties.iter().find(|p| p.0 == name).map(|p| &p.1[..])
+}
+
+// Tile gids from base64 (optionally zlib compressed) little endian u32s.
+fn gids_from_base64(text: &str, compression: &str) -> Result<Vec<u32>, String> {
+    let bytes = try!(base64(text));
+    let bytes = match compression {
+        "" => bytes,
+        "zlib" => try!(zlib_decode(&bytes).ok_or("couldn't inflate tile data".to_string())),
+        other => return Err(format!("{} compressed tile data isn't supported (use CSV, or base64 with zlib)", other))
+    };
+    if bytes.len() % 4 != 0 {
+        return Err("tile data isn't a whole number of tiles".to_string());
+    }
+    Ok(bytes.chunks(4).map(|b|
+        b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24
+    ).collect())
+}
+
+fn base64(text: &str) -> Result<Vec<u8>, String> {
+    let mut result = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits: u32 = 0;
+    let mut bit_count = 0;
+    for b in text.bytes() {
+        let value = match b {
+            b'A'...b'Z' => b - b'A',
+            b'a'...b'z' => b - b'a' + 26,
+            b'0'...b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            b' ' | b'\t' | b'\n' | b'\r' => continue,
+            _ => return Err(format!("bad base64 character '{}'", b as char))
+        };
+        bits = (bits << 6) | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            result.push((bits >> bit_count) as u8);
+            bits &= (1 << bit_count) - 1;
+        }
+    }
+    Ok(result)
+}
+
+fn tile_collision(id: u32, properties: &[(String, String)], collision: &mut Vec<(u32, Collision)>) -> Result<(), String> {
+    match property(properties, "collision") {
+        Some(name) => match collision_named(name) {
+            Some(c) => { collision.push((id, c)); Ok(()) }
+            None => Err(format!("tile {} has collision `{}` (expected solid, empty, one way, slope up or slope down)", id, name))
+        },
+        None => Ok(())
+    }
+}
+
+// === .tmj (and .tsj) ===
+
+fn json_properties(value: &Json) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+    let list = match value.get("properties").and_then(|p| p.as_array()) {
+        Some(list) => list,
+        None => return result
+    };
+    for property in list.iter() {
+        let name = match property.get("name").and_then(|n| n.as_str()) {
+            Some(name) => name,
+            None => continue
+        };
+        let kind = property.get("type").and_then(|t| t.as_str()).unwrap_or("string");
+        let text = match property.get("value") {
+            Some(&Json::String(ref s)) => s.clone(),
+            Some(&Json::Number(n))     => format!("{}", n),
+            Some(&Json::Bool(b))       => format!("{}", b),
+            // (Class properties and the like.)
+            _ => continue
+        };
+        if text.is_empty() { continue }
+        result.push((name.to_string(), property_value(kind, &text)));
+    }
+    result
+}
+
+fn tileset_from_json(first_gid: u32, filename: &str, value: &Json) -> Result<Tileset, String> {
+    let image = match value.get("image").and_then(|i| i.as_str()) {
+        Some(image) => resolve(filename, image),
+        None => return Err("tilesets have to be one image, not a collection of images".to_string())
+    };
+    let margin  = value.get("margin").and_then(|m| m.as_i32()).unwrap_or(0);
+    let spacing = value.get("spacing").and_then(|s| s.as_i32()).unwrap_or(0);
+    if margin != 0 || spacing != 0 {
+        return Err(format!("{} has margin or spacing between tiles, which isn't supported", image));
+    }
+
+    let mut collision = Vec::new();
+    if let Some(tiles) = value.get("tiles").and_then(|t| t.as_array()) {
+        for tile in tiles.iter() {
+            let id = try!(tile.number("id")) as u32;
+            try!(tile_collision(id, &json_properties(tile), &mut collision));
+        }
+    }
+    Ok(Tileset {
+        first_gid:   first_gid,
+        image:       image,
+        tile_width:  try!(value.number("tilewidth")) as i32,
+        tile_height: try!(value.number("tileheight")) as i32,
+        tile_count:  try!(value.number("tilecount")) as u32,
+        collision:   collision
+    })
+}
+
+fn json_layers(filename: &str, layers: &[Json], map: &mut Map) -> Result<(), String> {
+    for layer in layers.iter() {
+        let name = layer.get("name").and_then(|n| n.as_str()).unwrap_or("");
+        match layer.get("type").and_then(|t| t.as_str()).unwrap_or("") {
+            "tilelayer" => {
+                let gids = match layer.get("data") {
+                    Some(&Json::Array(ref values)) =>
+                        values.iter().map(|v| v.as_f64().unwrap_or(0.0) as u32).collect(),
+                    Some(&Json::String(ref text)) => {
+                        let compression = layer.get("compression").and_then(|c| c.as_str()).unwrap_or("");
+                        try!(gids_from_base64(text, compression).map_err(|e| format!("layer {}: {}", name, e)))
+                    }
+                    _ => return Err(format!("layer {} has no data (infinite maps aren't supported)", name))
+                };
+                map.layers.push(TileLayer { name: name.to_string(), gids: gids, properties: json_properties(layer) });
+            }
+            "objectgroup" => {
+                for object in layer.get("objects").and_then(|o| o.as_array()).unwrap_or(&[]).iter() {
+                    let text = |key: &str| object.get(key).and_then(|v| v.as_str()).unwrap_or("");
+                    let number = |key: &str| object.get(key).and_then(|v| v.as_f64()).unwrap_or(0.0) as GLfloat;
+                    let kind = if text("type") != "" { text("type") } else if text("class") != "" { text("class") } else { text("name") };
+                    map.objects.push(Object {
+                        kind: kind.to_string(),
+                        x: number("x"), y: number("y"),
+                        width: number("width"), height: number("height"),
+                        tile: object.get("gid").is_some(),
+                        properties: json_properties(object)
+                    });
+                }
+            }
+            "group" => {
+                let children = layer.get("layers").and_then(|l| l.as_array()).unwrap_or(&[]);
+                try!(json_layers(filename, children, map));
+            }
+            other => println!("{}: skipping {} layer {}", filename, other, name)
+        }
+    }
+    Ok(())
+}
+
+fn map_from_json(filename: &str, root: &Json) -> Result<Map, String> {
+    if root.get("infinite").and_then(|i| i.as_bool()) == Some(true) {
+        return Err("infinite maps aren't supported".to_string());
+    }
+    let mut map = Map {
+        width:       try!(root.number("width")) as i32,
+        height:      try!(root.number("height")) as i32,
+        tile_width:  try!(root.number("tilewidth")) as i32,
+        tile_height: try!(root.number("tileheight")) as i32,
+        tilesets: Vec::new(), layers: Vec::new(), objects: Vec::new()
+    };
+
+    for tileset in root.get("tilesets").and_then(|t| t.as_array()).unwrap_or(&[]).iter() {
+        let first_gid = try!(tileset.number("firstgid")) as u32;
+        map.tilesets.push(match tileset.get("source").and_then(|s| s.as_str()) {
+            Some(source) => try!(external_tileset(first_gid, &resolve(filename, source))),
+            None => try!(tileset_from_json(first_gid, filename, tileset))
+        });
+    }
+    try!(json_layers(filename, root.get("layers").and_then(|l| l.as_array()).unwrap_or(&[]), &mut map));
+    Ok(map)
+}
+
+// === .tmx (and .tsx) ===
+
+fn xml_properties(element: &Element) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+    let properties = match element.child("properties") {
+        Some(properties) => properties,
+        None => return result
+    };
+    for property in properties.children_named("property") {
+        let name = match property.attribute("name") {
+            Some(name) => name,
+            None => continue
+        };
+        let kind = property.attribute("type").unwrap_or("string");
+        // Multi-line strings go in the text instead.
+        let value = property.attribute("value").unwrap_or(&property.text);
+        if value.is_empty() { continue }
+        result.push((name.to_string(), property_value(kind, value)));
+    }
+    result
+}
+
+fn tileset_from_xml(first_gid: u32, filename: &str, element: &Element) -> Result<Tileset, String> {
+    let image = match element.child("image").and_then(|i| i.attribute("source")) {
+        Some(image) => resolve(filename, image),
+        None => return Err("tilesets have to be one image, not a collection of images".to_string())
+    };
+    let margin  = element.number("margin").unwrap_or(0.0);
+    let spacing = element.number("spacing").unwrap_or(0.0);
+    if margin != 0.0 || spacing != 0.0 {
+        return Err(format!("{} has margin or spacing between tiles, which isn't supported", image));
+    }
+
+    let mut collision = Vec::new();
+    for tile in element.children_named("tile") {
+        let id = try!(tile.number("id")) as u32;
+        try!(tile_collision(id, &xml_properties(tile), &mut collision));
+    }
+    Ok(Tileset {
+        first_gid:   first_gid,
+        image:       image,
+        tile_width:  try!(element.number("tilewidth")) as i32,
+        tile_height: try!(element.number("tileheight")) as i32,
+        tile_count:  try!(element.number("tilecount")) as u32,
+        collision:   collision
+    })
+}
+
+fn xml_layers(filename: &str, parent: &Element, map: &mut Map) -> Result<(), String> {
+    for layer in parent.children.iter() {
+        let name = layer.attribute("name").unwrap_or("");
+        match &layer.name[..] {
+            "layer" => {
+                let data = try!(layer.child("data").ok_or(format!("layer {} has no data", name)));
+                if data.child("chunk").is_some() {
+                    return Err(format!("layer {} is in chunks (infinite maps aren't supported)", name));
+                }
+                let gids = match data.attribute("encoding") {
+                    Some("csv") => {
+                        let mut gids = Vec::new();
+                        for gid in data.text.split(',').map(|g| g.trim()).filter(|g| !g.is_empty()) {
+                            match gid.parse::<u32>() {
+                                Ok(gid) => gids.push(gid),
+                                Err(_) => return Err(format!("layer {}: bad tile {}", name, gid))
+                            }
+                        }
+                        gids
+                    }
+                    Some("base64") => {
+                        let compression = data.attribute("compression").unwrap_or("");
+                        try!(gids_from_base64(&data.text, compression).map_err(|e| format!("layer {}: {}", name, e)))
+                    }
+                    Some(other) => return Err(format!("layer {}: unknown encoding {}", name, other)),
+                    None => data.children_named("tile").map(|t| t.number("gid").unwrap_or(0.0) as u32).collect()
+                };
+                map.layers.push(TileLayer { name: name.to_string(), gids: gids, properties: xml_properties(layer) });
+            }
+            "objectgroup" => {
+                for object in layer.children_named("object") {
+                    let text = |key: &str| object.attribute(key).unwrap_or("");
+                    let number = |key: &str| object.number(key).unwrap_or(0.0) as GLfloat;
+                    let kind = if text("type") != "" { text("type") } else if text("class") != "" { text("class") } else { text("name") };
+                    map.objects.push(Object {
+                        kind: kind.to_string(),
+                        x: number("x"), y: number("y"),
+                        width: number("width"), height: number("height"),
+                        tile: object.attribute("gid").is_some(),
+                        properties: xml_properties(object)
+                    });
+                }
+            }
+            "group" => try!(xml_layers(filename, layer, map)),
+            "imagelayer" => println!("{}: skipping image layer {}", filename, name),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn map_from_xml(filename: &str, root: &Element) -> Result<Map, String> {
+    if root.name != "map" {
+        return Err(format!("expected <map>, not <{}>", root.name));
+    }
+    if root.attribute("infinite") == Some("1") {
+        return Err("infinite maps aren't supported".to_string());
+    }
+    let mut map = Map {
+        width:       try!(root.number("width")) as i32,
+        height:      try!(root.number("height")) as i32,
+        tile_width:  try!(root.number("tilewidth")) as i32,
+        tile_height: try!(root.number("tileheight")) as i32,
+        tilesets: Vec::new(), layers: Vec::new(), objects: Vec::new()
+    };
+
+    for tileset in root.children_named("tileset") {
+        let first_gid = try!(tileset.number("firstgid")) as u32;
+        map.tilesets.push(match tileset.attribute("source") {
+            Some(source) => try!(external_tileset(first_gid, &resolve(filename, source))),
+            None => try!(tileset_from_xml(first_gid, filename, tileset))
+        });
+    }
+    try!(xml_layers(filename, root, &mut map));
+    Ok(map)
+}
+
+// A .tsx or .tsj file. (Tiled lets either kind of map use either kind.)
+fn external_tileset(first_gid: u32, filename: &str) -> Result<Tileset, String> {
+    let text = try!(read(filename));
+    let tileset = if filename.ends_with(".tsx") {
+        xml::parse(&text).and_then(|root| tileset_from_xml(first_gid, filename, &root))
+    } else {
+        json::parse(&text).and_then(|root| tileset_from_json(first_gid, filename, &root))
+    };
+    tileset.map_err(|e| format!("{}: {}", filename, e))
+}
+
+// === Into a level ===
+
+// Our layer names are one word that fits in a Name.
+fn layer_name(name: &str, part: usize) -> String {
+    let mut result: String = name.chars().map(|c| if c == ' ' { '_' } else { c }).collect();
+    if result.is_empty() { result = "tiles".to_string(); }
+    if part > 0 { result = format!("{}_{}", result, part + 1); }
+    while result.len() > MAX_NAME_LEN { result.pop(); }
+    result
+}
+
+// `image_name` finds the manifest image to draw each tileset with.
+fn convert(map: &Map, image_name: &Fn(&Tileset) -> Result<String, String>) -> Result<LevelFile, String> {
+    if map.tile_width != map.tile_height {
+        return Err(format!("tiles are {}x{}; they have to be square", map.tile_width, map.tile_height));
+    }
+    let tile_size = map.tile_width as GLfloat;
+    let map_height = map.height as GLfloat * tile_size;
+    let mut level = LevelFile {
+        tile_size: tile_size, origin: Vec2::s(0.0),
+        width: map.width, height: map.height,
+        // Anywhere on the map, unless there's a camera object.
+        camera_bounds: Rect::new(0.0, 0.0, map.width as GLfloat * tile_size, map_height),
+        collision: Vec::new(), layers: Vec::new(), spawns: Vec::new()
+    };
+
+    // Our tile ids don't say which tileset they're from, so tilesets have to
+    // agree on what each id does. Which tileset said what about each id:
+    let mut said: Vec<(u32, Collision, usize)> = Vec::new();
+    let mut images = Vec::with_capacity(map.tilesets.len());
+    for (index, tileset) in map.tilesets.iter().enumerate() {
+        if tileset.tile_width != map.tile_width || tileset.tile_height != map.tile_height {
+            return Err(format!("{} has {}x{} tiles, but the map's are {}x{}",
+                tileset.image, tileset.tile_width, tileset.tile_height, map.tile_width, map.tile_height));
+        }
+        images.push(try!(image_name(tileset)));
+
+        for &(number, collision) in tileset.collision.iter() {
+            let id = number + 1;
+            if id as usize >= MAX_TILE_KINDS {
+                return Err(format!("only tiles below {} can have collision", MAX_TILE_KINDS - 1));
+            }
+            match said.iter().find(|s| s.0 == id) {
+                Some(&(_, other, by)) if other != collision => return Err(format!(
+                    "{} says tile {} is {}, but {} says it's {}",
+                    map.tilesets[by].image, number, collision_name(other), tileset.image, collision_name(collision)
+                )),
+                Some(_) => {}
+                None => said.push((id, collision, index))
+            }
+        }
+    }
+    for &(id, collision, _) in said.iter() {
+        if collision != Collision::Solid { level.collision.push((id as TileId, collision)); }
+    }
+
+    let (width, height) = (map.width as usize, map.height as usize);
+    for (index, layer) in map.layers.iter().enumerate() {
+        if layer.gids.len() != width * height {
+            return Err(format!("layer {} has {} tiles instead of {}", layer.name, layer.gids.len(), width * height));
+        }
+        let collides = property(&layer.properties, "collides") != Some("false");
+        let render_layer = match property(&layer.properties, "render layer").map(|l| l.parse::<i32>()) {
+            Some(Ok(l)) => l,
+            Some(Err(_)) => return Err(format!("layer {}'s render layer isn't a number", layer.name)),
+            None => LAYER_FOREGROUND + index as i32
+        };
+
+        // One of ours for each tileset this one uses.
+        let mut parts: Vec<(usize, LayerFile)> = Vec::new();
+        for (i, &gid) in layer.gids.iter().enumerate() {
+            let gid = gid & GID_MASK;
+            if gid == 0 { continue }
+            let tileset = match map.tilesets.iter().rposition(|t| t.first_gid <= gid) {
+                Some(tileset) => tileset,
+                None => return Err(format!("layer {} has tile {}, which isn't in any tileset", layer.name, gid))
+            };
+            let id = gid - map.tilesets[tileset].first_gid + 1;
+            if id > TileId::max_value() as u32 {
+                return Err(format!("layer {} uses tile {} of a tileset; that's too many", layer.name, id - 1));
+            }
+            // Tiles a tileset says nothing about are solid, unless another
+            // tileset made that id something else.
+            if collides && !map.tilesets[tileset].collision.iter().any(|c| c.0 + 1 == id) {
+                if let Some(&(_, collision, by)) = said.iter().find(|s| s.0 == id && s.1 != Collision::Solid) {
+                    return Err(format!(
+                        "layer {} has tile {} of {}, which is solid, but {} says it's {}",
+                        layer.name, id - 1, map.tilesets[tileset].image, map.tilesets[by].image, collision_name(collision)
+                    ));
+                }
+            }
+
+            let part = match parts.iter().position(|p| p.0 == tileset) {
+                Some(part) => part,
+                None => {
+                    let name = layer_name(&layer.name, parts.len());
+                    parts.push((tileset, LayerFile {
+                        name: name, tileset: images[tileset].clone(),
+                        render_layer: render_layer, collides: collides,
+                        rows: vec![vec![0; width]; height]
+                    }));
+                    parts.len() - 1
+                }
+            };
+            parts[part].1.rows[i / width][i % width] = id as TileId;
+        }
+        for (_, part) in parts.into_iter() {
+            level.layers.push(part);
+        }
+    }
+
+    for object in map.objects.iter() {
+        // Tiled's y goes down from the top of the map.
+        let bottom = if object.tile { object.y } else { object.y + object.height };
+        let rect = Rect::new(object.x, map_height - bottom, object.width, object.height);
+        if object.kind == "camera" {
+            level.camera_bounds = rect;
+            continue;
+        }
+
+        let middle = Vec2::new((rect.x1 + rect.x2) / 2.0, (rect.y1 + rect.y2) / 2.0);
+        // Objects with no type, class or name come through with an empty
+        // kind, which Spawn::new turns away.
+        let mut spawn = try!(Spawn::new(&object.kind.replace(" ", "_"), middle)
+            .map_err(|e| format!("object at {} {}: {}", object.x, object.y, e)));
+        for &(ref key, ref value) in object.properties.iter() {
+            try!(spawn.set(key, value).map_err(|e| format!("{} object: {}", object.kind, e)));
+        }
+        level.spawns.push(spawn);
+    }
+    Ok(level)
+}
+
+#[test]
+fn tmx_maps_become_levels() {
+    let map = map_from_xml("assets/levels/test.tmx", &xml::parse("<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+        <map version=\"1.10\" orientation=\"orthogonal\" width=\"3\" height=\"2\" tilewidth=\"16\" tileheight=\"16\" infinite=\"0\">
+         <tileset firstgid=\"1\" name=\"dirt\" tilewidth=\"16\" tileheight=\"16\" tilecount=\"2\" columns=\"2\">
+          <image source=\"../terrain/dirt.png\" width=\"32\" height=\"16\"/>
+          <tile id=\"1\"><properties><property name=\"collision\" value=\"one way\"/></properties></tile>
+         </tileset>
+         <layer id=\"1\" name=\"Tile Layer 1\" width=\"3\" height=\"2\">
+          <data encoding=\"csv\">
+        0,2,0,
+        1,1,2147483649
+        </data>
+         </layer>
+         <objectgroup id=\"2\" name=\"spawns\">
+          <object id=\"1\" type=\"crattlecrute\" x=\"8\" y=\"4\">
+           <properties><property name=\"eye color\" type=\"color\" value=\"#ffdd304a\"/></properties>
+           <point/>
+          </object>
+         </objectgroup>
+        </map>").unwrap()).unwrap();
+
+    let level = convert(&map, &|tileset: &Tileset| {
+        assert_eq!(tileset.image, "assets/terrain/dirt.png");
+        Ok("dirt".to_string())
+    }).unwrap();
+
+    assert_eq!(level.layers.len(), 1);
+    assert_eq!(level.layers[0].name, "Tile_Layer_1");
+    // (The last one's flipped.)
+    assert_eq!(level.layers[0].rows, vec![vec![0, 2, 0], vec![1, 1, 1]]);
+    assert_eq!(level.collision, vec![(2, Collision::OneWay)]);
+    assert_eq!((level.spawns[0].position.x, level.spawns[0].position.y), (8.0, 28.0));
+    assert_eq!(level.spawns[0].get("eye color"), Some("DD304AFF"));
+}
+
+#[test]
+fn tilesets_have_to_agree_on_collision() {
+    // dirt's tile 1 is one way, and rock's is whatever `second_tile` says.
+    // The layer has both, and collides or not.
+    let level = |second_tile: &str, collides: bool| {
+        let map = map_from_xml("assets/levels/test.tmx", &xml::parse(&format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+            <map version=\"1.10\" orientation=\"orthogonal\" width=\"2\" height=\"1\" tilewidth=\"16\" tileheight=\"16\" infinite=\"0\">
+             <tileset firstgid=\"1\" name=\"dirt\" tilewidth=\"16\" tileheight=\"16\" tilecount=\"2\" columns=\"2\">
+              <image source=\"../terrain/dirt.png\" width=\"32\" height=\"16\"/>
+              <tile id=\"1\"><properties><property name=\"collision\" value=\"one way\"/></properties></tile>
+             </tileset>
+             <tileset firstgid=\"3\" name=\"rock\" tilewidth=\"16\" tileheight=\"16\" tilecount=\"2\" columns=\"2\">
+              <image source=\"../terrain/rock.png\" width=\"32\" height=\"16\"/>
+              {}
+             </tileset>
+             <layer id=\"1\" name=\"ground\" width=\"2\" height=\"1\">
+              <properties><property name=\"collides\" type=\"bool\" value=\"{}\"/></properties>
+              <data encoding=\"csv\">2,4</data>
+             </layer>
+            </map>", second_tile, collides)).unwrap()).unwrap();
+        convert(&map, &|tileset: &Tileset| Ok(tileset.image.clone()))
+    };
+    let one_way = "<tile id=\"1\"><properties><property name=\"collision\" value=\"one way\"/></properties></tile>";
+    let solid   = "<tile id=\"1\"><properties><property name=\"collision\" value=\"solid\"/></properties></tile>";
+
+    assert_eq!(level(one_way, true).unwrap().collision, vec![(2, Collision::OneWay)]);
+    assert!(level(solid, false).is_err());
+    // Saying nothing means solid, which only matters where it collides.
+    assert!(level("", true).is_err());
+    assert!(level("", false).is_ok());
+}
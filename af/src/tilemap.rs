@@ -0,0 +1,297 @@
+use gl::types::*;
+use std::ptr;
+use std::slice;
+use vecmath::{Vec2, Rect};
+use animation::{Name, MAX_NAME_LEN};
+use assets::registry::AssetString;
+use memory::Arena;
+use physics::Shape;
+
+// Level geometry as a grid of tiles. Each layer is drawn with its own
+// tileset (a SpriteType1 image in the manifest, one frame per tile), and
+// layers marked `collides` are what physics runs into, going by what
+// `collision` says about each tile id.
+//
+// Tile (0, 0) is the bottom left one, sitting at `origin`. Plain game logic
+// like sim.rs -- rendering just asks for the visible tiles (each_visible).
+
+pub const MAX_TILE_LAYERS: usize = 4;
+// Collision is looked up by tile id; anything past this is solid.
+pub const MAX_TILE_KINDS: usize = 256;
+// Tiles get drawn a chunk at a time (CHUNK_TILES x CHUNK_TILES).
+pub const CHUNK_TILES: i32 = 16;
+
+// 0 is no tile. Anything else is frame (id - 1) of the layer's tileset.
+pub type TileId = u16;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Collision {
+    Empty,
+    Solid,
+    // Can be jumped up through and stood on.
+    OneWay,
+    // 45 degree slopes going up to the right / down to the right.
+    SlopeUp,
+    SlopeDown
+}
+
+#[derive(Copy, Clone)]
+pub struct TileLayer {
+    pub name:    Name,
+    pub tileset: AssetString,
+    // See render_queue; tiles on a layer all share a depth.
+    pub render_layer: i32,
+    pub collides: bool,
+    // width * height ids, bottom row first. Lives in GameData::level_arena.
+    tiles: *mut TileId
+}
+
+pub struct Tilemap {
+    pub origin:    Vec2<GLfloat>,
+    pub tile_size: GLfloat,
+    pub width:     i32,
+    pub height:    i32,
+    pub layers:    [TileLayer; MAX_TILE_LAYERS],
+    pub layer_count: usize,
+    pub collision: [Collision; MAX_TILE_KINDS]
+}
+
+// Inclusive start, exclusive end.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TileRange {
+    pub x1: i32, pub y1: i32,
+    pub x2: i32, pub y2: i32
+}
+
+impl TileLayer {
+    fn empty() -> TileLayer {
+        TileLayer {
+            name: Name::empty(),
+            tileset: AssetString::new("").unwrap(),
+            render_layer: 0,
+            collides: false,
+            tiles: ptr::null_mut()
+        }
+    }
+}
+
+impl Tilemap {
+    // No layers and no tiles. Every id except 0 starts out solid.
+    pub fn new(origin: Vec2<GLfloat>, tile_size: GLfloat, width: i32, height: i32) -> Tilemap {
+        let mut collision = [Collision::Solid; MAX_TILE_KINDS];
+        collision[0] = Collision::Empty;
+        Tilemap {
+            origin: origin, tile_size: tile_size,
+            width: width, height: height,
+            layers: [TileLayer::empty(); MAX_TILE_LAYERS],
+            layer_count: 0,
+            collision: collision
+        }
+    }
+
+    pub fn empty() -> Tilemap { Tilemap::new(Vec2::s(0.0), 16.0, 0, 0) }
+
+    // Adds a layer with no tiles on it, returning its index.
+    pub fn add_layer(
+        &mut self,
+        arena:        &mut Arena,
+        name:         &str,
+        tileset:      &str,
+        render_layer: i32,
+        collides:     bool
+    ) -> Result<usize, String> {
+        if self.layer_count == MAX_TILE_LAYERS {
+            return Err(format!("more than {} tile layers", MAX_TILE_LAYERS));
+        }
+        if name.len() > MAX_NAME_LEN {
+            return Err(format!("layer name {} is longer than {} bytes", name, MAX_NAME_LEN));
+        }
+        let tileset = try!(AssetString::new(tileset).ok_or(format!("tileset {} is too long", tileset)));
+        if self.width < 0 || self.height < 0 {
+            return Err(format!("{}x{} isn't a size", self.width, self.height));
+        }
+        let count = try!((self.width as usize).checked_mul(self.height as usize)
+            .ok_or(format!("{}x{} is too many tiles", self.width, self.height)));
+        let tiles = unsafe {
+            let tiles = try!(arena.try_alloc::<TileId>(count).map_err(|e| format!("layer {}: {}", name, e)));
+            ptr::write_bytes(tiles, 0, count);
+            tiles
+        };
+
+        let index = self.layer_count;
+        self.layers[index] = TileLayer {
+            name: Name::new(name), tileset: tileset,
+            render_layer: render_layer, collides: collides,
+            tiles: tiles
+        };
+        self.layer_count += 1;
+        Ok(index)
+    }
+
+    pub fn layers(&self) -> &[TileLayer] { &self.layers[..self.layer_count] }
+
+    pub fn tiles(&self, layer: usize) -> &[TileId] {
+        let count = (self.width * self.height) as usize;
+        if count == 0 { return &[] }
+        unsafe { slice::from_raw_parts(self.layers[layer].tiles, count) }
+    }
+
+    pub fn tiles_mut(&mut self, layer: usize) -> &mut [TileId] {
+        let count = (self.width * self.height) as usize;
+        if count == 0 { return &mut [] }
+        unsafe { slice::from_raw_parts_mut(self.layers[layer].tiles, count) }
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && x < self.width && y < self.height
+    }
+
+    // Outside the map is empty.
+    pub fn get(&self, layer: usize, x: i32, y: i32) -> TileId {
+        if !self.contains(x, y) { return 0 }
+        self.tiles(layer)[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, layer: usize, x: i32, y: i32, id: TileId) {
+        if !self.contains(x, y) { return }
+        let width = self.width;
+        self.tiles_mut(layer)[(y * width + x) as usize] = id;
+    }
+
+    pub fn collision_of(&self, id: TileId) -> Collision {
+        if (id as usize) < MAX_TILE_KINDS { self.collision[id as usize] } else { Collision::Solid }
+    }
+
+    pub fn tile_rect(&self, x: i32, y: i32) -> Rect {
+        Rect::new(
+            self.origin.x + x as GLfloat * self.tile_size,
+            self.origin.y + y as GLfloat * self.tile_size,
+            self.tile_size, self.tile_size
+        )
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect::new(
+            self.origin.x, self.origin.y,
+            self.width as GLfloat * self.tile_size, self.height as GLfloat * self.tile_size
+        )
+    }
+
+    // Tiles touching `area`, clipped to the map.
+    pub fn range(&self, area: &Rect) -> TileRange {
+        let clamp = |v: i32, max: i32| if v < 0 { 0 } else if v > max { max } else { v };
+        let x1 = ((area.x1 - self.origin.x) / self.tile_size).floor() as i32;
+        let y1 = ((area.y1 - self.origin.y) / self.tile_size).floor() as i32;
+        let x2 = ((area.x2 - self.origin.x) / self.tile_size).floor() as i32 + 1;
+        let y2 = ((area.y2 - self.origin.y) / self.tile_size).floor() as i32 + 1;
+        TileRange {
+            x1: clamp(x1, self.width), y1: clamp(y1, self.height),
+            x2: clamp(x2, self.width), y2: clamp(y2, self.height)
+        }
+    }
+
+    // Calls `f` with the bottom left corner and id of every tile on `layer`
+    // in the chunks that `view` touches. Going by whole chunks means what's
+    // drawn only changes when the camera crosses a chunk edge.
+    pub fn each_visible<F>(&self, layer: usize, view: &Rect, mut f: F)
+        where F: FnMut(Vec2<GLfloat>, TileId)
+    {
+        let range = self.range(view);
+        if range.x1 >= range.x2 || range.y1 >= range.y2 { return }
+        let chunk_start = |v: i32| v / CHUNK_TILES * CHUNK_TILES;
+        let chunk_end   = |v: i32, max: i32| {
+            let end = (v + CHUNK_TILES - 1) / CHUNK_TILES * CHUNK_TILES;
+            if end > max { max } else { end }
+        };
+
+        let tiles = self.tiles(layer);
+        for y in chunk_start(range.y1)..chunk_end(range.y2, self.height) {
+            for x in chunk_start(range.x1)..chunk_end(range.x2, self.width) {
+                let id = tiles[(y * self.width + x) as usize];
+                if id != 0 { f(self.tile_rect(x, y).pos(), id); }
+            }
+        }
+    }
+
+    // Collision shapes for the colliding tiles touching `area`. Runs of solid
+    // or one-way tiles in a row come out as one long box, so nothing catches
+    // on the seams between them. Each one goes to `f`.
+    pub fn shapes_in<F: FnMut(Shape)>(&self, area: &Rect, mut f: F) {
+        let range = self.range(area);
+        for layer in 0..self.layer_count {
+            if !self.layers[layer].collides { continue }
+
+            for y in range.y1..range.y2 {
+                // Start of the run we're in, and what it's made of.
+                let mut run: Option<(i32, Collision)> = None;
+                for x in range.x1..range.x2 + 1 {
+                    let collision =
+                        if x < range.x2 { self.collision_of(self.get(layer, x, y)) }
+                        else { Collision::Empty };
+
+                    match run {
+                        Some((_, kind)) if kind == collision => continue,
+                        Some((start, kind)) => {
+                            let rect = Rect {
+                                x1: self.tile_rect(start, y).x1, y1: self.tile_rect(start, y).y1,
+                                x2: self.tile_rect(x, y).x1,     y2: self.tile_rect(start, y).y2
+                            };
+                            f(match kind {
+                                Collision::OneWay => Shape::OneWay(rect),
+                                _                 => Shape::Solid(rect)
+                            });
+                            run = None;
+                        }
+                        None => {}
+                    }
+
+                    match collision {
+                        Collision::Solid | Collision::OneWay => run = Some((x, collision)),
+                        Collision::SlopeUp   => f(Shape::Slope(self.tile_rect(x, y), true)),
+                        Collision::SlopeDown => f(Shape::Slope(self.tile_rect(x, y), false)),
+                        Collision::Empty => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn boxes_walk_up_slopes_and_land_on_one_way_platforms() {
+    use physics::{move_through, SKIN};
+    use std::mem::transmute;
+
+    let mut memory = vec![0u8; 4096];
+    let mut arena = Arena::new(unsafe { transmute(&mut memory[0]) }, memory.len());
+
+    // A floor, a slope up to a ledge, and a one-way platform off to the left.
+    let mut map = Tilemap::new(Vec2::new(0.0, 0.0), 16.0, 8, 4);
+    map.collision[2] = Collision::SlopeUp;
+    map.collision[3] = Collision::OneWay;
+    let layer = map.add_layer(&mut arena, "ground", "dirt_1", 0, true).unwrap();
+    for x in 0..8 { map.set(layer, x, 0, 1); }
+    map.set(layer, 4, 1, 2);
+    map.set(layer, 5, 1, 1);
+    map.set(layer, 0, 2, 3);
+    map.set(layer, 1, 2, 3);
+
+    let mut shapes = Vec::new();
+    map.shapes_in(&map.bounds(), |shape| shapes.push(shape));
+
+    // Walking right along the floor and onto the slope.
+    let walker = Rect::new(50.0, 16.0 + SKIN, 10.0, 10.0);
+    let moved = move_through(&walker, Vec2::new(8.0, -1.0), &shapes, true);
+    let walked = walker.offset(moved.delta);
+    assert!(moved.hit_floor);
+    assert!((walked.y1 - (16.0 + (walked.x2 - 64.0) + SKIN)).abs() < 0.001);
+
+    // Jumping up through the platform, then coming down onto it.
+    let jumper = Rect::new(4.0, 20.0, 10.0, 10.0);
+    let moved = move_through(&jumper, Vec2::new(0.0, 30.0), &shapes, false);
+    assert!(!moved.hit_ceiling);
+    let jumped = jumper.offset(moved.delta);
+    let moved = move_through(&jumped, Vec2::new(0.0, -30.0), &shapes, false);
+    assert!(moved.hit_floor);
+    assert!((jumped.offset(moved.delta).y1 - (48.0 + SKIN)).abs() < 0.001);
+}
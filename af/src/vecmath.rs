@@ -24,7 +24,7 @@ impl Rect {
         Rect {
             x1: x, y1: y,
             x2: x + width,
-            y2: y + width
+            y2: y + height
         }
     }
 
@@ -38,6 +38,16 @@ impl Rect {
     pub fn height(&self) -> GLfloat {
         self.y2 - self.y1
     }
+
+    pub fn offset(&self, by: Vec2<GLfloat>) -> Rect {
+        Rect { x1: self.x1 + by.x, y1: self.y1 + by.y, x2: self.x2 + by.x, y2: self.y2 + by.y }
+    }
+
+    // Touching edges don't count.
+    pub fn overlaps(&self, other: &Rect) -> bool {
+        self.x1 < other.x2 && other.x1 < self.x2 &&
+        self.y1 < other.y2 && other.y1 < self.y2
+    }
 }
 
 impl<T: Add + Copy> Add for Vec2<T> where T::Output: Copy {
@@ -0,0 +1,218 @@
+use std::str;
+
+// Just enough XML to read Tiled maps and tilesets: elements, attributes and
+// text. Comments, the <?xml ?> declaration and doctypes get skipped; CDATA
+// and namespaces aren't a thing here.
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Element {
+    pub name:       String,
+    pub attributes: Vec<(String, String)>,
+    pub children:   Vec<Element>,
+    // Everything directly inside, minus the children.
+    pub text:       String
+}
+
+impl Element {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|a| a.0 == name).map(|a| &a.1[..])
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> Box<Iterator<Item = &'a Element> + 'a> {
+        Box::new(self.children.iter().filter(move |c| c.name == name))
+    }
+
+    // attribute(name) as a number, with an error that says what was missing.
+    pub fn number(&self, name: &str) -> Result<f64, String> {
+        match self.attribute(name).map(|a| a.parse::<f64>()) {
+            Some(Ok(n)) => Ok(n),
+            _ => Err(format!("expected a number for {} on <{}>", name, self.name))
+        }
+    }
+}
+
+// The root element.
+pub fn parse(text: &str) -> Result<Element, String> {
+    let mut parser = Parser { bytes: text.as_bytes(), pos: 0 };
+    try!(parser.skip_misc());
+    if parser.peek() != Some(b'<') {
+        return Err(parser.error("expected an element"));
+    }
+    let root = try!(parser.element());
+    try!(parser.skip_misc());
+    if parser.pos != parser.bytes.len() {
+        return Err(parser.error("trailing characters"));
+    }
+    Ok(root)
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+
+fn is_space(b: u8) -> bool { b == b' ' || b == b'\t' || b == b'\n' || b == b'\r' }
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> String {
+        let line = self.bytes[..self.pos].iter().filter(|&&b| b == b'\n').count() + 1;
+        format!("XML line {}: {}", line, message)
+    }
+
+    fn peek(&self) -> Option<u8> { self.bytes.get(self.pos).cloned() }
+
+    fn starts_with(&self, s: &str) -> bool { self.bytes[self.pos..].starts_with(s.as_bytes()) }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b) = self.peek() {
+            if is_space(b) { self.pos += 1 } else { break }
+        }
+    }
+
+    // Up to and past `end`.
+    fn skip_past(&mut self, end: &str) -> Result<(), String> {
+        while self.pos < self.bytes.len() {
+            if self.starts_with(end) { self.pos += end.len(); return Ok(()) }
+            self.pos += 1;
+        }
+        Err(self.error(&format!("expected {}", end)))
+    }
+
+    // Whitespace, comments, <?...?> and <!...> between elements.
+    fn skip_misc(&mut self) -> Result<(), String> {
+        loop {
+            self.skip_whitespace();
+            if self.starts_with("<!--")    { try!(self.skip_past("-->")); }
+            else if self.starts_with("<?") { try!(self.skip_past("?>")); }
+            else if self.starts_with("<!") { try!(self.skip_past(">")); }
+            else { return Ok(()) }
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let start = self.pos;
+        while let Some(b) = self.peek() {
+            if is_space(b) || b == b'=' || b == b'>' || b == b'/' { break }
+            self.pos += 1;
+        }
+        if self.pos == start { return Err(self.error("expected a name")) }
+        Ok(str::from_utf8(&self.bytes[start..self.pos]).unwrap().to_string())
+    }
+
+    // Text up to `end`, with entities replaced.
+    fn text_until(&mut self, end: u8) -> Result<String, String> {
+        let start = self.pos;
+        while let Some(b) = self.peek() {
+            if b == end { break }
+            self.pos += 1;
+        }
+        let raw = match str::from_utf8(&self.bytes[start..self.pos]) {
+            Ok(raw) => raw,
+            Err(_) => return Err(self.error("text isn't UTF-8"))
+        };
+        self.unescape(raw)
+    }
+
+    fn unescape(&self, raw: &str) -> Result<String, String> {
+        let mut result = String::with_capacity(raw.len());
+        let mut rest = raw;
+        while let Some(amp) = rest.find('&') {
+            result.push_str(&rest[..amp]);
+            rest = &rest[amp..];
+            let semicolon = match rest.find(';') {
+                Some(i) => i,
+                None => return Err(self.error("unterminated entity"))
+            };
+            let entity = &rest[1..semicolon];
+            let c = match entity {
+                "amp" => Some('&'), "lt" => Some('<'), "gt" => Some('>'),
+                "quot" => Some('"'), "apos" => Some('\''),
+                _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(::std::char::from_u32),
+                _ if entity.starts_with("#")  => entity[1..].parse::<u32>().ok().and_then(::std::char::from_u32),
+                _ => None
+            };
+            match c {
+                Some(c) => result.push(c),
+                None => return Err(self.error(&format!("unknown entity &{};", entity)))
+            }
+            rest = &rest[semicolon + 1..];
+        }
+        result.push_str(rest);
+        Ok(result)
+    }
+
+    fn element(&mut self) -> Result<Element, String> {
+        self.pos += 1;
+        let name = try!(self.name());
+        let mut element = Element { name: name, attributes: Vec::new(), children: Vec::new(), text: String::new() };
+
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b'/') => {
+                    self.pos += 1;
+                    if self.peek() != Some(b'>') { return Err(self.error("expected '>'")) }
+                    self.pos += 1;
+                    return Ok(element);
+                }
+                Some(b'>') => { self.pos += 1; break }
+                Some(_) => {
+                    let attribute = try!(self.name());
+                    self.skip_whitespace();
+                    if self.peek() != Some(b'=') { return Err(self.error("expected '='")) }
+                    self.pos += 1;
+                    self.skip_whitespace();
+                    let quote = match self.peek() {
+                        Some(q) if q == b'"' || q == b'\'' => q,
+                        _ => return Err(self.error("expected a quoted value"))
+                    };
+                    self.pos += 1;
+                    let value = try!(self.text_until(quote));
+                    if self.peek() != Some(quote) { return Err(self.error("unterminated attribute")) }
+                    self.pos += 1;
+                    element.attributes.push((attribute, value));
+                }
+                None => return Err(self.error("unexpected end of file"))
+            }
+        }
+
+        loop {
+            let text = try!(self.text_until(b'<'));
+            element.text.push_str(&text);
+            if self.peek().is_none() { return Err(self.error(&format!("<{}> is never closed", element.name))) }
+
+            if self.starts_with("</") {
+                self.pos += 2;
+                let closing = try!(self.name());
+                if closing != element.name {
+                    return Err(self.error(&format!("</{}> closes <{}>", closing, element.name)));
+                }
+                self.skip_whitespace();
+                if self.peek() != Some(b'>') { return Err(self.error("expected '>'")) }
+                self.pos += 1;
+                return Ok(element);
+            }
+            else if self.starts_with("<!--") {
+                try!(self.skip_past("-->"));
+            }
+            else {
+                element.children.push(try!(self.element()));
+            }
+        }
+    }
+}
+
+#[test]
+fn elements_attributes_and_text() {
+    let root = parse("<?xml version=\"1.0\"?>\n<!-- a map -->\n<map width='2'>\
+                      <data encoding=\"csv\">1,&#50;</data><empty a=\"&lt;&amp;\"/></map>").unwrap();
+    assert_eq!(root.name, "map");
+    assert_eq!(root.number("width"), Ok(2.0));
+    assert_eq!(root.child("data").map(|d| &d.text[..]), Some("1,2"));
+    assert_eq!(root.child("empty").and_then(|e| e.attribute("a")), Some("<&"));
+    assert!(parse("<map><data></map>").is_err());
+}
@@ -0,0 +1,57 @@
+# Writes assets/crattlecrute/body.png.points from asset-dev/crattlecrute.blend,
+# for af/src/attachment.rs. Every empty named "point.<name>" (like point.eye)
+# is an attachment point; each frame of the scene's frame range is a sheet
+# frame, rendered by the scene camera at the scene's render resolution.
+#
+#     blender asset-dev/crattlecrute.blend --background --python asset-dev/export_points.py
+#
+# (or open it in Blender's text editor and Run Script). The game picks the
+# file up while it's running.
+#
+# Each line is `<name> frame <n> = x y angle`:
+#   - n counts from 1, like the .png.info files.
+#   - x y are whole sheet pixels from the middle of the frame to the point,
+#     with y going up.
+#   - angle is in radians, counterclockwise, of the empty's X axis as the
+#     camera sees it.
+
+import bpy
+import math
+import os
+from bpy_extras.object_utils import world_to_camera_view
+from mathutils import Vector
+
+OUTPUT = os.path.join(os.path.dirname(bpy.data.filepath), "..", "assets", "crattlecrute", "body.png.points")
+PREFIX = "point."
+
+scene  = bpy.context.scene
+camera = scene.camera
+scale  = scene.render.resolution_percentage / 100.0
+width  = scene.render.resolution_x * scale
+height = scene.render.resolution_y * scale
+
+points = sorted([o for o in scene.objects if o.name.startswith(PREFIX)], key=lambda o: o.name)
+if not points:
+    raise Exception("No empties named " + PREFIX + "<name> to export")
+
+lines = []
+start_frame = scene.frame_current
+for point in points:
+    name = point.name[len(PREFIX):]
+    for n, frame in enumerate(range(scene.frame_start, scene.frame_end + 1)):
+        scene.frame_set(frame)
+
+        view = world_to_camera_view(scene, camera, point.matrix_world.to_translation())
+        x = int(round(view.x * width  - width  / 2.0))
+        y = int(round(view.y * height - height / 2.0))
+
+        to_camera = camera.matrix_world.to_3x3().inverted() * point.matrix_world.to_3x3()
+        axis  = to_camera * Vector((1.0, 0.0, 0.0))
+        angle = math.atan2(axis.y, axis.x)
+
+        lines.append("%s frame %d = %d %d %f" % (name, n + 1, x, y, angle))
+scene.frame_set(start_frame)
+
+with open(os.path.normpath(OUTPUT), "w") as f:
+    f.write("\n".join(lines) + "\n")
+print("Wrote %d attachment points to %s" % (len(lines), os.path.normpath(OUTPUT)))
@@ -0,0 +1,14 @@
+// Shared by every sprite fragment shader.
+
+in vec2 texcoord;
+out vec4 color;
+uniform sampler2D tex;
+
+bool approx(vec4 a, vec4 b, float alpha)
+{
+    vec4 diff = abs(a - b);
+    return diff.x <= alpha &&
+           diff.y <= alpha &&
+           diff.z <= alpha &&
+           diff.w <= alpha;
+}
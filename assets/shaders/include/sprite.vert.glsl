@@ -0,0 +1,95 @@
+// Shared by every sprite vertex shader. The per vertex/instance attributes
+// are generated from the shader_assets! declarations in af/src/assets/mod.rs.
+
+// Every image's frames (see af/src/assets/frame_table.rs): two texels per
+// frame, texcoords (bottom left xy, top right zw) then size and offset from
+// the pivot in pixels.
+uniform highp sampler2D frame_table;
+uniform int first_frame; // this image's first frame in the table
+uniform vec2 screen_size;
+uniform vec2 cam_pos;     // in pixels
+uniform float scale;
+
+out vec2 texcoord;
+
+// Set by load_frame.
+vec2 sprite_size; // in pixels
+vec4 frame_rect;
+vec2 frame_offset; // in pixels, from `position` to the middle of the frame
+
+vec4 frame_texel(int i)
+{
+    return texelFetch(frame_table, ivec2(i % FRAME_TABLE_WIDTH, i / FRAME_TABLE_WIDTH), 0);
+}
+
+// Call this before anything uses sprite_size. Frames live in an atlas, so
+// there is no "whole texture" frame -1.
+void load_frame(int frame)
+{
+    int i = (first_frame + max(frame, 0)) * 2;
+    frame_rect  = frame_texel(i);
+    vec4 size   = frame_texel(i + 1);
+    sprite_size  = size.xy;
+    frame_offset = size.zw;
+}
+
+// frame_offset, flipped and rotated along with the sprite. Only trimmed
+// frames and frames with a pivot have one.
+vec2 pivot_offset(int flipped, float angle)
+{
+    vec2 offset = flipped != 0 ? vec2(-frame_offset.x, frame_offset.y) : frame_offset;
+    return vec2(
+        offset.x * cos(angle) - offset.y * sin(angle),
+        offset.x * sin(angle) + offset.y * cos(angle)
+    );
+}
+
+// Same corner order as gl_VertexID: top right, bottom right, bottom left,
+// top left.
+vec2 frame_texcoord(int index)
+{
+    if (index == 0) return frame_rect.zw;
+    if (index == 1) return frame_rect.zy;
+    if (index == 2) return frame_rect.xy;
+    return frame_rect.xw;
+}
+
+vec2 from_pixel(vec2 pos)
+{
+    return pos / screen_size;
+}
+ivec2 from_pixel(ivec2 pos)
+{
+    return pos / ivec2(screen_size);
+}
+
+int flipped_vertex_id()
+{
+    return 3 - gl_VertexID;
+}
+
+// These are I suppose an optimization for squares
+// rotating about their centers.
+const float VERT_DIST = 1.41421356237;
+const float ANGLE_OFFSETS[4] = float[4](
+    // pi/4
+    0.78539816339,
+    // 7pi/4
+    5.49778714378,
+    // 5pi/4
+    3.92699081699,
+    // 3pi/4
+    2.35619449019
+);
+
+vec4 color_from(int color)
+{
+    // Totally assumes little endian...
+    int red = int((uint(color) & 0xFF000000u) >> 24u);
+    return vec4(
+        float(red) / 256.0,
+        float((color & 0x00FF0000) >> 16) / 256.0,
+        float((color & 0x0000FF00) >> 8)  / 256.0,
+        float( color & 0x000000FF)        / 256.0
+    );
+}
@@ -0,0 +1,6 @@
+#include "include/sprite.frag.glsl"
+
+void main()
+{
+    color = texture(tex, texcoord);
+}
@@ -0,0 +1,15 @@
+// No rotation or color swapping - just frames and flipping.
+#include "include/sprite.vert.glsl"
+
+void main()
+{
+    load_frame(frame);
+    vec2 pixel_screen_pos = (position + pivot_offset(flipped, 0.0) - cam_pos) * 2.0;
+    gl_Position = vec4(
+        (vertex_pos * from_pixel(sprite_size) + from_pixel(pixel_screen_pos)) * scale,
+        0.0f, 1.0f
+    );
+    int index = flipped != 0 ? flipped_vertex_id() : gl_VertexID;
+    texcoord = frame_texcoord(index);
+    texcoord.y = 1.0 - texcoord.y;
+}
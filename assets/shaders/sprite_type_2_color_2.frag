@@ -0,0 +1,16 @@
+#include "include/sprite.frag.glsl"
+
+in vec4 cswap1_from;
+in vec4 cswap1_to;
+in vec4 cswap2_from;
+in vec4 cswap2_to;
+
+void main()
+{
+    color = texture(tex, texcoord);
+
+    if (approx(color, cswap1_from, 0.1))
+        color = cswap1_to;
+    else if (approx(color, cswap2_from, 0.1))
+        color = cswap2_to;
+}
@@ -0,0 +1,30 @@
+// Frames, flipping, rotates around center, 2 colors can be swapped.
+// Position refers to the center of the sprite.
+#include "include/sprite.vert.glsl"
+
+out vec4 cswap1_from;
+out vec4 cswap1_to;
+out vec4 cswap2_from;
+out vec4 cswap2_to;
+
+void main()
+{
+    load_frame(frame);
+    vec2 pixel_screen_pos = (position + pivot_offset(flipped, angle) - cam_pos) * 2.0;
+
+    float vert_angle = angle + ANGLE_OFFSETS[gl_VertexID];
+    vec2 vert = VERT_DIST * vec2(cos(vert_angle), sin(vert_angle));
+
+    gl_Position = vec4(
+        (vert * from_pixel(sprite_size) + from_pixel(pixel_screen_pos)) * scale,
+        0.0f, 1.0f
+    );
+    int index = flipped != 0 ? flipped_vertex_id() : gl_VertexID;
+    texcoord = frame_texcoord(index);
+    texcoord.y = 1.0 - texcoord.y;
+
+    cswap1_from = color_from(color_swap_1.x);
+    cswap1_to   = color_from(color_swap_1.y);
+    cswap2_from = color_from(color_swap_2.x);
+    cswap2_to   = color_from(color_swap_2.y);
+}
@@ -0,0 +1,12 @@
+#include "include/sprite.frag.glsl"
+
+in vec4 cswap_from;
+in vec4 cswap_to;
+
+void main()
+{
+    color = texture(tex, texcoord);
+
+    if (approx(color, cswap_from, 0.1))
+        color = cswap_to;
+}
@@ -0,0 +1,32 @@
+// Rotates around given focal point, 1 color swap.
+#include "include/sprite.vert.glsl"
+
+out vec4 cswap_from;
+out vec4 cswap_to;
+
+void main()
+{
+    load_frame(frame);
+    vec2 pixel_screen_pos = (position + pivot_offset(flipped, angle) - cam_pos) * 2.0;
+    vec2 effective_focus = flipped == 0 ?
+        vec2(focus) : vec2(sprite_size.x - float(focus.x), focus.y);
+
+    vec2 pixel_offset = vertex_pos * sprite_size - effective_focus * 2.0;
+    float pixel_angle = angle + atan(pixel_offset.y, pixel_offset.x);
+    vec2 direction    = vec2(cos(pixel_angle), sin(pixel_angle));
+    float distance    = sqrt(dot(pixel_offset, pixel_offset));
+
+    vec2 vert = from_pixel(distance * direction);
+
+    gl_Position = vec4(
+        (vert + from_pixel(pixel_screen_pos)) * scale,
+        0.0f, 1.0f
+    );
+
+    int index = flipped != 0 ? flipped_vertex_id() : gl_VertexID;
+    texcoord = frame_texcoord(index);
+    texcoord.y = 1.0 - texcoord.y;
+
+    cswap_from = color_from(color_swap.x);
+    cswap_to   = color_from(color_swap.y);
+}
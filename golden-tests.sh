@@ -0,0 +1,12 @@
+#!/bin/sh
+# Runs the golden image tests in af/src/capture.rs with Mesa's software
+# renderer (llvmpipe) on a virtual X server, so no GPU or display is needed.
+# Needs xvfb-run and Mesa.
+#
+#     ./golden-tests.sh                      compare against assets/golden
+#     AF_UPDATE_GOLDEN=1 ./golden-tests.sh   rewrite assets/golden instead
+set -e
+cd "$(dirname "$0")/af"
+LIBGL_ALWAYS_SOFTWARE=1 GALLIUM_DRIVER=llvmpipe \
+    xvfb-run -a -s "-screen 0 640x480x24" \
+    cargo test sprite_shaders_match_golden_images -- --ignored "$@"
@@ -1,10 +1,43 @@
 use std::sync::mpsc::Sender;
-use std::mem::{uninitialized};
-use libc::{c_long};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::mem::{uninitialized, transmute, size_of};
+use std::ffi::CString;
+use std::slice;
+use std::str;
+use libc::{c_long, c_int, c_char, c_void, c_short};
 
 pub static GAME_LIB_DIR: &'static str = "./af/target/debug/";
 pub static GAME_LIB_PATH: &'static str = "./af/target/debug/libaf.so";
 pub static GAME_LIB_FILE: &'static str = "./libaf.so";
+pub static GAME_LIB_NAME: &'static str = "libaf.so";
+pub static ASSETS_DIR: &'static str = "assets";
+
+#[repr(C)]
+struct InotifyEvent {
+    wd:     c_int,
+    mask:   u32,
+    cookie: u32,
+    len:    u32,
+    // Followed by `len` bytes of null-padded file name.
+}
+
+impl InotifyEvent {
+    pub fn file_name(&self) -> &str { unsafe {
+        let first: *const u8 = transmute((self as *const InotifyEvent).offset(1));
+        let bytes = slice::from_raw_parts(first, self.len as usize);
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        str::from_utf8(&bytes[..end]).unwrap_or("")
+    }}
+}
+
+#[repr(C)]
+struct PollFd {
+    fd:      c_int,
+    events:  c_short,
+    revents: c_short
+}
 
 #[repr(C)]
 struct Timespec {
@@ -16,12 +49,148 @@ extern "C" {
     fn clock_getcpuclockid(pid: i32, clock_id: *mut usize) -> isize;
     fn clock_getres(clock_id: usize, res: *mut Timespec) -> isize;
     fn clock_gettime(clock_id: usize, tp: *mut Timespec) -> isize;
+
+    fn inotify_init() -> c_int;
+    fn inotify_add_watch(fd: c_int, pathname: *const c_char, mask: u32) -> c_int;
+    fn read(fd: c_int, buf: *mut c_void, count: usize) -> isize;
+    fn poll(fds: *mut PollFd, nfds: u64, timeout: c_int) -> c_int;
 }
 
 const CLOCK_MONOTOMIC_RAW: usize = 4;
 
+const IN_CLOSE_WRITE: u32 = 0x00000008;
+const IN_MOVED_TO:    u32 = 0x00000080;
+// Cargo hard links the finished lib in from deps/, which is only a create.
+const IN_CREATE:      u32 = 0x00000100;
+const WATCH_MASK:     u32 = IN_CLOSE_WRITE | IN_MOVED_TO | IN_CREATE;
+const POLLIN: c_short = 0x001;
+
+// How long the lib has to sit untouched before we consider the build done.
+// Cargo tends to write the .so more than once in quick succession.
+const SETTLE_TIME_MS: c_int = 250;
+
+// Reads whatever events are pending on the inotify fd and hands each one to
+// `f`. Returns false if the read failed. The buffer is u32s so that events
+// (which the kernel pads out to keep aligned) can be read in place.
+unsafe fn read_events<F>(fd: c_int, buffer: &mut [u32], mut f: F) -> bool
+    where F: FnMut(&InotifyEvent)
+{
+    let start = buffer.as_mut_ptr() as *mut u8;
+    let bytes_read = read(fd, start as *mut c_void, buffer.len() * size_of::<u32>());
+    if bytes_read <= 0 { return false }
+
+    let mut offset = 0usize;
+    while offset < bytes_read as usize {
+        let event = &*(start.offset(offset as isize) as *const InotifyEvent);
+        if event.len > 0 { f(event); }
+        offset += size_of::<InotifyEvent>() + event.len as usize;
+    }
+    true
+}
+
+// Calls `read_more` until nothing has happened on `fd` for SETTLE_TIME_MS.
+unsafe fn wait_for_quiet<F>(fd: c_int, mut read_more: F) where F: FnMut() {
+    let mut poll_fd = PollFd { fd: fd, events: POLLIN, revents: 0 };
+    loop {
+        poll_fd.revents = 0;
+        match poll(&mut poll_fd, 1, SETTLE_TIME_MS) {
+            0 => break,
+            n if n < 0 => { println!("poll on inotify fd failed"); break }
+            _ => read_more()
+        }
+    }
+}
+
 pub fn watch_for_updated_game_lib(ref sender: &Sender<()>) {
-    println!("on linux - no hot code update for now!");
+    unsafe {
+        let fd = inotify_init();
+        if fd < 0 { panic!("inotify_init failed, no hot code reload!") }
+
+        let dylib_dir_str = CString::new(GAME_LIB_DIR).unwrap();
+        if inotify_add_watch(fd, dylib_dir_str.as_ptr(), WATCH_MASK) < 0 {
+            panic!("Couldn't watch {} for game lib changes", GAME_LIB_DIR);
+        }
+
+        // Room for plenty of events with max length file names.
+        let mut buffer = [0u32; 1024];
+
+        loop {
+            // Blocks until something in the directory changes.
+            let mut lib_written = false;
+            let read_ok = read_events(fd, &mut buffer, |event| {
+                if event.file_name() == GAME_LIB_NAME { lib_written = true; }
+            });
+            if !read_ok { println!("Failed to read inotify events for {}", GAME_LIB_DIR); }
+            if !lib_written { continue; }
+
+            // Swallow the burst of duplicate events until the lib settles down.
+            wait_for_quiet(fd, || { read_events(fd, &mut buffer, |_| {}); });
+
+            if sender.send(()).is_err() { return }
+        }
+    }
+}
+
+// inotify doesn't do subdirectories on its own, so every directory under
+// `dir` gets its own watch.
+unsafe fn watch_recursively(fd: c_int, dir: &Path, watched: &mut HashMap<c_int, String>) {
+    let dir_string = dir.to_str().unwrap().to_string();
+    let dir_cstr = CString::new(dir_string.clone()).unwrap();
+    let wd = inotify_add_watch(fd, dir_cstr.as_ptr(), WATCH_MASK);
+    if wd < 0 {
+        println!("Couldn't watch {} for asset changes", dir_string);
+        return;
+    }
+    watched.insert(wd, dir_string);
+
+    match fs::read_dir(dir) {
+        Ok(entries) => for entry in entries {
+            match entry {
+                Ok(entry) => if fs::metadata(&entry.path()).map(|m| m.is_dir()).unwrap_or(false) {
+                    watch_recursively(fd, &entry.path(), watched);
+                },
+                Err(_) => {}
+            }
+        },
+        Err(e) => println!("Couldn't list {}: {}", dir_string, e)
+    }
+}
+
+// Sends the path (like "assets/crattlecrute/body.png") of every file under
+// ASSETS_DIR that gets written to. Each file is sent once per burst of writes.
+pub fn watch_for_updated_assets(ref sender: &Sender<String>) {
+    unsafe {
+        let fd = inotify_init();
+        if fd < 0 { println!("inotify_init failed, no asset hot reload!"); return }
+
+        let mut watched = HashMap::new();
+        watch_recursively(fd, Path::new(ASSETS_DIR), &mut watched);
+
+        let mut buffer = [0u32; 1024];
+        loop {
+            let mut changed: Vec<String> = Vec::new();
+            {
+                let mut collect = |event: &InotifyEvent| {
+                    match watched.get(&event.wd) {
+                        Some(dir) => {
+                            let path = format!("{}/{}", dir, event.file_name());
+                            if !changed.contains(&path) { changed.push(path); }
+                        }
+                        None => {}
+                    }
+                };
+                if !read_events(fd, &mut buffer, |e| collect(e)) {
+                    println!("Failed to read inotify events for {}", ASSETS_DIR);
+                    continue;
+                }
+                wait_for_quiet(fd, || { read_events(fd, &mut buffer, |e| collect(e)); });
+            }
+
+            for path in changed.into_iter() {
+                if sender.send(path).is_err() { return }
+            }
+        }
+    }
 }
 
 pub fn query_performance_counter(counter: &mut i64) {
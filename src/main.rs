@@ -19,6 +19,9 @@ pub mod osx;
 #[cfg(target_os = "macos")]
 use osx as platform;
 
+pub mod memory;
+pub mod replay;
+
 use std::path::Path;
 use std::fs;
 use glfw::{Context};
@@ -26,21 +29,40 @@ use libc::c_void;
 use std::dynamic_lib::DynamicLibrary;
 use std::thread;
 // use std::c_str::ToCStr;
-use std::mem::{transmute, uninitialized, drop};
+use std::mem::{transmute, uninitialized, zeroed, drop};
 use std::sync::mpsc::channel;
 use std::sync::mpsc::Receiver;
+use memory::{GameMemory, MemoryStorage};
+use replay::Replay;
+
+// GLData holds the whole image registry, so this has to be roomy.
+const GL_MEMORY_SIZE: usize = 128 * 1024;
+const REPLAY_FILE: &'static str = "./input.afreplay";
+
+// Must match af::MemoryLayout!
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq)]
+struct MemoryLayout {
+    game_data_version: u32,
+    game_data_size:    usize,
+    gl_data_version:   u32,
+    gl_data_size:      usize
+}
+
+type LayoutFn = extern "C" fn () -> MemoryLayout;
 
 type LoadFn = extern "C" fn (
     bool, // first load?
-    &mut u8, // GameData
+    &MemoryLayout, // layout the memory was last loaded with
+    &mut GameMemory, // GameData + game allocations
     &mut u8, // GLData
     &glfw::Glfw,
     &glfw::Window,
     *const c_void // glfw _data
-);
+) -> bool; // false if the memory couldn't be migrated to the new layout
 
 type UpdateFn = extern "C" fn (
-    &mut u8, // GameData
+    &mut GameMemory, // GameData + game allocations
     &mut u8, // GLData
     f32, // delta time
     &glfw::Glfw,
@@ -49,6 +71,13 @@ type UpdateFn = extern "C" fn (
     i64
 );
 
+type AssetChangedFn = extern "C" fn (
+    *const u8, usize, // path of the changed file, relative to cwd
+    &mut GameMemory, // GameData + game allocations
+    &mut u8, // GLData
+    &glfw::Window
+);
+
 // Glfw shit
 extern "C" {
     pub static _glfw: *const c_void;
@@ -70,8 +99,13 @@ fn load_game_lib() -> DynamicLibrary {
     }
 }
 
-fn load_symbols_from(lib: &DynamicLibrary) -> (LoadFn, UpdateFn) {
+fn load_symbols_from(lib: &DynamicLibrary) -> (LayoutFn, LoadFn, UpdateFn, AssetChangedFn) {
     unsafe {
+        let layout: LayoutFn = match lib.symbol::<u8>("memory_layout") {
+            Ok(f) => transmute(f),
+            Err(e) => panic!("Couldn't grab memory_layout symbol from game lib! {}", e)
+        };
+
         let load: LoadFn = match lib.symbol::<u8>("load") {
             Ok(f) => transmute(f),
             Err(e) => panic!("Couldn't grab update symbol from game lib! {}", e)
@@ -82,12 +116,37 @@ fn load_symbols_from(lib: &DynamicLibrary) -> (LoadFn, UpdateFn) {
             Err(e) => panic!("Couldn't grab load symbol from game lib! {}", e)
         };
 
-        (load, update)
+        let asset_changed: AssetChangedFn = match lib.symbol::<u8>("asset_changed") {
+            Ok(f) => transmute(f),
+            Err(e) => panic!("Couldn't grab asset_changed symbol from game lib! {}", e)
+        };
+
+        (layout, load, update, asset_changed)
+    }
+}
+
+// The game lib doesn't get to grow its structs past what we've allocated.
+fn check_layout_fits(layout: &MemoryLayout, memory: &GameMemory) {
+    if layout.game_data_size > memory.permanent_size {
+        panic!(
+            "GameData (v{}) is {} bytes, but only {} bytes of permanent memory are allocated!",
+            layout.game_data_version, layout.game_data_size, memory.permanent_size
+        );
+    }
+    if layout.gl_data_size > GL_MEMORY_SIZE {
+        panic!(
+            "GLData (v{}) is {} bytes, but only {} bytes of GL memory are allocated!",
+            layout.gl_data_version, layout.gl_data_size, GL_MEMORY_SIZE
+        );
     }
 }
 
 fn main() {
-    let glfw = glfw::init(glfw::FAIL_ON_ERRORS).unwrap();
+    let mut glfw = glfw::init(glfw::FAIL_ON_ERRORS).unwrap();
+    // So the game can get KHR_debug messages (see af/src/gl_debug.rs).
+    if cfg!(debug_assertions) {
+        glfw.window_hint(glfw::WindowHint::OpenglDebugContext(true));
+    }
 
     let (mut window, events) = glfw
         .create_window(500, 500, "Hello this is window", glfw::WindowMode::Windowed)
@@ -99,22 +158,34 @@ fn main() {
     window.set_size_polling(true);
     window.make_current();
 
-    let mut game_memory = unsafe { Box::new([uninitialized::<u8>(); 4096]) };
-    let mut gl_memory   = unsafe { Box::new([uninitialized::<u8>(); 1024]) };
+    let mut memory_storage = MemoryStorage::from_args();
+    let mut game_memory    = memory_storage.game_memory();
+    let mut gl_memory      = unsafe { Box::new([uninitialized::<u8>(); GL_MEMORY_SIZE]) };
+
+    // Window events go through the replay system before the game sees them.
+    let (game_event_sender, game_events) = channel();
+    let mut replay = Replay::new(REPLAY_FILE);
 
 
     copy_game_lib_to_cwd();
     let mut game_lib = load_game_lib();
-    let (mut load, mut update) = load_symbols_from(&game_lib);
+    let (mut layout, mut load, mut update, mut asset_changed) = load_symbols_from(&game_lib);
+    let mut current_layout = layout();
+    check_layout_fits(&current_layout, &game_memory);
 
     let (game_lib_sender, game_lib_receiver) = channel();
+    let (asset_sender, asset_receiver) = channel();
     unsafe {
+        let _t = thread::Builder::new().name("Asset Watcher".to_string()).spawn(
+            move || platform::watch_for_updated_assets(&asset_sender)
+        );
         let _t = thread::Builder::new().name("Game Lib Updater".to_string()).spawn(
             move || platform::watch_for_updated_game_lib(&game_lib_sender)
         );
         load(
             true,
-            transmute(&mut game_memory[0]),
+            &current_layout,
+            &mut game_memory,
             transmute(&mut gl_memory[0]),
             &glfw, &window,
             _glfw
@@ -134,28 +205,68 @@ fn main() {
                     drop(game_lib);
                     copy_game_lib_to_cwd();
                     game_lib = load_game_lib();
-                    match load_symbols_from(&game_lib) { (l, u) => { load = l; update = u } }
+                    match load_symbols_from(&game_lib) {
+                        (m, l, u, a) => { layout = m; load = l; update = u; asset_changed = a }
+                    }
 
-                    load(
+                    let new_layout = layout();
+                    check_layout_fits(&new_layout, &game_memory);
+
+                    let migrated = load(
                         false,
-                        transmute(&mut game_memory[0]),
+                        &current_layout,
+                        &mut game_memory,
                         transmute(&mut gl_memory[0]),
                         &glfw, &window,
                         _glfw
                     );
+                    if new_layout != current_layout && replay.active() {
+                        // The snapshot was taken with the old layout.
+                        println!("Memory layout changed, so the recorded snapshot is useless now.");
+                        replay.stop();
+                    }
+                    if !migrated {
+                        println!("Game memory layout changed and couldn't be migrated. Cold restarting!");
+                        memory_storage.clear();
+                        *gl_memory = zeroed();
+                        load(
+                            true,
+                            &new_layout,
+                            &mut game_memory,
+                            transmute(&mut gl_memory[0]),
+                            &glfw, &window,
+                            _glfw
+                        );
+                    }
+                    current_layout = new_layout;
                 }
                 _ => {}
             }
 
+            while let Ok(path) = asset_receiver.try_recv() {
+                asset_changed(
+                    path.as_ptr(), path.len(),
+                    &mut game_memory,
+                    transmute(&mut gl_memory[0]),
+                    &window
+                );
+            }
+
             let delta_time =
                 if last_frame_time <= 0 { 1.0/60.0 }
                 else { ((this_frame_time - last_frame_time) as f32) / ticks_per_second };
 
+            glfw.poll_events();
+            let live_events = glfw::flush_messages(&events).collect();
+            let delta_time = replay.frame(
+                &mut memory_storage, delta_time, live_events, &game_event_sender
+            );
+
             update(
-                transmute(&mut game_memory[0]),
+                &mut game_memory,
                 transmute(&mut gl_memory[0]),
                 delta_time,
-                &glfw, &window, &events,
+                &glfw, &window, &game_events,
                 this_frame_time
             );
 
@@ -0,0 +1,90 @@
+use std::env;
+use std::mem::transmute;
+
+pub static DEFAULT_PERMANENT_KB: usize = 16 * 1024;
+pub static DEFAULT_TRANSIENT_KB: usize = 4 * 1024;
+
+// Handed to the game lib on every load and update. The pointers never
+// change for the lifetime of the process, so anything the game allocates
+// in here survives a library swap.
+//
+// Must match af::memory::GameMemory!
+#[repr(C)]
+pub struct GameMemory {
+    // GameData lives at the start of this. The rest is the game's to
+    // allocate entity lists and such out of.
+    pub permanent:      *mut u8,
+    pub permanent_size: usize,
+    // Scratch space that the game is free to clobber every frame.
+    pub transient:      *mut u8,
+    pub transient_size: usize
+}
+
+// Owns the actual storage behind a GameMemory.
+pub struct MemoryStorage {
+    permanent: Box<[u8]>,
+    transient: Box<[u8]>
+}
+
+impl MemoryStorage {
+    pub fn new(permanent_size: usize, transient_size: usize) -> MemoryStorage {
+        println!(
+            "Allocating {}KB permanent and {}KB transient game memory",
+            permanent_size / 1024, transient_size / 1024
+        );
+        MemoryStorage {
+            permanent: vec![0u8; permanent_size].into_boxed_slice(),
+            transient: vec![0u8; transient_size].into_boxed_slice()
+        }
+    }
+
+    // Sizes come from --permanent-kb=N and --transient-kb=N.
+    pub fn from_args() -> MemoryStorage {
+        let mut permanent_kb = DEFAULT_PERMANENT_KB;
+        let mut transient_kb = DEFAULT_TRANSIENT_KB;
+
+        for arg in env::args().skip(1) {
+            if arg.starts_with("--permanent-kb=") {
+                permanent_kb = parse_kb(&arg["--permanent-kb=".len()..], &arg);
+            }
+            else if arg.starts_with("--transient-kb=") {
+                transient_kb = parse_kb(&arg["--transient-kb=".len()..], &arg);
+            }
+        }
+
+        MemoryStorage::new(permanent_kb * 1024, transient_kb * 1024)
+    }
+
+    pub fn game_memory(&mut self) -> GameMemory {
+        unsafe {
+            GameMemory {
+                permanent:      transmute(&mut self.permanent[0]),
+                permanent_size: self.permanent.len(),
+                transient:      transmute(&mut self.transient[0]),
+                transient_size: self.transient.len()
+            }
+        }
+    }
+
+    pub fn permanent_bytes(&self) -> &[u8] { &self.permanent }
+
+    // For input playback -- puts permanent memory back the way it was when
+    // the recording started.
+    pub fn restore_permanent(&mut self, snapshot: &[u8]) {
+        assert_eq!(snapshot.len(), self.permanent.len());
+        for (byte, &saved) in self.permanent.iter_mut().zip(snapshot.iter()) { *byte = saved; }
+    }
+
+    // Only for cold restarts -- the game expects fresh memory to be zeroed.
+    pub fn clear(&mut self) {
+        for byte in self.permanent.iter_mut() { *byte = 0; }
+        for byte in self.transient.iter_mut() { *byte = 0; }
+    }
+}
+
+fn parse_kb(value: &str, arg: &str) -> usize {
+    match value.parse::<usize>() {
+        Ok(kb) if kb > 0 => kb,
+        _ => panic!("Bad memory size in {} (expected a positive number of KB)", arg)
+    }
+}
@@ -40,3 +40,7 @@ pub fn query_performance_frequency() -> i64 {
 pub fn watch_for_updated_game_lib(ref sender: &Sender<()>) {
     println!("on mac - no hot code update for now!");
 }
+
+pub fn watch_for_updated_assets(ref sender: &Sender<String>) {
+    println!("on mac - no asset hot reload for now!");
+}
@@ -0,0 +1,321 @@
+// Looped input playback, Handmade Hero style.
+//
+// F5 snapshots permanent game memory and starts logging every frame's
+// delta time and key events. F5 again stops recording and starts playing the
+// recording back in a loop: memory is restored from the snapshot and the same
+// frames are fed to the game over and over, so gameplay code can be tweaked
+// with hot reload while the input stays the same. F6 stops playback.
+
+extern crate glfw;
+
+use std::fs::File;
+use std::io::{Read, Write, BufWriter};
+use std::mem::transmute;
+use std::sync::mpsc::Sender;
+use glfw::{Action, Key, WindowEvent};
+use memory::MemoryStorage;
+
+pub static RECORD_KEY: Key = Key::F5;
+pub static STOP_PLAYBACK_KEY: Key = Key::F6;
+
+pub type Event = (f64, WindowEvent);
+
+struct RecordedFrame {
+    delta_time: f32,
+    events: Vec<Event>
+}
+
+enum State {
+    Idle,
+    Recording(BufWriter<File>),
+    PlayingBack(Vec<RecordedFrame>, usize)
+}
+
+pub struct Replay {
+    path: &'static str,
+    state: State,
+    // Permanent memory as it was when recording started.
+    snapshot: Vec<u8>
+}
+
+// ============== File format ==============
+// snapshot length: u64, snapshot bytes
+// then per frame:
+//   delta time: f32, event count: u32
+//   per event: time: f64, key: i32, scancode: i32, action: i32, modifiers: i32
+//
+// Everything is written in native byte order -- these files aren't meant to
+// leave the machine they were recorded on.
+
+macro_rules! write_raw {
+    ($file:expr, $ty:ty[$size:expr], $val:expr) => {{
+        let bytes: [u8; $size] = unsafe { transmute($val as $ty) };
+        $file.write_all(&bytes)
+    }}
+}
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+
+macro_rules! read_raw {
+    ($cursor:expr, $ty:ty[$size:expr]) => {{
+        if $cursor.pos + $size > $cursor.bytes.len() { None }
+        else {
+            let mut bytes = [0u8; $size];
+            for i in 0..$size { bytes[i] = $cursor.bytes[$cursor.pos + i]; }
+            $cursor.pos += $size;
+            Some(unsafe { transmute::<[u8; $size], $ty>(bytes) })
+        }
+    }}
+}
+
+fn is_replay_control(event: &WindowEvent) -> bool {
+    match *event {
+        WindowEvent::Key(key, _, _, _) => key == RECORD_KEY || key == STOP_PLAYBACK_KEY,
+        _ => false
+    }
+}
+
+// Only key events are gameplay input. Everything else (resizes, escape to
+// quit, F9 to save the level) should keep coming from the real window during
+// playback -- a recorded F9 would rewrite the level file on every loop.
+fn is_recordable(event: &WindowEvent) -> bool {
+    match *event {
+        WindowEvent::Key(Key::Escape, _, _, _) => false,
+        WindowEvent::Key(Key::F9, _, _, _) => false,
+        WindowEvent::Key(_, _, _, _) => true,
+        _ => false
+    }
+}
+
+fn write_frame(file: &mut BufWriter<File>, delta_time: f32, events: &[Event]) -> ::std::io::Result<()> {
+    let key_events: Vec<&Event> = events.iter()
+        .filter(|&&(_, ref e)| is_recordable(e) && !is_replay_control(e))
+        .collect();
+
+    try!(write_raw!(file, f32[4], delta_time));
+    try!(write_raw!(file, u32[4], key_events.len()));
+    for &&(time, ref event) in key_events.iter() {
+        match *event {
+            WindowEvent::Key(key, scancode, action, modifiers) => {
+                try!(write_raw!(file, f64[8], time));
+                try!(write_raw!(file, i32[4], key as i32));
+                try!(write_raw!(file, i32[4], scancode));
+                try!(write_raw!(file, i32[4], action as i32));
+                try!(write_raw!(file, i32[4], modifiers.bits()));
+            }
+            _ => unreachable!()
+        }
+    }
+    Ok(())
+}
+
+static KEYS: [Key; 120] = [
+    Key::Space, Key::Apostrophe, Key::Comma, Key::Minus, Key::Period, Key::Slash,
+    Key::Num0, Key::Num1, Key::Num2, Key::Num3, Key::Num4,
+    Key::Num5, Key::Num6, Key::Num7, Key::Num8, Key::Num9,
+    Key::Semicolon, Key::Equal,
+    Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I,
+    Key::J, Key::K, Key::L, Key::M, Key::N, Key::O, Key::P, Key::Q, Key::R,
+    Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    Key::LeftBracket, Key::Backslash, Key::RightBracket, Key::GraveAccent,
+    Key::World1, Key::World2,
+    Key::Escape, Key::Enter, Key::Tab, Key::Backspace, Key::Insert, Key::Delete,
+    Key::Right, Key::Left, Key::Down, Key::Up,
+    Key::PageUp, Key::PageDown, Key::Home, Key::End,
+    Key::CapsLock, Key::ScrollLock, Key::NumLock, Key::PrintScreen, Key::Pause,
+    Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8,
+    Key::F9, Key::F10, Key::F11, Key::F12, Key::F13, Key::F14, Key::F15, Key::F16,
+    Key::F17, Key::F18, Key::F19, Key::F20, Key::F21, Key::F22, Key::F23, Key::F24,
+    Key::F25,
+    Key::Kp0, Key::Kp1, Key::Kp2, Key::Kp3, Key::Kp4,
+    Key::Kp5, Key::Kp6, Key::Kp7, Key::Kp8, Key::Kp9,
+    Key::KpDecimal, Key::KpDivide, Key::KpMultiply, Key::KpSubtract,
+    Key::KpAdd, Key::KpEnter, Key::KpEqual,
+    Key::LeftShift, Key::LeftControl, Key::LeftAlt, Key::LeftSuper,
+    Key::RightShift, Key::RightControl, Key::RightAlt, Key::RightSuper,
+    Key::Menu
+];
+
+fn key_from_i32(value: i32) -> Option<Key> {
+    KEYS.iter().find(|&&key| key as i32 == value).map(|&key| key)
+}
+
+fn action_from_i32(value: i32) -> Option<Action> {
+    [Action::Release, Action::Press, Action::Repeat].iter()
+        .find(|&&action| action as i32 == value).map(|&action| action)
+}
+
+fn read_frame(cursor: &mut Cursor) -> Option<RecordedFrame> {
+    let delta_time = match read_raw!(cursor, f32[4]) { Some(d) => d, None => return None };
+    let count      = match read_raw!(cursor, u32[4]) { Some(c) => c, None => return None };
+
+    let mut events = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let time     = match read_raw!(cursor, f64[8]) { Some(v) => v, None => return None };
+        let key      = match read_raw!(cursor, i32[4]) { Some(v) => v, None => return None };
+        let scancode = match read_raw!(cursor, i32[4]) { Some(v) => v, None => return None };
+        let action   = match read_raw!(cursor, i32[4]) { Some(v) => v, None => return None };
+        let mods     = match read_raw!(cursor, i32[4]) { Some(v) => v, None => return None };
+
+        // A corrupt or foreign file could hold anything here, so only
+        // values that name a real key and action are played back.
+        match (key_from_i32(key), action_from_i32(action)) {
+            (Some(key), Some(action)) => events.push((time, WindowEvent::Key(
+                key, scancode, action, glfw::Modifiers::from_bits_truncate(mods)
+            ))),
+            _ => {}
+        }
+    }
+
+    Some(RecordedFrame { delta_time: delta_time, events: events })
+}
+
+impl Replay {
+    pub fn new(path: &'static str) -> Replay {
+        Replay { path: path, state: State::Idle, snapshot: Vec::new() }
+    }
+
+    pub fn active(&self) -> bool {
+        match self.state { State::Idle => false, _ => true }
+    }
+
+    pub fn start_recording(&mut self, storage: &MemoryStorage) {
+        let mut file = match File::create(self.path) {
+            Ok(f) => BufWriter::new(f),
+            Err(e) => { println!("Couldn't create {} for recording: {}", self.path, e); return }
+        };
+
+        self.snapshot = storage.permanent_bytes().to_vec();
+        let written = write_raw!(file, u64[8], self.snapshot.len())
+            .and_then(|_| file.write_all(&self.snapshot));
+        if let Err(e) = written {
+            println!("Couldn't write memory snapshot to {}: {}", self.path, e);
+            return;
+        }
+
+        println!("Recording input to {}", self.path);
+        self.state = State::Recording(file);
+    }
+
+    pub fn start_playback(&mut self, storage: &mut MemoryStorage) {
+        self.state = State::Idle;
+
+        let mut bytes = Vec::new();
+        match File::open(self.path).and_then(|mut f| f.read_to_end(&mut bytes)) {
+            Ok(_) => {}
+            Err(e) => { println!("Couldn't read {} for playback: {}", self.path, e); return }
+        }
+
+        let mut cursor = Cursor { bytes: &bytes, pos: 0 };
+        let snapshot_len = match read_raw!(cursor, u64[8]) {
+            Some(len) => len as usize,
+            None => { println!("{} is empty, nothing to play back.", self.path); return }
+        };
+        if snapshot_len != storage.permanent_bytes().len() || cursor.pos + snapshot_len > bytes.len() {
+            println!(
+                "Snapshot in {} doesn't match the current permanent memory size. Not playing back.",
+                self.path
+            );
+            return;
+        }
+        self.snapshot = bytes[cursor.pos..cursor.pos + snapshot_len].to_vec();
+        cursor.pos += snapshot_len;
+
+        let mut frames = Vec::new();
+        while let Some(frame) = read_frame(&mut cursor) { frames.push(frame); }
+        if frames.is_empty() {
+            println!("No frames recorded in {}, nothing to play back.", self.path);
+            return;
+        }
+
+        println!("Playing back {} frames from {} (F6 to stop)", frames.len(), self.path);
+        storage.restore_permanent(&self.snapshot);
+        self.state = State::PlayingBack(frames, 0);
+    }
+
+    pub fn stop(&mut self) {
+        match self.state {
+            State::Recording(_) => println!("Stopped recording."),
+            State::PlayingBack(_, _) => println!("Stopped playback."),
+            State::Idle => {}
+        }
+        self.state = State::Idle;
+    }
+
+    // Takes this frame's live events and delta time, forwards whatever the
+    // game should see this frame to `game_events`, and returns the delta time
+    // the game should run with.
+    pub fn frame(
+        &mut self,
+        storage: &mut MemoryStorage,
+        delta_time: f32,
+        live_events: Vec<Event>,
+        game_events: &Sender<Event>
+    ) -> f32 {
+        // === Replay controls ===
+        for &(_, ref event) in live_events.iter() {
+            match *event {
+                WindowEvent::Key(key, _, Action::Press, _) if key == RECORD_KEY => {
+                    match self.state {
+                        State::Recording(_) => { self.stop(); self.start_playback(storage); }
+                        _ => self.start_recording(storage)
+                    }
+                }
+                WindowEvent::Key(key, _, Action::Press, _) if key == STOP_PLAYBACK_KEY => {
+                    self.stop();
+                }
+                _ => {}
+            }
+        }
+
+        let mut failed = false;
+        let effective_delta = match self.state {
+            State::Idle => {
+                for event in live_events.into_iter() {
+                    if !is_replay_control(&event.1) { game_events.send(event).unwrap(); }
+                }
+                delta_time
+            }
+
+            State::Recording(ref mut file) => {
+                if let Err(e) = write_frame(file, delta_time, &live_events) {
+                    println!("Failed to record frame: {}", e);
+                    failed = true;
+                }
+                for event in live_events.into_iter() {
+                    if !is_replay_control(&event.1) { game_events.send(event).unwrap(); }
+                }
+                delta_time
+            }
+
+            State::PlayingBack(ref frames, ref mut index) => {
+                if *index >= frames.len() {
+                    *index = 0;
+                    storage.restore_permanent(&self.snapshot);
+                }
+                let ref frame = frames[*index];
+                *index += 1;
+
+                for event in live_events.into_iter() {
+                    if !is_replay_control(&event.1) && !is_recordable(&event.1) {
+                        game_events.send(event).unwrap();
+                    }
+                }
+                for &(time, ref event) in frame.events.iter() {
+                    match *event {
+                        WindowEvent::Key(key, scancode, action, mods) =>
+                            game_events.send((time, WindowEvent::Key(key, scancode, action, mods))).unwrap(),
+                        _ => {}
+                    }
+                }
+                frame.delta_time
+            }
+        };
+
+        if failed { self.stop(); }
+        effective_delta
+    }
+}
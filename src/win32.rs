@@ -26,6 +26,12 @@ impl Win32FileNotifyInformation {
         let v = slice::from_raw_parts(&self.first_file_name_char, self.file_name_length as usize);
         String::from_utf16_lossy(v)
     }}
+
+    // file_name_length is in bytes, and sub-directories come with backslashes.
+    pub fn relative_path(&self) -> String { unsafe {
+        let v = slice::from_raw_parts(&self.first_file_name_char, self.file_name_length as usize / 2);
+        String::from_utf16_lossy(v).replace("\\", "/")
+    }}
 }
 
 extern "C" {
@@ -71,6 +77,7 @@ const OPEN_EXISTING: i32 = 3;
 pub static GAME_LIB_DIR: &'static str = "./af/target/debug/";
 pub static GAME_LIB_PATH: &'static str = "./af/target/debug/af.dll";
 pub static GAME_LIB_FILE: &'static str = "./af.dll";
+pub static ASSETS_DIR: &'static str = "assets";
 
 pub fn query_performance_frequency() -> i64 {
     let mut freq = 0i64;
@@ -89,12 +96,10 @@ pub fn query_performance_counter(counter: &mut i64) {
     }
 }
 
-pub unsafe fn watch_for_updated_game_lib(ref sender: &Sender<()>) {
-    let dylib_dir  = Path::new(GAME_LIB_DIR);
-
-    let dylib_dir_str = CString::new(dylib_dir.to_str().unwrap()).unwrap();
+unsafe fn open_directory(dir: &Path) -> *const c_void {
+    let dir_str = CString::new(dir.to_str().unwrap()).unwrap();
     let handle = CreateFileA(
-        dylib_dir_str.as_ptr(),
+        dir_str.as_ptr(),
         FILE_LIST_DIRECTORY,
         FILE_SHARE_DELETE|FILE_SHARE_READ|FILE_SHARE_WRITE,
         ptr::null(),
@@ -104,10 +109,59 @@ pub unsafe fn watch_for_updated_game_lib(ref sender: &Sender<()>) {
     );
     if handle == INVALID_HANDLE_VALUE {
         match GetLastError() {
-            5 => panic!("CreateFile for {} failed: Access denied", dylib_dir.display()),
-            error_code => panic!("CreateFile for {} failed: Error code {}", dylib_dir.display(), error_code)
+            5 => panic!("CreateFile for {} failed: Access denied", dir.display()),
+            error_code => panic!("CreateFile for {} failed: Error code {}", dir.display(), error_code)
         }
     }
+    handle
+}
+
+// Sends the path (like "assets/crattlecrute/body.png") of every file under
+// ASSETS_DIR that gets written to.
+pub unsafe fn watch_for_updated_assets(ref sender: &Sender<String>) {
+    let handle = open_directory(Path::new(ASSETS_DIR));
+
+    let results_buffer = [0u8; 4096];
+    let results_size: i32 = 0;
+
+    loop {
+        match ReadDirectoryChangesW(
+            handle,
+            transmute(&results_buffer[0]),
+            results_buffer.len() as i32,
+            true,
+            FILE_NOTIFY_CHANGE_LAST_WRITE,
+            &results_size,
+            ptr::null(),
+            ptr::null()
+        ) {
+            0 => println!("Failed to listen for asset changes! {}", GetLastError()),
+
+            _ => {
+                // Windows reports a write more than once, so dedupe per batch.
+                let mut changed: Vec<String> = Vec::new();
+                let mut offset = 0usize;
+                loop {
+                    let result = transmute::<_, &Win32FileNotifyInformation>(&results_buffer[offset]);
+                    let path = format!("{}/{}", ASSETS_DIR, result.relative_path());
+                    if !changed.contains(&path) { changed.push(path); }
+
+                    if result.next_entry_offset == 0 { break }
+                    offset += result.next_entry_offset as usize;
+                }
+
+                for path in changed.into_iter() {
+                    if sender.send(path).is_err() { return }
+                }
+            }
+        }
+    }
+}
+
+pub unsafe fn watch_for_updated_game_lib(ref sender: &Sender<()>) {
+    let dylib_dir  = Path::new(GAME_LIB_DIR);
+
+    let handle = open_directory(dylib_dir);
 
     let results_buffer = [0u8; 1024];
     let results_size: i32 = 0;